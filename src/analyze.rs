@@ -0,0 +1,538 @@
+// Converts read data into `Expr` trees before they run. Special forms are recognized once here
+// rather than on every evaluation, and each variable reference inside a lambda is resolved to a
+// slot in a frame, so looking it up at runtime doesn't involve any hashing.
+use std::cell::OnceCell;
+use std::rc::Rc;
+use std::slice;
+use atom::Atom;
+use builtins::{scheme_apply, scheme_call_with_values, scheme_car, scheme_cdr, scheme_list, scheme_values};
+use builtins::{scheme_is_record_of, scheme_make_record, scheme_record_ref, scheme_record_set};
+use compile::Chunk;
+use environment::{SchemeFn, SchemeFnWrap};
+use error::SchemeError;
+use gc::{self, Object};
+use record::RecordType;
+use symbol::Symbol;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variable {
+    // Slot `index` of the frame `depth` levels out from the current one
+    Local(usize, usize),
+    // Looked up by name, starting from the environment the expression runs in
+    Global(Symbol),
+}
+
+pub struct LambdaExpr {
+    pub name: String,
+    // Number of required parameters, which fill the first slots of the frame
+    pub required: usize,
+    // Whether further arguments are collected into a list in the slot after them
+    pub rest: bool,
+    // Slots needed for the parameters and any internal definitions
+    pub frame_size: usize,
+    // Whether the VM can keep the frame in slots on its stack, because nothing in the body
+    // closes over it, defines into it or runs code that looks it up as an environment
+    pub on_stack: bool,
+    pub body: Vec<Expr>,
+    // The body compiled to bytecode, the first time the VM calls the lambda
+    pub chunk: OnceCell<Rc<Chunk>>,
+}
+
+pub struct GuardExpr {
+    pub body: Vec<Expr>,
+    pub clauses: Vec<Clause>,
+    // Clauses run in a new frame of this size, with the raised object in slot 0
+    pub frame_size: usize,
+}
+
+// A `guard` clause
+pub enum Clause {
+    Else(Vec<Expr>),
+    // `(test)`, which returns the value of the test
+    Test(Expr),
+    Body(Expr, Vec<Expr>),
+    // `(test => receiver)`
+    Arrow(Expr, Expr),
+}
+
+pub enum Expr {
+    Constant(Atom),
+    Variable(Variable),
+    Define(Variable, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    Lambda(Rc<LambdaExpr>),
+    Sequence(Vec<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Guard(Rc<GuardExpr>),
+    Import(Vec<Atom>),
+    DefineLibrary(Atom, Vec<Atom>),
+    // Included files are read when the `include` runs, with `true` for `include-ci`
+    Include(Vec<Atom>, bool),
+}
+
+// Something an expression holds a reference to
+pub enum Reference<'a> {
+    Constant(&'a Atom),
+    Lambda(&'a Rc<LambdaExpr>),
+}
+
+impl Expr {
+    // Call `f` on every quoted datum and lambda in the expression, not including those inside
+    // the lambdas, so the cycle collector can see them
+    pub fn for_each_reference(&self, f: &mut dyn FnMut(Reference)) {
+        let all = |exprs: &[Expr], f: &mut dyn FnMut(Reference)| for expr in exprs {
+            expr.for_each_reference(f);
+        };
+        match *self {
+            Expr::Constant(ref atom) => f(Reference::Constant(atom)),
+            Expr::Variable(_) => (),
+            Expr::Define(_, ref value) => value.for_each_reference(f),
+            Expr::If(ref test, ref consequent, ref alternative) => {
+                test.for_each_reference(f);
+                consequent.for_each_reference(f);
+                if let Some(ref alternative) = *alternative {
+                    alternative.for_each_reference(f);
+                }
+            },
+            Expr::Lambda(ref lambda) => f(Reference::Lambda(lambda)),
+            Expr::Sequence(ref body) => all(body, f),
+            Expr::Call(ref func, ref args) => {
+                func.for_each_reference(f);
+                all(args, f);
+            },
+            Expr::Guard(ref guard) => {
+                all(&guard.body, f);
+                for clause in &guard.clauses {
+                    match *clause {
+                        Clause::Else(ref body) => all(body, f),
+                        Clause::Test(ref test) => test.for_each_reference(f),
+                        Clause::Body(ref test, ref body) => {
+                            test.for_each_reference(f);
+                            all(body, f);
+                        },
+                        Clause::Arrow(ref test, ref receiver) => {
+                            test.for_each_reference(f);
+                            receiver.for_each_reference(f);
+                        },
+                    }
+                }
+            },
+            Expr::Import(_) | Expr::DefineLibrary(_, _) | Expr::Include(_, _) => (),
+        }
+    }
+
+    // Whether the expression needs the frame it runs in to be an environment, rather than only
+    // reading the frame's slots
+    pub fn needs_environment(&self) -> bool {
+        match *self {
+            Expr::Constant(_) | Expr::Variable(_) => false,
+            Expr::If(ref test, ref consequent, ref alternative) => {
+                test.needs_environment() || consequent.needs_environment()
+                    || alternative.as_ref().is_some_and(|alternative| alternative.needs_environment())
+            },
+            Expr::Sequence(ref body) => body.iter().any(Expr::needs_environment),
+            Expr::Call(ref func, ref args) => func.needs_environment() || args.iter().any(Expr::needs_environment),
+            Expr::Define(_, _) | Expr::Lambda(_) | Expr::Guard(_) | Expr::Import(_)
+                | Expr::DefineLibrary(_, _) | Expr::Include(_, _) => true,
+        }
+    }
+}
+
+// Analyze a top-level form, where every variable is global
+pub fn analyze(atom: &Atom) -> Result<Expr, SchemeError> {
+    Analyzer { scopes: vec![] }.analyze(atom)
+}
+
+struct Analyzer {
+    // Names of the slots in each enclosing frame, innermost last
+    scopes: Vec<Vec<Symbol>>,
+}
+
+impl Analyzer {
+    fn resolve(&self, symbol: Symbol) -> Variable {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.iter().rposition(|&name| name == symbol) {
+                return Variable::Local(depth, index)
+            }
+        }
+        Variable::Global(symbol)
+    }
+
+    // The variable a `define` of `symbol` assigns, adding a slot to the current frame if needed
+    fn declare(&mut self, symbol: Symbol) -> Variable {
+        match self.scopes.last_mut() {
+            None => Variable::Global(symbol),
+            Some(scope) => {
+                let index = scope.iter().position(|&name| name == symbol).unwrap_or_else(|| {
+                    scope.push(symbol);
+                    scope.len() - 1
+                });
+                Variable::Local(0, index)
+            }
+        }
+    }
+
+    // Declare the names defined directly in a body up front, so earlier
+    // definitions can refer to later ones
+    fn declare_definitions(&mut self, body: &[Atom]) {
+        for form in body {
+            let parts = match form.to_vec() {
+                Some(parts) => parts,
+                None => continue
+            };
+            match parts.first().and_then(Atom::as_symbol) {
+                Some(keyword) if keyword == "define" => {
+                    let name = match parts.get(1) {
+                        Some(Atom::Pair(pair)) => pair.borrow().car.as_symbol(),
+                        Some(target) => target.as_symbol(),
+                        None => None
+                    };
+                    if let Some(name) = name {
+                        self.declare(name);
+                    }
+                },
+                Some(keyword) if keyword == "define-values" => if let Some(formals) = parts.get(1) {
+                    let (required, tail) = formals.list_parts();
+                    for name in required.iter().chain(Some(&tail)).filter_map(Atom::as_symbol) {
+                        self.declare(name);
+                    }
+                },
+                Some(keyword) if keyword == "define-record-type" => {
+                    for name in record_type_names(&parts[1..]) {
+                        self.declare(name);
+                    }
+                },
+                Some(keyword) if keyword == "begin" => self.declare_definitions(&parts[1..]),
+                _ => ()
+            }
+        }
+    }
+
+    fn analyze(&mut self, atom: &Atom) -> Result<Expr, SchemeError> {
+        match *atom {
+            Atom::Symbol(symbol) => Ok(Expr::Variable(self.resolve(symbol))),
+            Atom::Pair(_) => {
+                let list = atom.to_vec().ok_or_else(|| SchemeError::syntax("Ill-formed expression"))?;
+                let (first, rest) = list.split_first().ok_or_else(|| SchemeError::syntax("Ill-formed expression"))?;
+                if let Some(expr) = self.analyze_special_form(first, rest)? {
+                    return Ok(expr)
+                }
+                let func = self.analyze(first)?;
+                let args = self.analyze_all(rest)?;
+                Ok(Expr::Call(Box::new(func), args))
+            },
+            _ => Ok(Expr::Constant(atom.clone())),
+        }
+    }
+
+    fn analyze_all(&mut self, atoms: &[Atom]) -> Result<Vec<Expr>, SchemeError> {
+        atoms.iter().map(|atom| self.analyze(atom)).collect()
+    }
+
+    // Analyze a lambda body in a new frame whose first slots are `params`
+    fn analyze_lambda(&mut self, name: &str, params: &Atom, body: &[Atom]) -> Result<Expr, SchemeError> {
+        let (required, tail) = params.list_parts();
+        let mut scope = vec![];
+        for param in required.iter().chain(Some(&tail).filter(|&tail| *tail != Atom::Nil)) {
+            scope.push(param.as_symbol().ok_or_else(|| SchemeError::syntax("Non-symbol in lambda arg list"))?);
+        }
+        self.scopes.push(scope);
+        self.declare_definitions(body);
+        let body = self.analyze_all(body);
+        let scope = self.scopes.pop().unwrap_or_default();
+        let body = body?;
+        let lambda = Rc::new(LambdaExpr {
+            name: name.to_string(),
+            required: required.len(),
+            rest: tail != Atom::Nil,
+            frame_size: scope.len(),
+            on_stack: !body.iter().any(Expr::needs_environment),
+            body,
+            chunk: OnceCell::new(),
+        });
+        gc::track(Object::Code(lambda.clone()));
+        Ok(Expr::Lambda(lambda))
+    }
+
+    fn analyze_define(&mut self, target: &Atom, body: &[Atom]) -> Result<Expr, SchemeError> {
+        match *target {
+            Atom::Symbol(symbol) => {
+                let value = match body.len() {
+                    0 => Expr::Constant(Atom::Unspecified),
+                    1 => self.analyze(&body[0])?,
+                    _ => return Err(SchemeError::syntax("Ill formed define!"))
+                };
+                Ok(Expr::Define(self.declare(symbol), Box::new(value)))
+            },
+            Atom::Pair(ref pair) => {
+                let pair = pair.borrow();
+                let name = pair.car.as_symbol().ok_or_else(|| SchemeError::syntax("define name must be a symbol"))?;
+                let variable = self.declare(name);
+                let lambda = self.analyze_lambda(&name.name(), &pair.cdr, body)?;
+                Ok(Expr::Define(variable, Box::new(lambda)))
+            },
+            _ => Err(SchemeError::syntax("First argument to define must be a symbol or list"))
+        }
+    }
+
+    // `(define-values formals expr)`, which collects the values into a list in a hidden variable
+    // and defines each name from it
+    fn analyze_define_values(&mut self, formals: &Atom, init: &Atom) -> Result<Expr, SchemeError> {
+        let (required, tail) = formals.list_parts();
+        let mut names = vec![];
+        for name in required.iter().chain(Some(&tail).filter(|&tail| *tail != Atom::Nil)) {
+            names.push(name.clone());
+        }
+        let producer = self.analyze_lambda("define-values", &Atom::Nil, slice::from_ref(init))?;
+        // Binding the values to `formals` checks there are the right number of them
+        let mut list = vec![builtin(scheme_list)];
+        list.extend(names.iter().cloned());
+        let consumer = self.analyze_lambda("define-values", formals, &[Atom::list(list)])?;
+
+        let values = self.declare(Symbol::uninterned("values"));
+        let mut exprs = vec![Expr::Define(values, Box::new(call_builtin(scheme_call_with_values, vec![producer, consumer])))];
+        for (i, name) in names.iter().enumerate() {
+            let mut rest = Expr::Variable(values);
+            for _ in 0..i {
+                rest = call_builtin(scheme_cdr, vec![rest]);
+            }
+            let name = name.as_symbol_result()?;
+            exprs.push(Expr::Define(self.declare(name), Box::new(call_builtin(scheme_car, vec![rest]))));
+        }
+        exprs.push(Expr::Define(values, Box::new(Expr::Constant(Atom::Unspecified))));
+        Ok(Expr::Sequence(exprs))
+    }
+
+    // `(let-values bindings body...)`, or `let*-values` if `sequential`, where each binding is
+    // `(formals expr)`
+    fn analyze_let_values(&mut self, name: &str, bindings: &Atom, body: &[Atom], sequential: bool) -> Result<Expr, SchemeError> {
+        let bindings = bindings.to_vec().ok_or_else(|| SchemeError::syntax(&format!("{} bindings must be a list", name)))?;
+        let mut parts = vec![];
+        for binding in &bindings {
+            match binding.to_vec() {
+                Some(ref binding) if binding.len() == 2 => parts.push((binding[0].clone(), binding[1].clone())),
+                _ => return Err(SchemeError::syntax(&format!("{} binding must be (formals expression)", name)))
+            }
+        }
+        let ((formals, init), rest) = match parts.split_first() {
+            Some(first) => first,
+            None => {
+                let body = self.analyze_lambda(name, &Atom::Nil, body)?;
+                return Ok(Expr::Call(Box::new(body), vec![]))
+            }
+        };
+        if sequential || rest.is_empty() {
+            // Bind the first formals around a `let*-values` of the rest
+            let mut inner = vec![Atom::Symbol("let*-values".into()), Atom::list(bindings[1..].to_vec())];
+            inner.extend(body.iter().cloned());
+            let producer = self.analyze_lambda(name, &Atom::Nil, slice::from_ref(init))?;
+            let consumer = self.analyze_lambda(name, formals, &[Atom::list(inner)])?;
+            return Ok(call_builtin(scheme_call_with_values, vec![producer, consumer]))
+        }
+        // Evaluate every expression before binding any formals, keeping each expression's values
+        // in a list until then
+        let mut temporaries = vec![];
+        let mut lists = vec![];
+        let mut inner_bindings = vec![];
+        for (formals, init) in &parts {
+            let temporary = Atom::Symbol(Symbol::uninterned("values"));
+            let producer = self.analyze_lambda(name, &Atom::Nil, slice::from_ref(init))?;
+            lists.push(call_builtin(scheme_call_with_values, vec![producer, Expr::Constant(builtin(scheme_list))]));
+            let spread = Atom::list(vec![builtin(scheme_apply), builtin(scheme_values), temporary.clone()]);
+            inner_bindings.push(Atom::list(vec![formals.clone(), spread]));
+            temporaries.push(temporary);
+        }
+        let mut inner = vec![Atom::Symbol("let*-values".into()), Atom::list(inner_bindings)];
+        inner.extend(body.iter().cloned());
+        let lambda = self.analyze_lambda(name, &Atom::list(temporaries), &[Atom::list(inner)])?;
+        Ok(Expr::Call(Box::new(lambda), lists))
+    }
+
+    fn analyze_if(&mut self, args: &[Atom]) -> Result<Expr, SchemeError> {
+        match args.len() {
+            0 | 1 => Err(SchemeError::syntax("if requires at least 2 arguments")),
+            2 | 3 => {
+                let test = self.analyze(&args[0])?;
+                let consequent = self.analyze(&args[1])?;
+                let alternative = match args.get(2) {
+                    Some(alternative) => Some(Box::new(self.analyze(alternative)?)),
+                    None => None
+                };
+                Ok(Expr::If(Box::new(test), Box::new(consequent), alternative))
+            },
+            _ => Err(SchemeError::syntax("Too many arguments to 'if'"))
+        }
+    }
+
+    fn analyze_clause(&mut self, clause: &Atom) -> Result<Clause, SchemeError> {
+        let clause = clause.to_vec().ok_or_else(|| SchemeError::syntax("guard clause must be a list"))?;
+        let (test, body) = clause.split_first().ok_or_else(|| SchemeError::syntax("Empty guard clause"))?;
+        if test.as_symbol().is_some_and(|sym| sym == "else") {
+            return Ok(Clause::Else(self.analyze_all(body)?))
+        }
+        let test = self.analyze(test)?;
+        if body.len() == 2 && body[0].as_symbol().is_some_and(|sym| sym == "=>") {
+            return Ok(Clause::Arrow(test, self.analyze(&body[1])?))
+        }
+        if body.is_empty() {
+            return Ok(Clause::Test(test))
+        }
+        Ok(Clause::Body(test, self.analyze_all(body)?))
+    }
+
+    fn analyze_guard(&mut self, spec: &Atom, body: &[Atom]) -> Result<Expr, SchemeError> {
+        let spec = spec.to_vec().ok_or_else(|| SchemeError::syntax("guard expects (variable clause...)"))?;
+        let (var, clauses) = spec.split_first().ok_or_else(|| SchemeError::syntax("guard requires a variable"))?;
+        let var = var.as_symbol().ok_or_else(|| SchemeError::syntax("guard variable must be a symbol"))?;
+        let body = self.analyze_all(body)?;
+        self.scopes.push(vec![var]);
+        let clauses: Result<Vec<Clause>, SchemeError> = clauses.iter().map(|clause| self.analyze_clause(clause)).collect();
+        let scope = self.scopes.pop().unwrap_or_default();
+        Ok(Expr::Guard(Rc::new(GuardExpr { body, clauses: clauses?, frame_size: scope.len() })))
+    }
+
+    // Define `name` as a lambda taking `params` whose body is the call `body`
+    fn define_procedure(&mut self, name: &Atom, params: Vec<Atom>, body: Vec<Atom>) -> Result<Expr, SchemeError> {
+        let name = name.as_symbol().ok_or_else(|| SchemeError::syntax("Record procedure names must be symbols"))?;
+        let variable = self.declare(name);
+        let lambda = self.analyze_lambda(&name.name(), &Atom::list(params), &[Atom::list(body)])?;
+        Ok(Expr::Define(variable, Box::new(lambda)))
+    }
+
+    // `(define-record-type name (constructor field...) predicate (field accessor [modifier])...)`.
+    // The record type is a constant in each procedure, so they keep working if `name` is redefined
+    fn analyze_define_record_type(&mut self, args: &[Atom]) -> Result<Expr, SchemeError> {
+        if args.len() < 3 {
+            return Err(SchemeError::syntax("define-record-type requires a name, a constructor and a predicate"))
+        }
+        let name = args[0].as_symbol().ok_or_else(|| SchemeError::syntax("Record type name must be a symbol"))?;
+        let mut specs = vec![];
+        for spec in &args[3..] {
+            let spec = match spec.to_vec() {
+                Some(spec) if !spec.is_empty() && spec.len() <= 3 => spec,
+                _ => return Err(SchemeError::syntax("Record field must be (field accessor [modifier])"))
+            };
+            let field = spec[0].as_symbol().ok_or_else(|| SchemeError::syntax("Record field name must be a symbol"))?;
+            if specs.iter().any(|&(other, _)| other == field) {
+                return Err(SchemeError::syntax(&format!("Duplicate record field {}", field)))
+            }
+            specs.push((field, spec));
+        }
+        let fields: Vec<Symbol> = specs.iter().map(|&(field, _)| field).collect();
+        let record_type = Atom::RecordType(Rc::new(RecordType { name, fields: fields.clone() }));
+        let mut exprs = vec![Expr::Define(self.declare(name), Box::new(Expr::Constant(record_type.clone())))];
+
+        // The constructor sets the fields it doesn't take to the unspecified value
+        if args[1] != Atom::Bool(false) {
+            let parts = match args[1].to_vec() {
+                Some(parts) if !parts.is_empty() => parts,
+                _ => return Err(SchemeError::syntax("Record constructor must be (constructor field...)"))
+            };
+            let params = &parts[1..];
+            if let Some(param) = params.iter().find(|param| !param.as_symbol().is_some_and(|param| fields.contains(&param))) {
+                return Err(SchemeError::syntax(&format!("Record constructor takes unknown field {}", param)))
+            }
+            let mut body = vec![builtin(scheme_make_record), record_type.clone()];
+            body.extend(fields.iter().map(|&field| {
+                let field = Atom::Symbol(field);
+                if params.contains(&field) { field } else { Atom::Unspecified }
+            }));
+            exprs.push(self.define_procedure(&parts[0], params.to_vec(), body)?);
+        }
+
+        let obj = Atom::Symbol("obj".into());
+        let value = Atom::Symbol("value".into());
+        exprs.push(self.define_procedure(&args[2], vec![obj.clone()], vec![builtin(scheme_is_record_of), obj.clone(), record_type.clone()])?);
+        for (index, (_, spec)) in specs.iter().enumerate() {
+            let index = Atom::Int(index as i32);
+            if let Some(accessor) = spec.get(1) {
+                let body = vec![builtin(scheme_record_ref), obj.clone(), record_type.clone(), index.clone()];
+                exprs.push(self.define_procedure(accessor, vec![obj.clone()], body)?);
+            }
+            if let Some(modifier) = spec.get(2) {
+                let body = vec![builtin(scheme_record_set), obj.clone(), record_type.clone(), index, value.clone()];
+                exprs.push(self.define_procedure(modifier, vec![obj.clone(), value.clone()], body)?);
+            }
+        }
+        Ok(Expr::Sequence(exprs))
+    }
+
+    fn analyze_special_form(&mut self, first: &Atom, args: &[Atom]) -> Result<Option<Expr>, SchemeError> {
+        let sym = match first.as_symbol() {
+            Some(sym) => sym.name(),
+            None => return Ok(None)
+        };
+        let expr = match &*sym {
+            "define" => {
+                let (target, body) = args.split_first().ok_or_else(|| SchemeError::syntax("define takes 2 arguments"))?;
+                self.analyze_define(target, body)?
+            },
+            "if" => self.analyze_if(args)?,
+            "lambda" => {
+                let (params, body) = args.split_first().ok_or_else(|| SchemeError::syntax("lambda requires an argument list"))?;
+                self.analyze_lambda("lambda", params, body)?
+            },
+            "quote" => {
+                if args.len() != 1 {
+                    return Err(SchemeError::syntax("quote takes 1 argument"))
+                }
+                Expr::Constant(args[0].clone())
+            },
+            "begin" => Expr::Sequence(self.analyze_all(args)?),
+            "import" => Expr::Import(args.to_vec()),
+            "define-library" => {
+                let (name, declarations) = args.split_first().ok_or_else(|| SchemeError::syntax("define-library requires a name"))?;
+                Expr::DefineLibrary(name.clone(), declarations.to_vec())
+            },
+            "include" => Expr::Include(args.to_vec(), false),
+            "include-ci" => Expr::Include(args.to_vec(), true),
+            "guard" => {
+                let (spec, body) = args.split_first().ok_or_else(|| SchemeError::syntax("guard requires a clause list"))?;
+                self.analyze_guard(spec, body)?
+            },
+            "receive" => {
+                if args.len() < 2 {
+                    return Err(SchemeError::syntax("receive requires formals and an expression"))
+                }
+                let producer = self.analyze_lambda("receive", &Atom::Nil, &args[1..2])?;
+                let consumer = self.analyze_lambda("receive", &args[0], &args[2..])?;
+                call_builtin(scheme_call_with_values, vec![producer, consumer])
+            },
+            "let-values" | "let*-values" => {
+                let (bindings, body) = args.split_first().ok_or_else(|| SchemeError::syntax(&format!("{} requires bindings", sym)))?;
+                self.analyze_let_values(&sym, bindings, body, &*sym == "let*-values")?
+            },
+            "define-values" => {
+                if args.len() != 2 {
+                    return Err(SchemeError::syntax("define-values takes 2 arguments"))
+                }
+                self.analyze_define_values(&args[0], &args[1])?
+            },
+            "define-record-type" => self.analyze_define_record_type(args)?,
+            _ => return Ok(None)
+        };
+        Ok(Some(expr))
+    }
+}
+
+// The names a `define-record-type` form with operands `args` defines
+fn record_type_names(args: &[Atom]) -> Vec<Symbol> {
+    let mut names: Vec<Option<Symbol>> = vec![];
+    names.push(args.first().and_then(Atom::as_symbol));
+    names.push(args.get(1).and_then(Atom::car).as_ref().and_then(Atom::as_symbol));
+    names.push(args.get(2).and_then(Atom::as_symbol));
+    for spec in args.iter().skip(3).filter_map(Atom::to_vec) {
+        names.extend(spec.iter().skip(1).map(Atom::as_symbol));
+    }
+    names.into_iter().flatten().collect()
+}
+
+// A builtin procedure as a datum, so forms can expand into calls to it even where its name is
+// shadowed
+fn builtin(func: SchemeFn) -> Atom {
+    Atom::Callable(SchemeFnWrap::Fn(func))
+}
+
+fn call_builtin(func: SchemeFn, args: Vec<Expr>) -> Expr {
+    Expr::Call(Box::new(Expr::Constant(builtin(func))), args)
+}
@@ -1,94 +1,446 @@
-use std::fmt::{Debug, Display, Formatter};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
 use std;
-use environment::SchemeFnWrap;
+use environment::{Environment, SchemeFnWrap};
+use error::SchemeError;
+use gc::{self, Object};
+use hash_table::HashTable;
+use port::Port;
+use print::write_escaped;
+use record::{Record, RecordType};
+use symbol::Symbol;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
+pub struct Pair {
+    pub car: Atom,
+    pub cdr: Atom,
+}
+
+// Atoms compare with `==` the way `eqv?` does: pairs, strings, procedures and error objects are
+// only equal to themselves. See `is_equal` for structural comparison
+#[derive(Clone)]
 pub enum Atom {
     Bool(bool),
     Int(i32),
-    Symbol(String),
-    Cons(Box<Atom>, Box<Atom>),
-    List(Vec<Atom>),
+    Char(char),
+    Symbol(Symbol),
+    // Strings are immutable, so copies share one allocation
+    Str(Rc<str>),
+    // Pairs are shared, so copies of an Atom::Pair all see `set-car!` and `set-cdr!`
+    Pair(Rc<RefCell<Pair>>),
+    // Vectors and bytevectors are shared the same way
+    Vector(Rc<RefCell<Vec<Atom>>>),
+    Bytevector(Rc<RefCell<Vec<u8>>>),
+    HashTable(Rc<RefCell<HashTable>>),
+    Port(Rc<Port>),
+    Record(Rc<Record>),
+    RecordType(Rc<RecordType>),
+    // An environment such as `interaction-environment` returns, for `eval` and `load`
+    Environment(Rc<RefCell<Environment>>),
     Callable(SchemeFnWrap),
+    Error(Rc<SchemeError>),
+    // The empty list
     Nil,
+    // The value of expressions like `define` that have no useful result
+    Unspecified,
+    // Returned by input procedures at the end of a port
+    Eof,
+    // The result of `values` with zero or several values
+    Values(Rc<[Atom]>),
 }
 
-impl<'a> Atom {
+impl Atom {
+    pub fn cons(car: Atom, cdr: Atom) -> Atom {
+        let pair = Rc::new(RefCell::new(Pair { car, cdr }));
+        gc::track(Object::Pair(pair.clone()));
+        Atom::Pair(pair)
+    }
+
+    pub fn vector(atoms: Vec<Atom>) -> Atom {
+        let vector = Rc::new(RefCell::new(atoms));
+        gc::track(Object::Vector(vector.clone()));
+        Atom::Vector(vector)
+    }
+
+    pub fn bytevector(bytes: Vec<u8>) -> Atom {
+        Atom::Bytevector(Rc::new(RefCell::new(bytes)))
+    }
+
+    pub fn hash_table(table: HashTable) -> Atom {
+        let table = Rc::new(RefCell::new(table));
+        gc::track(Object::HashTable(table.clone()));
+        Atom::HashTable(table)
+    }
+
+    pub fn record(record_type: Rc<RecordType>, fields: Vec<Atom>) -> Atom {
+        let record = Rc::new(Record { record_type, fields: RefCell::new(fields) });
+        gc::track(Object::Record(record.clone()));
+        Atom::Record(record)
+    }
+
+    // An error object that a handler or `guard` clause can receive
+    pub fn error(err: SchemeError) -> Atom {
+        let err = Rc::new(err);
+        gc::track(Object::Error(err.clone()));
+        Atom::Error(err)
+    }
+
+    // The result of returning `values`, which is the value itself if there's exactly one
+    pub fn values(mut values: Vec<Atom>) -> Atom {
+        if values.len() == 1 {
+            return values.pop().unwrap()
+        }
+        Atom::Values(values.into())
+    }
+
+    // The values returned by an expression that evaluated to this atom
+    pub fn into_values(self) -> Vec<Atom> {
+        match self {
+            Atom::Values(values) => values.to_vec(),
+            atom => vec![atom]
+        }
+    }
+
+    // Build a proper list from `atoms`
+    pub fn list(atoms: Vec<Atom>) -> Atom {
+        Atom::list_with_tail(atoms, Atom::Nil)
+    }
+
+    // Build a list from `atoms` ending in `tail` rather than the empty list
+    pub fn list_with_tail(atoms: Vec<Atom>, tail: Atom) -> Atom {
+        atoms.into_iter().rev().fold(tail, |cdr, car| Atom::cons(car, cdr))
+    }
+
     pub fn as_int(&self) -> Option<i32> {
         if let Atom::Int(i) = *self {
-            return Some(i)
+            Some(i)
         } else {
-            return None
+            None
         }
     }
-    pub fn as_int_result(&self) -> Result<i32, String> {
-        self.as_int().ok_or("Not an int".to_string())
+    pub fn as_int_result(&self) -> Result<i32, SchemeError> {
+        self.as_int().ok_or_else(|| SchemeError::type_error("Not an int"))
     }
-    pub fn as_list(&'a self) -> Option<&Vec<Atom>> {
-        if let Atom::List(ref l) = *self {
-            return Some(l)
+    pub fn as_byte_result(&self) -> Result<u8, SchemeError> {
+        match *self {
+            Atom::Int(n) if (0..=255).contains(&n) => Ok(n as u8),
+            _ => Err(SchemeError::type_error("Not a byte"))
+        }
+    }
+    pub fn as_char_result(&self) -> Result<char, SchemeError> {
+        if let Atom::Char(c) = *self {
+            Ok(c)
         } else {
-            return None
+            Err(SchemeError::type_error("Not a char"))
+        }
+    }
+    pub fn as_pair(&self) -> Option<&Rc<RefCell<Pair>>> {
+        if let Atom::Pair(ref pair) = *self {
+            Some(pair)
+        } else {
+            None
+        }
+    }
+    pub fn as_pair_result(&self) -> Result<&Rc<RefCell<Pair>>, SchemeError> {
+        self.as_pair().ok_or_else(|| SchemeError::type_error("Not a pair"))
+    }
+    pub fn as_vector_result(&self) -> Result<&Rc<RefCell<Vec<Atom>>>, SchemeError> {
+        if let Atom::Vector(ref vector) = *self {
+            Ok(vector)
+        } else {
+            Err(SchemeError::type_error("Not a vector"))
+        }
+    }
+    pub fn as_bytevector_result(&self) -> Result<&Rc<RefCell<Vec<u8>>>, SchemeError> {
+        if let Atom::Bytevector(ref bytes) = *self {
+            Ok(bytes)
+        } else {
+            Err(SchemeError::type_error("Not a bytevector"))
+        }
+    }
+    pub fn as_hash_table_result(&self) -> Result<&Rc<RefCell<HashTable>>, SchemeError> {
+        if let Atom::HashTable(ref table) = *self {
+            Ok(table)
+        } else {
+            Err(SchemeError::type_error("Not a hash table"))
+        }
+    }
+    pub fn as_port_result(&self) -> Result<&Rc<Port>, SchemeError> {
+        if let Atom::Port(ref port) = *self {
+            Ok(port)
+        } else {
+            Err(SchemeError::type_error("Not a port"))
+        }
+    }
+    pub fn as_record_type_result(&self) -> Result<&Rc<RecordType>, SchemeError> {
+        if let Atom::RecordType(ref record_type) = *self {
+            Ok(record_type)
+        } else {
+            Err(SchemeError::type_error("Not a record type"))
+        }
+    }
+    pub fn as_environment_result(&self) -> Result<&Rc<RefCell<Environment>>, SchemeError> {
+        if let Atom::Environment(ref env) = *self {
+            Ok(env)
+        } else {
+            Err(SchemeError::type_error("Not an environment"))
+        }
+    }
+    pub fn car(&self) -> Option<Atom> {
+        self.as_pair().map(|pair| pair.borrow().car.clone())
+    }
+    pub fn cdr(&self) -> Option<Atom> {
+        self.as_pair().map(|pair| pair.borrow().cdr.clone())
+    }
+    // Split a list into its elements and whatever ends it, which is Nil for a proper list
+    pub fn list_parts(&self) -> (Vec<Atom>, Atom) {
+        let mut atoms = vec![];
+        let mut current = self.clone();
+        while let Some(pair) = current.as_pair().cloned() {
+            let pair = pair.borrow();
+            atoms.push(pair.car.clone());
+            current = pair.cdr.clone();
         }
+        (atoms, current)
     }
-    pub fn as_list_result(&'a self) -> Result<&Vec<Atom>, String> {
-        self.as_list().ok_or("Not a list".to_string())
+    // The elements of a proper list, or None for anything else
+    pub fn to_vec(&self) -> Option<Vec<Atom>> {
+        let mut atoms = vec![];
+        let mut current = self.clone();
+        // Trails behind `current` at half speed to detect cycles
+        let mut slow = self.clone();
+        loop {
+            current = match current {
+                Atom::Nil => return Some(atoms),
+                Atom::Pair(ref pair) => {
+                    let pair = pair.borrow();
+                    atoms.push(pair.car.clone());
+                    pair.cdr.clone()
+                },
+                _ => return None
+            };
+            if atoms.len() % 2 == 0 {
+                slow = slow.cdr().unwrap_or(Atom::Nil);
+                if let (Atom::Pair(a), Atom::Pair(b)) = (&slow, &current) {
+                    if Rc::ptr_eq(a, b) {
+                        return None
+                    }
+                }
+            }
+        }
     }
-    pub fn as_callable_result(&'a self) -> Result<&SchemeFnWrap, String> {
-        if let &Atom::Callable(ref callable) = self {
-            return Ok(callable)
+    // The pairs of a list, which fails on circular lists. Whatever ends the list is left in
+    // `Pairs::tail` once they run out
+    pub fn pairs(&self) -> Pairs {
+        Pairs { current: self.clone(), slow: self.clone(), count: 0 }
+    }
+    pub fn to_vec_result(&self) -> Result<Vec<Atom>, SchemeError> {
+        self.to_vec().ok_or_else(|| SchemeError::type_error("Not a list"))
+    }
+    // True for proper lists: chains of pairs ending in the empty list, without cycles
+    pub fn is_list(&self) -> bool {
+        let mut slow = self.clone();
+        let mut fast = self.clone();
+        loop {
+            for _ in 0..2 {
+                fast = match fast {
+                    Atom::Nil => return true,
+                    Atom::Pair(ref pair) => pair.borrow().cdr.clone(),
+                    _ => return false
+                };
+            }
+            slow = slow.cdr().unwrap_or(Atom::Nil);
+            if let (Atom::Pair(a), Atom::Pair(b)) = (&slow, &fast) {
+                if Rc::ptr_eq(a, b) {
+                    return false
+                }
+            }
+        }
+    }
+    pub fn as_callable_result(&self) -> Result<&SchemeFnWrap, SchemeError> {
+        if let Atom::Callable(ref callable) = *self {
+            Ok(callable)
         } else {
-            return Err("Not a callable".to_string())
+            Err(SchemeError::type_error("Not a callable"))
         }
     }
-    pub fn as_symbol(&self) -> Option<&String> {
-        if let &Atom::Symbol(ref sym) = self {
+    pub fn as_symbol(&self) -> Option<Symbol> {
+        if let Atom::Symbol(sym) = *self {
             return Some(sym)
         }
-        return None
+        None
+    }
+    pub fn as_symbol_result(&self) -> Result<Symbol, SchemeError> {
+        self.as_symbol().ok_or_else(|| SchemeError::type_error("Not a symbol"))
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        if let Atom::Str(ref s) = *self {
+            return Some(s)
+        }
+        None
+    }
+    pub fn as_str_result(&self) -> Result<&str, SchemeError> {
+        self.as_str().ok_or_else(|| SchemeError::type_error("Not a string"))
+    }
+    pub fn as_error_result(&self) -> Result<&SchemeError, SchemeError> {
+        if let Atom::Error(ref err) = *self {
+            Ok(err)
+        } else {
+            Err(SchemeError::type_error("Not an error object"))
+        }
+    }
+    // Identity comparison for `eq?` and `eqv?`
+    pub fn is_eqv(&self, other: &Atom) -> bool {
+        match (self, other) {
+            (Atom::Bool(a), Atom::Bool(b)) => a == b,
+            (Atom::Int(a), Atom::Int(b)) => a == b,
+            (Atom::Char(a), Atom::Char(b)) => a == b,
+            (Atom::Symbol(a), Atom::Symbol(b)) => a == b,
+            (Atom::Str(a), Atom::Str(b)) => Rc::ptr_eq(a, b),
+            (Atom::Pair(a), Atom::Pair(b)) => Rc::ptr_eq(a, b),
+            (Atom::Vector(a), Atom::Vector(b)) => Rc::ptr_eq(a, b),
+            (Atom::Bytevector(a), Atom::Bytevector(b)) => Rc::ptr_eq(a, b),
+            (Atom::HashTable(a), Atom::HashTable(b)) => Rc::ptr_eq(a, b),
+            (Atom::Port(a), Atom::Port(b)) => Rc::ptr_eq(a, b),
+            (Atom::Record(a), Atom::Record(b)) => Rc::ptr_eq(a, b),
+            (Atom::RecordType(a), Atom::RecordType(b)) => Rc::ptr_eq(a, b),
+            (Atom::Environment(a), Atom::Environment(b)) => Rc::ptr_eq(a, b),
+            (Atom::Callable(a), Atom::Callable(b)) => a == b,
+            (Atom::Error(a), Atom::Error(b)) => Rc::ptr_eq(a, b),
+            (Atom::Nil, Atom::Nil) => true,
+            (Atom::Unspecified, Atom::Unspecified) => true,
+            (Atom::Eof, Atom::Eof) => true,
+            _ => false
+        }
+    }
+    // Structural comparison for `equal?`, which terminates on circular structures
+    pub fn is_equal(&self, other: &Atom) -> bool {
+        self.is_equal_visiting(other, &mut HashSet::new())
+    }
+    // `visited` holds the pairs of pairs and vectors already being compared. Meeting one again
+    // means the structures are alike so far, so that branch is taken to be equal
+    fn is_equal_visiting(&self, other: &Atom, visited: &mut HashSet<(*const (), *const ())>) -> bool {
+        let mut a = self.clone();
+        let mut b = other.clone();
+        // Loop down the cdrs and only recurse into the cars, so long lists don't use up the stack
+        loop {
+            let (pair_a, pair_b) = match (&a, &b) {
+                (Atom::Pair(pair_a), Atom::Pair(pair_b)) => (pair_a.clone(), pair_b.clone()),
+                (Atom::Str(str_a), Atom::Str(str_b)) => return str_a == str_b,
+                (Atom::Vector(vec_a), Atom::Vector(vec_b)) => {
+                    if !visited.insert((Rc::as_ptr(vec_a) as *const (), Rc::as_ptr(vec_b) as *const ())) {
+                        return true
+                    }
+                    let (vec_a, vec_b) = (vec_a.borrow(), vec_b.borrow());
+                    return vec_a.len() == vec_b.len() &&
+                        vec_a.iter().zip(vec_b.iter()).all(|(a, b)| a.is_equal_visiting(b, visited))
+                },
+                (Atom::Bytevector(bytes_a), Atom::Bytevector(bytes_b)) => return *bytes_a.borrow() == *bytes_b.borrow(),
+                _ => return a.is_eqv(&b)
+            };
+            if !visited.insert((Rc::as_ptr(&pair_a) as *const (), Rc::as_ptr(&pair_b) as *const ())) {
+                return true
+            }
+            let (pair_a, pair_b) = (pair_a.borrow(), pair_b.borrow());
+            if !pair_a.car.is_equal_visiting(&pair_b.car, visited) {
+                return false
+            }
+            a = pair_a.cdr.clone();
+            b = pair_b.cdr.clone();
+        }
     }
-    pub fn as_symbol_result(&self) -> Result<&String, String> {
-        self.as_symbol().ok_or("Not a symbol".to_string())
+    // Everything except #f counts as true in a conditional
+    pub fn is_truthy(&self) -> bool {
+        *self != Atom::Bool(false)
     }
 }
 
-impl Debug for Atom {
-    fn fmt(&self, f:&mut Formatter) -> std::fmt::Result {
-        use self::Atom::*;
-        match self {
-            &Bool(b) => write!(f, "'{}'", b),
-            &Int(n) => write!(f, "'{}'", n),
-            &Symbol(ref s) => write!(f, "'{}'", &**s),
-            &Cons(ref car, ref cdr) => write!(f, "'({:?} . {:?})", &**car, &**cdr),
-            &List(ref atoms) => write!(f, "{:?}", &**atoms),
-            &Callable(_) => write!(f, "SchemeFn()"),
-            &Nil => write!(f, "Nil"),
+pub struct Pairs {
+    current: Atom,
+    // Trails behind `current` at half speed to detect cycles
+    slow: Atom,
+    count: usize,
+}
+
+impl Pairs {
+    // The rest of the list, which is Nil once a proper list has run out
+    pub fn tail(&self) -> &Atom {
+        &self.current
+    }
+}
+
+impl Iterator for Pairs {
+    type Item = Result<Rc<RefCell<Pair>>, SchemeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = self.current.as_pair()?.clone();
+        self.current = pair.borrow().cdr.clone();
+        self.count += 1;
+        if self.count.is_multiple_of(2) {
+            self.slow = self.slow.cdr().unwrap_or(Atom::Nil);
+            if let (Atom::Pair(a), Atom::Pair(b)) = (&self.slow, &self.current) {
+                if Rc::ptr_eq(a, b) {
+                    // Stop here rather than report the same cycle forever
+                    self.current = Atom::Nil;
+                    return Some(Err(SchemeError::type_error("Circular list")))
+                }
+            }
         }
+        Some(Ok(pair))
+    }
+}
+
+// Character names the reader accepts after `#\\`, besides the characters themselves
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        self.is_eqv(other)
     }
 }
 
-#[allow(unused_must_use)]
-impl Display for Atom {
+impl Debug for Atom {
     fn fmt(&self, f:&mut Formatter) -> std::fmt::Result {
         use self::Atom::*;
-        match self {
-            &Bool(b) => write!(f, "{}", b),
-            &Int(n) => write!(f, "{}", n),
-            &Symbol(ref s) => write!(f, "{}", &**s),
-            &Cons(ref car, ref cdr) => write!(f, "'({} . {})", &**car, &**cdr),
-            &List(ref atoms) => {
-                write!(f, "(");
-                for (i, ref atom) in (&**atoms).iter().enumerate() {
-                    if i == atoms.len()-1 {
-                        write!(f, "{}", *atom);
-                    } else {
-                        write!(f, "{} ", *atom);
-                    }
+        match *self {
+            Bool(b) => write!(f, "'{}'", b),
+            Int(n) => write!(f, "'{}'", n),
+            Char(c) => write!(f, "{:?}", c),
+            Symbol(s) => write!(f, "'{}'", s),
+            Str(ref s) => write_escaped(f, s),
+            Pair(_) => {
+                let (atoms, tail) = self.list_parts();
+                write!(f, "{:?}", atoms)?;
+                if tail != Nil {
+                    write!(f, " . {:?}", tail)?;
                 }
-                write!(f, ")")
-            }
-            &Callable(_) => write!(f, "SchemeFn()"),
-            &Nil => write!(f, "Nil"),
+                Ok(())
+            },
+            Vector(ref vector) => write!(f, "Vector({:?})", vector.borrow()),
+            Bytevector(ref bytes) => write!(f, "Bytevector({:?})", bytes.borrow()),
+            HashTable(ref table) => write!(f, "HashTable({})", table.borrow().len()),
+            Port(_) => write!(f, "Port"),
+            Record(ref record) => write!(f, "Record({:?}, {:?})", record.record_type.name.to_string(), record.fields.borrow()),
+            RecordType(ref record_type) => write!(f, "RecordType({:?})", record_type.name.to_string()),
+            Environment(_) => write!(f, "Environment"),
+            Callable(_) => write!(f, "SchemeFn()"),
+            Error(ref err) => write!(f, "Error({:?})", err.message),
+            Nil => write!(f, "Nil"),
+            Unspecified => write!(f, "Unspecified"),
+            Eof => write!(f, "Eof"),
+            Values(ref values) => write!(f, "Values({:?})", values),
         }
     }
-}
\ No newline at end of file
+}
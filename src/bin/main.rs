@@ -1,21 +1,313 @@
 extern crate rust_scheme;
+extern crate rustyline;
 
-use rust_scheme::interpreter::run_program;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
 
-fn main() {
-    //let program = "(begin (define pi 3.14159) (define r 10) (* pi (* r r)))";
-    //let program = "(begin (define (x y) (* y 5) (* y 10)) (x 8))";
-    //let program = "(/ (- (+ 1 (+ 1 1)) 5) 2)";
-    let program = "(begin (define (fact x) (* x (fact (- x 1))) (fact 5))";
-    //let program = "(begin (define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))) (fib 20))";
-    //let program = "(apply * 5 5 (list 4 5 6))";
-    //let program = "( list (+ 34 6) ( * 2 1 )  )";
-    //let mut input = String::new();
-    //read_stdin_into(&mut input);
-    //tokenize(program);
-    match run_program(program) {
-        Ok(result) => println!("Read program: {}", result),
-        Err(err) => println!("Semantic error! {}", err),
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+
+use rust_scheme::atom::Atom;
+use rust_scheme::environment::Environment;
+use rust_scheme::error::{ErrorKind, SchemeError};
+use rust_scheme::format::format_source;
+use rust_scheme::interpreter::{Engine, eval_program, set_command_line, set_engine};
+use rust_scheme::library::add_library_path;
+use rust_scheme::load::load_file;
+use rust_scheme::parse::is_complete;
+
+const USAGE: &str = "\
+Usage: rust-scheme [options] [file | -] [args...]
+       rust-scheme fmt [--check] files...
+
+With no file or expressions, start an interactive REPL.
+  -e EXPR     Evaluate EXPR and print its value. May be given more than once
+  -L DIR      Add DIR to the library search path used by import
+  --engine E  Run code with E, either vm (the default) or tree
+  -           Read the program from stdin
+  --          Stop option parsing; remaining arguments go to (command-line)
+  -h, --help  Show this message
+
+fmt reformats the files in place. With --check it only lists the ones that need it,
+exiting with status 1 if there are any";
+
+const HELP: &str = "\
+Enter Scheme expressions to evaluate them. Expressions may span several lines.
+  ,help   Show this message
+  ,env    List the bindings in the current environment
+  ,quit   Exit the REPL";
+
+// Results longer than this are broken over several lines
+const PRETTY_WIDTH: usize = 79;
+
+struct ReplHelper {
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '\'')
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        let candidates = self.env.borrow().symbols().into_iter()
+            .filter(|symbol| symbol.starts_with(prefix))
+            .map(|symbol| Pair { display: symbol.clone(), replacement: symbol })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_complete(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rust_scheme_history"))
+}
+
+fn print_error(source: &str, err: &SchemeError) {
+    let source = err.file.as_ref().map_or(source, |file| &**file);
+    match err.span {
+        Some(span) => eprintln!("{}:{}: Error: {}", source, span, err),
+        None => eprintln!("{}: Error: {}", source, err),
+    }
+    for name in &err.trace {
+        eprintln!("  in {}", name);
+    }
+}
+
+fn print_env(env: &Rc<RefCell<Environment>>) {
+    let env = env.borrow();
+    for symbol in env.symbols() {
+        match env.get_symbol(symbol.as_str()) {
+            Ok(Atom::Callable(_)) => println!("{}", symbol),
+            Ok(value) => println!("{} = {}", symbol, value),
+            Err(_) => (),
+        }
     }
 }
 
+// Runs the REPL until EOF or `,quit`, returning the exit code
+fn run_repl(env: Rc<RefCell<Environment>>) -> i32 {
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Failed to start REPL: {}", err);
+            return 1
+        }
+    };
+    editor.set_helper(Some(ReplHelper { env: env.clone() }));
+    let history = history_path();
+    if let Some(ref path) = history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut code = 0;
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue
+            },
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Error reading input: {}", err);
+                break
+            }
+        };
+        input.push_str(&line);
+        input.push('\n');
+        // Without a terminal the validator isn't consulted, so keep reading until the input balances
+        if !is_complete(&input) {
+            continue
+        }
+        let source = input.trim().to_string();
+        input.clear();
+        if source.is_empty() {
+            continue
+        }
+        let _ = editor.add_history_entry(source.as_str());
+
+        match source.as_ref() {
+            ",quit" | ",q" => break,
+            ",help" | ",h" => println!("{}", HELP),
+            ",env" => print_env(&env),
+            _ if source.starts_with(',') => println!("Unknown command {}, try ,help", source),
+            _ => match eval_program(&source, env.clone()) {
+                Ok(Atom::Unspecified) => (),
+                Ok(result) => println!("{}", result.pretty(PRETTY_WIDTH)),
+                Err(SchemeError { kind: ErrorKind::Exit(exit_code), .. }) => {
+                    code = exit_code;
+                    break
+                },
+                Err(err) => print_error("repl", &err),
+            }
+        }
+    }
+
+    if let Some(ref path) = history {
+        let _ = editor.save_history(path);
+    }
+    code
+}
+
+// Converts the result of running a whole program into an exit code
+fn exit_code(name: &str, result: Result<Atom, SchemeError>, print_result: bool) -> i32 {
+    match result {
+        Ok(result) => {
+            if print_result && result != Atom::Unspecified {
+                println!("{}", result);
+            }
+            0
+        },
+        Err(SchemeError { kind: ErrorKind::Exit(code), .. }) => code,
+        Err(err) => {
+            print_error(name, &err);
+            1
+        }
+    }
+}
+
+fn run_stdin(env: Rc<RefCell<Environment>>) -> i32 {
+    let mut program = String::new();
+    match io::stdin().read_to_string(&mut program) {
+        Ok(_) => exit_code("-", eval_program(&program, env), false),
+        Err(err) => {
+            eprintln!("Failed to read stdin: {}", err);
+            1
+        }
+    }
+}
+
+// Reformats each file in place, or with `check` lists the files that aren't formatted
+fn run_fmt(paths: &[String], check: bool) -> i32 {
+    let mut code = 0;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: Error: {}", path, err);
+                code = 1;
+                continue
+            }
+        };
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                print_error(path, &err);
+                code = 1;
+                continue
+            }
+        };
+        if formatted == source {
+            continue
+        }
+        if check {
+            println!("{}", path);
+            code = 1;
+        } else if let Err(err) = fs::write(path, formatted) {
+            eprintln!("{}: Error: {}", path, err);
+            code = 1;
+        }
+    }
+    code
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2)
+}
+
+fn main() {
+    let mut args = env::args();
+    let program_name = args.next().unwrap_or_else(|| "rust-scheme".to_string());
+    let mut expressions: Vec<String> = vec![];
+    let mut script: Option<String> = None;
+
+    let args: Vec<String> = args.collect();
+    if args.first().map(String::as_str) == Some("fmt") {
+        let check = args.iter().any(|arg| arg == "--check");
+        let paths: Vec<String> = args[1..].iter().filter(|&arg| arg != "--check").cloned().collect();
+        if paths.is_empty() {
+            usage_error("fmt requires at least one file");
+        }
+        process::exit(run_fmt(&paths, check))
+    }
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "-e" => match args.next() {
+                Some(expression) => expressions.push(expression),
+                None => usage_error("-e requires an expression"),
+            },
+            "-L" => match args.next() {
+                Some(dir) => add_library_path(PathBuf::from(dir)),
+                None => usage_error("-L requires a directory"),
+            },
+            "--engine" => match args.next().as_deref() {
+                Some("vm") => set_engine(Engine::Vm),
+                Some("tree") => set_engine(Engine::Tree),
+                Some(engine) => usage_error(&format!("Unknown engine {}", engine)),
+                None => usage_error("--engine requires vm or tree"),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return
+            },
+            "--" => break,
+            _ if arg.starts_with('-') && arg != "-" => usage_error(&format!("Unknown option {}", arg)),
+            _ => {
+                script = Some(arg);
+                break
+            }
+        }
+    }
+    let mut command_line = vec![script.clone().unwrap_or(program_name)];
+    command_line.extend(args);
+    set_command_line(command_line);
+
+    let env = Environment::standard_env();
+    for expression in &expressions {
+        let code = exit_code("-e", eval_program(expression, env.clone()), true);
+        if code != 0 {
+            process::exit(code);
+        }
+    }
+    let code = match script {
+        Some(ref path) if path == "-" => run_stdin(env),
+        Some(path) => exit_code(&path, load_file(&path, env, false), false),
+        None if expressions.is_empty() => run_repl(env),
+        None => 0,
+    };
+    process::exit(code)
+}
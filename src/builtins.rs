@@ -1,207 +1,1995 @@
 #![allow(unused_variables)]
 use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
 use std::rc::Rc;
 use atom::Atom;
-use environment::Environment;
-use interpreter::{evaluate, execute_fn};
+use continuation::{Continuation, escaped, next_extent, pop_winder, push_winder};
+use environment::{Environment, SchemeFn, SchemeFnWrap, env_alloc, env_root};
+use error::{ErrorKind, SchemeError};
+use gc;
+use hash_table::{self, Equivalence, HashTable};
+use symbol::Symbol;
+use load::{file_error, load_file};
+use port::{self, Current, Port};
+use record::Record;
+use interpreter::{evaluate, execute_fn, raise, with_exception_handler, command_line};
+use library::import;
 
-pub fn scheme_add(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
-    return Ok(Atom::Int(args.iter().filter_map(|a| a.as_int()).fold(0, |a, b| a + b)));
+// The error for arithmetic on `args` whose result doesn't fit in an integer
+pub fn integer_overflow(args: &[Atom]) -> SchemeError {
+    SchemeError::user("Integer overflow", args.to_vec())
 }
 
-pub fn scheme_multiply(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
-    return Ok(Atom::Int(args.iter().filter_map(|a| a.as_int()).fold(1, |a, b| a * b)));
+pub fn scheme_add(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    match args.iter().filter_map(|a| a.as_int()).try_fold(0, i32::checked_add) {
+        Some(sum) => Ok(Atom::Int(sum)),
+        None => Err(integer_overflow(&args))
+    }
+}
+
+pub fn scheme_multiply(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    match args.iter().filter_map(|a| a.as_int()).try_fold(1, i32::checked_mul) {
+        Some(product) => Ok(Atom::Int(product)),
+        None => Err(integer_overflow(&args))
+    }
 }
 
-pub fn scheme_subtract(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_subtract(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to subtract {}", args.len()));
-    } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Int(arg1 - arg2));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to subtract {}", args.len())));
+    }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    match arg1.checked_sub(arg2) {
+        Some(difference) => Ok(Atom::Int(difference)),
+        None => Err(integer_overflow(&args))
     }
 }
 
-pub fn scheme_divide(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_divide(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to divide {}", args.len()));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to divide {}", args.len())));
+    }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    if arg2 == 0 {
+        return Err(SchemeError::user("Division by zero", vec![args[0].clone()]));
+    }
+    match arg1.checked_div(arg2) {
+        Some(quotient) => Ok(Atom::Int(quotient)),
+        None => Err(integer_overflow(&args))
+    }
+}
+
+// Floored division, returning the quotient and remainder
+pub fn scheme_floor_divide(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (quotient, remainder) = floor_division("floor/", &args)?;
+    Ok(Atom::values(vec![Atom::Int(quotient), Atom::Int(remainder)]))
+}
+
+pub fn scheme_floor_quotient(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::Int(floor_division("floor-quotient", &args)?.0))
+}
+
+pub fn scheme_floor_remainder(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::Int(floor_division("floor-remainder", &args)?.1))
+}
+
+// Truncated division, returning the quotient and remainder
+pub fn scheme_truncate_divide(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (quotient, remainder) = truncate_division("truncate/", &args)?;
+    Ok(Atom::values(vec![Atom::Int(quotient), Atom::Int(remainder)]))
+}
+
+pub fn scheme_truncate_quotient(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::Int(truncate_division("truncate-quotient", &args)?.0))
+}
+
+pub fn scheme_truncate_remainder(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::Int(truncate_division("truncate-remainder", &args)?.1))
+}
+
+fn floor_division(name: &str, args: &[Atom]) -> Result<(i32, i32), SchemeError> {
+    let (quotient, remainder) = truncate_division(name, args)?;
+    let d = args[1].as_int_result()?;
+    // Truncation rounds towards zero, so a negative inexact quotient is one too large
+    if remainder != 0 && (remainder < 0) != (d < 0) {
+        Ok((quotient - 1, remainder + d))
     } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Int(arg1 / arg2));
+        Ok((quotient, remainder))
     }
 }
 
-pub fn scheme_gt(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+// The quotient and remainder of truncated division, or an error if the quotient doesn't fit in
+// an integer, as when dividing the most negative integer by -1
+fn truncate_division(name: &str, args: &[Atom]) -> Result<(i32, i32), SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to > {}", args.len()));
-    } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Bool(arg1 > arg2));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to {} {}", name, args.len())));
     }
+    let n = args[0].as_int_result()?;
+    let d = args[1].as_int_result()?;
+    if d == 0 {
+        return Err(SchemeError::user("Division by zero", vec![args[0].clone()]));
+    }
+    match (n.checked_div(d), n.checked_rem(d)) {
+        (Some(quotient), Some(remainder)) => Ok((quotient, remainder)),
+        _ => Err(integer_overflow(args))
+    }
+}
+
+// The largest integer whose square is at most the argument, and the difference between them
+pub fn scheme_exact_integer_sqrt(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to exact-integer-sqrt {}", args.len())));
+    }
+    let n = args[0].as_int_result()?;
+    if n < 0 {
+        return Err(SchemeError::user("exact-integer-sqrt requires a non-negative integer", vec![args[0].clone()]));
+    }
+    let root = (n as f64).sqrt() as i32;
+    // Correct for rounding in the floating point square root
+    let root = (root.saturating_sub(1)..=root + 1).rev().find(|&r| (r as i64) * (r as i64) <= n as i64).unwrap_or(0);
+    Ok(Atom::values(vec![Atom::Int(root), Atom::Int(n - root * root)]))
 }
 
-pub fn scheme_lt(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_gt(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to < {}", args.len()));
-    } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Bool(arg1 < arg2));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to > {}", args.len())));
     }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    Ok(Atom::Bool(arg1 > arg2))
 }
 
-pub fn scheme_ge(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_lt(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to >= {}", args.len()));
-    } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Bool(arg1 >= arg2));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to < {}", args.len())));
     }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    Ok(Atom::Bool(arg1 < arg2))
 }
 
-pub fn scheme_le(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_ge(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to <= {}", args.len()));
-    } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Bool(arg1 <= arg2));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to >= {}", args.len())));
     }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    Ok(Atom::Bool(arg1 >= arg2))
 }
 
-pub fn scheme_eq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_le(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to = {}", args.len()));
-    } else {
-        let arg1 = try!(args[0].as_int_result());
-        let arg2 = try!(args[1].as_int_result());
-        return Ok(Atom::Bool(arg1 == arg2));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to <= {}", args.len())));
+    }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    Ok(Atom::Bool(arg1 <= arg2))
+}
+
+pub fn scheme_eq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to = {}", args.len())));
     }
+    let arg1 = args[0].as_int_result()?;
+    let arg2 = args[1].as_int_result()?;
+    Ok(Atom::Bool(arg1 == arg2))
 }
 
-pub fn scheme_abs(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_abs(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 1 {
-        return Err(format!("Invalid number of operands to abs {}", args.len()));
-    } else {
-        let arg = try!(args[0].as_int_result());
-        return Ok(Atom::Int(arg.abs()));
+        return Err(SchemeError::arity(&format!("Invalid number of operands to abs {}", args.len())));
+    }
+    let arg = args[0].as_int_result()?;
+    match arg.checked_abs() {
+        Some(abs) => Ok(Atom::Int(abs)),
+        None => Err(integer_overflow(&args))
     }
 }
 
-pub fn scheme_append(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_append(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let mut args = args;
+    // The last argument is shared rather than copied, and need not be a list
+    let tail = args.pop().unwrap_or(Atom::Nil);
     let mut l1 = vec![];
     for arg in args {
-        let mut lst = try!(arg.as_list_result()).clone();
-        l1.append(&mut lst);
+        l1.append(&mut arg.to_vec_result()?);
     }
-    return Ok(Atom::List(l1))
+    Ok(Atom::list_with_tail(l1, tail))
 }
 
-pub fn scheme_apply(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_apply(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() < 2 {
-        return Err(format!("Invalid number of operands to apply {}", args.len()))
+        return Err(SchemeError::arity(&format!("Invalid number of operands to apply {}", args.len())))
     }
-    let func = try!(args[0].as_callable_result()).clone();
-    let mut lst: Vec<Atom> = args.clone().into_iter().skip(1).take(args.len()-1).collect();
-    lst.append(&mut try!(args[args.len()-1].as_list_result()).clone());
-    execute_fn(func, lst, env)
+    let (func, args) = apply_args(&args)?;
+    execute_fn(func, args, env)
 }
 
-pub fn scheme_begin(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
-    if args.len() == 0 {
-        return Err("Begin requires at least one argument".to_string())
-    }
-    let args_len = args.len();
-    let mut iter = args.into_iter();
-    for arg in iter.by_ref().take(args_len-1) {
-        try!(evaluate(arg.clone(), env.clone()));
+// The procedure `apply` calls and the arguments it passes, given at least two operands
+pub fn apply_args(args: &[Atom]) -> Result<(SchemeFnWrap, Vec<Atom>), SchemeError> {
+    let func = args[0].as_callable_result()?.clone();
+    let mut lst: Vec<Atom> = args[1..args.len()-1].to_vec();
+    lst.append(&mut args[args.len()-1].to_vec_result()?);
+    Ok((func, lst))
+}
+
+pub fn scheme_values(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::values(args))
+}
+
+// Only used outside the VM, which calls the consumer without recursing
+pub fn scheme_call_with_values(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to call-with-values {}", args.len())))
     }
-    return evaluate(iter.last().unwrap_or(Atom::Nil), env)
+    let producer = args[0].as_callable_result()?.clone();
+    let consumer = args[1].as_callable_result()?.clone();
+    let values = execute_fn(producer, vec![], env.clone())?.into_values();
+    execute_fn(consumer, values, env)
 }
 
-pub fn scheme_car(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+// Only used outside the VM, which captures continuations that can be resumed after returning
+pub fn scheme_call_cc(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 1 {
-        return Err("CAR expects 1 argument".to_string())
+        return Err(SchemeError::arity(&format!("Invalid number of operands to call/cc {}", args.len())))
     }
-    match args[0].clone() {
-        Atom::Cons(car, cdr) => return Ok(*car),
-        Atom::List(atoms) => {
-            if atoms.len() > 0 {
-                return Ok(atoms[0].clone())
-            } else {
-                return Err("CAR expects a pair".to_string())
-            }
+    let receiver = args[0].as_callable_result()?.clone();
+    let extent = next_extent();
+    let k = Continuation::escape_only(extent).into_atom();
+    match execute_fn(receiver, vec![k], env) {
+        Err(err) => match escaped(&err) {
+            Some((k, values)) if k.extent == extent => Ok(Continuation::result(values)),
+            _ => Err(err)
         },
-        _ => return Err("CAR expects a pair".to_string())
+        result => result
     }
 }
 
-pub fn scheme_cdr(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_dynamic_wind(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to dynamic-wind {}", args.len())))
+    }
+    let before = args[0].as_callable_result()?.clone();
+    let thunk = args[1].as_callable_result()?.clone();
+    let after = args[2].as_callable_result()?.clone();
+    execute_fn(before.clone(), vec![], env.clone())?;
+    push_winder(before, after.clone());
+    let result = execute_fn(thunk, vec![], env.clone());
+    // A continuation called from the thunk has already run `after` on its way out
+    if let Err(ref err) = result {
+        if escaped(err).is_some() {
+            return result
+        }
+    }
+    pop_winder();
+    execute_fn(after, vec![], env)?;
+    result
+}
+
+pub fn scheme_car(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 1 {
-        return Err("CDR expects 1 argument".to_string())
+        return Err(SchemeError::arity("CAR expects 1 argument"))
     }
-    match args[0].clone() {
-        Atom::Cons(car, cdr) => return Ok(*cdr),
-        Atom::List(atoms) => {
-            if atoms.len() > 0 {
-                return Ok(Atom::List(atoms.into_iter().skip(1).collect()))
-            } else {
-                return Err("CDR expects a pair".to_string())
-            }
-        },
-        _ => return Err("CDR expects a pair".to_string())
+    args[0].car().ok_or_else(|| SchemeError::type_error("CAR expects a pair"))
+}
+
+pub fn scheme_cdr(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity("CDR expects 1 argument"))
     }
+    args[0].cdr().ok_or_else(|| SchemeError::type_error("CDR expects a pair"))
 }
 
-pub fn scheme_cons(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_cons(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to cons {}", args.len()))
-    } else {
-        return Ok(Atom::Cons(Box::new(args[0].clone()), Box::new(args[1].clone())))
+        return Err(SchemeError::arity(&format!("Invalid number of operands to cons {}", args.len())))
     }
+    Ok(Atom::cons(args[0].clone(), args[1].clone()))
 }
 
-// TODO -- Shore up implementation
-pub fn scheme_is_eq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_set_car(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to eq? {}", args.len()))
-    } else {
-        return Ok(Atom::Bool(match &args[0] {
-            &Atom::Bool(_) => args[0] == args[1],
-            &Atom::Int(_) => args[0] == args[1],
-            _ => &args[0] as *const Atom == &args[1] as *const Atom
-        }))
+        return Err(SchemeError::arity(&format!("Invalid number of operands to set-car! {}", args.len())))
     }
+    args[0].as_pair_result()?.borrow_mut().car = args[1].clone();
+    Ok(Atom::Unspecified)
 }
 
-pub fn scheme_is_equal(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_set_cdr(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 2 {
-        return Err(format!("Invalid number of operands to equal? {}", args.len()))
-    } else {
-        return Ok(Atom::Bool(args[0] == args[1]))
+        return Err(SchemeError::arity(&format!("Invalid number of operands to set-cdr! {}", args.len())))
+    }
+    args[0].as_pair_result()?.borrow_mut().cdr = args[1].clone();
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_is_eq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to eq? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0].is_eqv(&args[1])))
+}
+
+pub fn scheme_is_eqv(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to eqv? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0].is_eqv(&args[1])))
+}
+
+pub fn scheme_is_equal(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to equal? {}", args.len())))
     }
+    Ok(Atom::Bool(args[0].is_equal(&args[1])))
 }
 
-pub fn scheme_list(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
-    Ok(Atom::List(args))
+pub fn scheme_list(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::list(args))
 }
 
-pub fn scheme_is_list(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
+pub fn scheme_is_list(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
     if args.len() != 1 {
-        return Err(format!("Invalid number of operands to equal? {}", args.len()))
-    } else {
-        if let Some(_) = args[0].as_list() {
-            return Ok(Atom::Bool(true))
+        return Err(SchemeError::arity(&format!("Invalid number of operands to equal? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0].is_list()))
+}
+
+pub fn scheme_is_null(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to null? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0] == Atom::Nil))
+}
+
+pub fn scheme_is_pair(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to pair? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0].as_pair().is_some()))
+}
+
+// The elements of a proper list, failing on improper and circular ones
+fn list_elements(list: &Atom) -> Result<Vec<Atom>, SchemeError> {
+    let mut pairs = list.pairs();
+    let mut atoms = vec![];
+    for pair in &mut pairs {
+        atoms.push(pair?.borrow().car.clone());
+    }
+    if *pairs.tail() != Atom::Nil {
+        return Err(SchemeError::type_error("Not a list"))
+    }
+    Ok(atoms)
+}
+
+pub fn scheme_length(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to length {}", args.len())))
+    }
+    let mut pairs = args[0].pairs();
+    let mut len = 0;
+    for pair in &mut pairs {
+        pair?;
+        len += 1;
+    }
+    if *pairs.tail() != Atom::Nil {
+        return Err(SchemeError::type_error("Not a list"))
+    }
+    Ok(Atom::Int(len))
+}
+
+pub fn scheme_reverse(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to reverse {}", args.len())))
+    }
+    let mut atoms = list_elements(&args[0])?;
+    atoms.reverse();
+    Ok(Atom::list(atoms))
+}
+
+pub fn scheme_last_pair(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to last-pair {}", args.len())))
+    }
+    let mut last = None;
+    for pair in args[0].pairs() {
+        last = Some(pair?);
+    }
+    last.map(Atom::Pair).ok_or_else(|| SchemeError::type_error("Not a pair"))
+}
+
+// Copies the pairs of a list, keeping whatever ends it
+pub fn scheme_list_copy(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to list-copy {}", args.len())))
+    }
+    let mut pairs = args[0].pairs();
+    let mut atoms = vec![];
+    for pair in &mut pairs {
+        atoms.push(pair?.borrow().car.clone());
+    }
+    Ok(Atom::list_with_tail(atoms, pairs.tail().clone()))
+}
+
+// The list left after taking `k` cdrs of `list`
+fn list_tail(list: &Atom, k: &Atom) -> Result<Atom, SchemeError> {
+    let out_of_range = || SchemeError::user("Index out of range", vec![k.clone()]);
+    let count = k.as_int_result()?;
+    if count < 0 {
+        return Err(out_of_range())
+    }
+    let mut tail = list.clone();
+    for _ in 0..count {
+        tail = tail.cdr().ok_or_else(out_of_range)?;
+    }
+    Ok(tail)
+}
+
+pub fn scheme_list_tail(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to list-tail {}", args.len())))
+    }
+    list_tail(&args[0], &args[1])
+}
+
+pub fn scheme_list_ref(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to list-ref {}", args.len())))
+    }
+    list_tail(&args[0], &args[1])?.car().ok_or_else(|| SchemeError::user("Index out of range", vec![args[1].clone()]))
+}
+
+// How `member` and `assoc` compare, with the procedure given to them if there is one
+enum Comparison<'a> {
+    Eqv,
+    Equal,
+    Procedure(&'a SchemeFnWrap),
+}
+
+impl<'a> Comparison<'a> {
+    fn operand(args: &'a [Atom], at: usize) -> Result<Comparison<'a>, SchemeError> {
+        match args.get(at) {
+            Some(func) => Ok(Comparison::Procedure(func.as_callable_result()?)),
+            None => Ok(Comparison::Equal),
+        }
+    }
+
+    fn matches(&self, x: &Atom, y: Atom, env: &Rc<RefCell<Environment>>) -> Result<bool, SchemeError> {
+        match *self {
+            Comparison::Eqv => Ok(x.is_eqv(&y)),
+            Comparison::Equal => Ok(x.is_equal(&y)),
+            Comparison::Procedure(func) => Ok(execute_fn(func.clone(), vec![x.clone(), y], env.clone())?.is_truthy()),
+        }
+    }
+}
+
+// The first pair of `list` whose car matches `x`, or #f
+fn member(x: &Atom, list: &Atom, comparison: Comparison, env: &Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let mut pairs = list.pairs();
+    for pair in &mut pairs {
+        let pair = pair?;
+        let car = pair.borrow().car.clone();
+        if comparison.matches(x, car, env)? {
+            return Ok(Atom::Pair(pair))
+        }
+    }
+    if *pairs.tail() != Atom::Nil {
+        return Err(SchemeError::type_error("Not a list"))
+    }
+    Ok(Atom::Bool(false))
+}
+
+// The first entry of the association list whose key matches `key`, or #f
+fn assoc(key: &Atom, alist: &Atom, comparison: Comparison, env: &Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    for entry in list_elements(alist)? {
+        let entry_key = entry.as_pair().ok_or_else(|| SchemeError::type_error("Association list entry is not a pair"))?
+            .borrow().car.clone();
+        if comparison.matches(key, entry_key, env)? {
+            return Ok(entry)
+        }
+    }
+    Ok(Atom::Bool(false))
+}
+
+pub fn scheme_memq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to memq {}", args.len())))
+    }
+    member(&args[0], &args[1], Comparison::Eqv, &env)
+}
+
+pub fn scheme_memv(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to memv {}", args.len())))
+    }
+    member(&args[0], &args[1], Comparison::Eqv, &env)
+}
+
+pub fn scheme_member(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to member {}", args.len())))
+    }
+    member(&args[0], &args[1], Comparison::operand(&args, 2)?, &env)
+}
+
+pub fn scheme_assq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to assq {}", args.len())))
+    }
+    assoc(&args[0], &args[1], Comparison::Eqv, &env)
+}
+
+pub fn scheme_assv(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to assv {}", args.len())))
+    }
+    assoc(&args[0], &args[1], Comparison::Eqv, &env)
+}
+
+pub fn scheme_assoc(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to assoc {}", args.len())))
+    }
+    assoc(&args[0], &args[1], Comparison::operand(&args, 2)?, &env)
+}
+
+// The argument lists for calling a procedure on the elements of `lists` at each position, up to
+// the length of the shortest list
+fn list_rows(lists: &[Atom]) -> Result<Vec<Vec<Atom>>, SchemeError> {
+    let lists = lists.iter().map(list_elements).collect::<Result<Vec<_>, SchemeError>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    Ok((0..len).map(|i| lists.iter().map(|list| list[i].clone()).collect()).collect())
+}
+
+// Checks for a procedure followed by at least one list, as `map` and most SRFI 1 procedures take
+pub fn procedure_and_lists<'a>(name: &str, args: &'a [Atom]) -> Result<(&'a SchemeFnWrap, Vec<Vec<Atom>>), SchemeError> {
+    if args.len() < 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to {} {}", name, args.len())))
+    }
+    Ok((args[0].as_callable_result()?, list_rows(&args[1..])?))
+}
+
+// Only used outside the VM, which runs the procedure in its own frames
+pub fn scheme_map(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (func, rows) = procedure_and_lists("map", &args)?;
+    let results = rows.into_iter()
+        .map(|row| execute_fn(func.clone(), row, env.clone()))
+        .collect::<Result<_, SchemeError>>()?;
+    Ok(Atom::list(results))
+}
+
+// Only used outside the VM, which runs the procedure in its own frames
+pub fn scheme_for_each(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (func, rows) = procedure_and_lists("for-each", &args)?;
+    for row in rows {
+        execute_fn(func.clone(), row, env.clone())?;
+    }
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_append_map(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (func, rows) = procedure_and_lists("append-map", &args)?;
+    let mut results = vec![];
+    for row in rows {
+        results.extend(list_elements(&execute_fn(func.clone(), row, env.clone())?)?);
+    }
+    Ok(Atom::list(results))
+}
+
+pub fn scheme_filter_map(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (func, rows) = procedure_and_lists("filter-map", &args)?;
+    let mut results = vec![];
+    for row in rows {
+        let result = execute_fn(func.clone(), row, env.clone())?;
+        if result.is_truthy() {
+            results.push(result);
+        }
+    }
+    Ok(Atom::list(results))
+}
+
+// The elements of the list operand that do and don't satisfy the predicate
+fn partition_operands(name: &str, args: &[Atom], env: &Rc<RefCell<Environment>>) -> Result<(Vec<Atom>, Vec<Atom>), SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to {} {}", name, args.len())))
+    }
+    let pred = args[0].as_callable_result()?;
+    let (mut matching, mut rest) = (vec![], vec![]);
+    for atom in list_elements(&args[1])? {
+        if execute_fn(pred.clone(), vec![atom.clone()], env.clone())?.is_truthy() {
+            matching.push(atom);
         } else {
-            return Ok(Atom::Bool(false))
+            rest.push(atom);
+        }
+    }
+    Ok((matching, rest))
+}
+
+pub fn scheme_filter(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (matching, _) = partition_operands("filter", &args, &env)?;
+    Ok(Atom::list(matching))
+}
+
+pub fn scheme_remove(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (_, rest) = partition_operands("remove", &args, &env)?;
+    Ok(Atom::list(rest))
+}
+
+pub fn scheme_partition(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (matching, rest) = partition_operands("partition", &args, &env)?;
+    Ok(Atom::values(vec![Atom::list(matching), Atom::list(rest)]))
+}
+
+// Calls `(kons e1 e2 ... acc)` on the elements at each position from left to right
+pub fn scheme_fold(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to fold {}", args.len())))
+    }
+    let kons = args[0].as_callable_result()?;
+    let mut acc = args[1].clone();
+    for mut row in list_rows(&args[2..])? {
+        row.push(acc);
+        acc = execute_fn(kons.clone(), row, env.clone())?;
+    }
+    Ok(acc)
+}
+
+// Like `fold`, from right to left
+pub fn scheme_fold_right(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to fold-right {}", args.len())))
+    }
+    let kons = args[0].as_callable_result()?;
+    let mut acc = args[1].clone();
+    for mut row in list_rows(&args[2..])?.into_iter().rev() {
+        row.push(acc);
+        acc = execute_fn(kons.clone(), row, env.clone())?;
+    }
+    Ok(acc)
+}
+
+// Like `fold` starting from the first element, or `ridentity` for the empty list
+pub fn scheme_reduce(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to reduce {}", args.len())))
+    }
+    let func = args[0].as_callable_result()?;
+    let mut atoms = list_elements(&args[2])?.into_iter();
+    let mut acc = match atoms.next() {
+        Some(first) => first,
+        None => return Ok(args[1].clone())
+    };
+    for atom in atoms {
+        acc = execute_fn(func.clone(), vec![atom, acc], env.clone())?;
+    }
+    Ok(acc)
+}
+
+pub fn scheme_find(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to find {}", args.len())))
+    }
+    let pred = args[0].as_callable_result()?;
+    for atom in list_elements(&args[1])? {
+        if execute_fn(pred.clone(), vec![atom.clone()], env.clone())?.is_truthy() {
+            return Ok(atom)
+        }
+    }
+    Ok(Atom::Bool(false))
+}
+
+// The first pair whose car satisfies the predicate, or #f
+pub fn scheme_find_tail(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to find-tail {}", args.len())))
+    }
+    let pred = args[0].as_callable_result()?;
+    for pair in args[1].pairs() {
+        let pair = pair?;
+        let car = pair.borrow().car.clone();
+        if execute_fn(pred.clone(), vec![car], env.clone())?.is_truthy() {
+            return Ok(Atom::Pair(pair))
+        }
+    }
+    Ok(Atom::Bool(false))
+}
+
+// The first true value the predicate returns, or #f
+pub fn scheme_any(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (pred, rows) = procedure_and_lists("any", &args)?;
+    for row in rows {
+        let result = execute_fn(pred.clone(), row, env.clone())?;
+        if result.is_truthy() {
+            return Ok(result)
+        }
+    }
+    Ok(Atom::Bool(false))
+}
+
+// #f if the predicate returns #f for any elements, otherwise the last value it returns
+pub fn scheme_every(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (pred, rows) = procedure_and_lists("every", &args)?;
+    let mut result = Atom::Bool(true);
+    for row in rows {
+        result = execute_fn(pred.clone(), row, env.clone())?;
+        if !result.is_truthy() {
+            break
+        }
+    }
+    Ok(result)
+}
+
+pub fn scheme_list_index(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (pred, rows) = procedure_and_lists("list-index", &args)?;
+    for (i, row) in rows.into_iter().enumerate() {
+        if execute_fn(pred.clone(), row, env.clone())?.is_truthy() {
+            return Ok(Atom::Int(i as i32))
+        }
+    }
+    Ok(Atom::Bool(false))
+}
+
+// The list `(start start+step ...)` with `count` elements
+pub fn scheme_iota(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to iota {}", args.len())))
+    }
+    let count = args[0].as_int_result()?;
+    if count < 0 {
+        return Err(SchemeError::user("Count must not be negative", vec![args[0].clone()]))
+    }
+    let start = args.get(1).map_or(Ok(0), Atom::as_int_result)?;
+    let step = args.get(2).map_or(Ok(1), Atom::as_int_result)?;
+    let elements = (0..count).map(|i| i.checked_mul(step).and_then(|offset| start.checked_add(offset)).map(Atom::Int));
+    let elements: Option<Vec<Atom>> = elements.collect();
+    Ok(Atom::list(elements.ok_or_else(|| integer_overflow(&args))?))
+}
+
+// Removes the elements equal to `x`, comparing with `equal?` or the given procedure
+pub fn scheme_delete(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to delete {}", args.len())))
+    }
+    let comparison = Comparison::operand(&args, 2)?;
+    let mut kept = vec![];
+    for atom in list_elements(&args[1])? {
+        if !comparison.matches(&args[0], atom.clone(), &env)? {
+            kept.push(atom);
         }
     }
+    Ok(Atom::list(kept))
+}
+
+// Keeps the first of each group of equal elements, in their original order
+pub fn scheme_delete_duplicates(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to delete-duplicates {}", args.len())))
+    }
+    let comparison = Comparison::operand(&args, 1)?;
+    let mut kept: Vec<Atom> = vec![];
+    'elements: for atom in list_elements(&args[0])? {
+        for earlier in &kept {
+            if comparison.matches(earlier, atom.clone(), &env)? {
+                continue 'elements
+            }
+        }
+        kept.push(atom);
+    }
+    Ok(Atom::list(kept))
+}
+
+// Merge two sorted runs, taking from `left` first when elements are equal so sorting is stable
+fn merge_runs(left: Vec<Atom>, right: Vec<Atom>, less: &mut dyn FnMut(&Atom, &Atom) -> Result<bool, SchemeError>) -> Result<Vec<Atom>, SchemeError> {
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let next = if less(r, l)? { right.next() } else { left.next() };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+// A stable merge sort. The comparison is a Scheme procedure, so unlike `slice::sort_by` this
+// copes with it failing or not being a consistent order
+fn merge_sort(mut atoms: Vec<Atom>, less: &mut dyn FnMut(&Atom, &Atom) -> Result<bool, SchemeError>) -> Result<Vec<Atom>, SchemeError> {
+    if atoms.len() < 2 {
+        return Ok(atoms)
+    }
+    let right = atoms.split_off(atoms.len() / 2);
+    let left = merge_sort(atoms, less)?;
+    let right = merge_sort(right, less)?;
+    merge_runs(left, right, less)
+}
+
+// Calls the procedure `less?` to compare two elements
+fn less_than<'a>(less: &'a Atom, env: &'a Rc<RefCell<Environment>>) -> Result<impl FnMut(&Atom, &Atom) -> Result<bool, SchemeError> + 'a, SchemeError> {
+    let func = less.as_callable_result()?;
+    Ok(move |a: &Atom, b: &Atom| Ok(execute_fn(func.clone(), vec![a.clone(), b.clone()], env.clone())?.is_truthy()))
+}
+
+// The elements of a list or vector, sorted
+fn sorted(sequence: &Atom, less: &Atom, env: &Rc<RefCell<Environment>>) -> Result<Vec<Atom>, SchemeError> {
+    let atoms = match *sequence {
+        Atom::Vector(ref vector) => vector.borrow().clone(),
+        _ => list_elements(sequence)?
+    };
+    merge_sort(atoms, &mut less_than(less, env)?)
+}
+
+// Returns a sorted copy of a list or vector
+pub fn scheme_sort(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to sort {}", args.len())))
+    }
+    let atoms = sorted(&args[0], &args[1], &env)?;
+    match args[0] {
+        Atom::Vector(_) => Ok(Atom::vector(atoms)),
+        _ => Ok(Atom::list(atoms))
+    }
 }
 
-pub fn scheme_let(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
-    Err("let not defined".to_string())
-}
\ No newline at end of file
+// Sorts a list or vector in place, returning it
+pub fn scheme_sort_in_place(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to sort! {}", args.len())))
+    }
+    let atoms = sorted(&args[0], &args[1], &env)?;
+    match args[0] {
+        Atom::Vector(ref vector) => *vector.borrow_mut() = atoms,
+        _ => for (pair, atom) in args[0].pairs().zip(atoms) {
+            pair?.borrow_mut().car = atom;
+        }
+    }
+    Ok(args[0].clone())
+}
+
+pub fn scheme_list_sort(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to list-sort {}", args.len())))
+    }
+    let atoms = merge_sort(list_elements(&args[1])?, &mut less_than(&args[0], &env)?)?;
+    Ok(Atom::list(atoms))
+}
+
+pub fn scheme_vector_sort(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector-sort {}", args.len())))
+    }
+    let atoms = args[1].as_vector_result()?.borrow().clone();
+    Ok(Atom::vector(merge_sort(atoms, &mut less_than(&args[0], &env)?)?))
+}
+
+// Merges two sorted lists, with elements of the first before equal ones of the second
+pub fn scheme_merge(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to merge {}", args.len())))
+    }
+    let merged = merge_runs(list_elements(&args[0])?, list_elements(&args[1])?, &mut less_than(&args[2], &env)?)?;
+    Ok(Atom::list(merged))
+}
+
+pub fn scheme_let(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Err(SchemeError::syntax("let not defined"))
+}
+
+pub fn scheme_is_symbol(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to symbol? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0].as_symbol().is_some()))
+}
+
+pub fn scheme_symbol_to_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to symbol->string {}", args.len())))
+    }
+    Ok(Atom::Str(args[0].as_symbol_result()?.name()))
+}
+
+pub fn scheme_string_to_symbol(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to string->symbol {}", args.len())))
+    }
+    Ok(Atom::Symbol(Symbol::intern(args[0].as_str_result()?)))
+}
+
+pub fn scheme_is_symbol_eq(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to symbol=? {}", args.len())))
+    }
+    let first = args[0].as_symbol_result()?;
+    let mut result = true;
+    for arg in &args[1..] {
+        result &= arg.as_symbol_result()? == first;
+    }
+    Ok(Atom::Bool(result))
+}
+
+// `(gensym [prefix])` makes a symbol that can't be typed in or produced by `string->symbol`
+pub fn scheme_gensym(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to gensym {}", args.len())))
+    }
+    let prefix = match args.first() {
+        None => "g".into(),
+        Some(&Atom::Symbol(sym)) => sym.name(),
+        Some(prefix) => Rc::from(prefix.as_str_result()?),
+    };
+    Ok(Atom::Symbol(Symbol::uninterned(&prefix)))
+}
+
+// Check `atom` is an index into a sequence of `len` elements
+fn index_operand(atom: &Atom, len: usize) -> Result<usize, SchemeError> {
+    match atom.as_int_result()? {
+        index if index >= 0 && (index as usize) < len => Ok(index as usize),
+        _ => Err(SchemeError::user("Index out of range", vec![atom.clone()]))
+    }
+}
+
+// The optional start and end operands from `args[from..]`, which select part of a sequence of
+// `len` elements and default to all of it
+fn range_operands(args: &[Atom], from: usize, len: usize) -> Result<(usize, usize), SchemeError> {
+    let bound = |i: usize, default: usize| match args.get(i) {
+        Some(atom) => match atom.as_int_result()? {
+            n if n >= 0 && n as usize <= len => Ok(n as usize),
+            _ => Err(SchemeError::user("Index out of range", vec![atom.clone()]))
+        },
+        None => Ok(default)
+    };
+    let start = bound(from, 0)?;
+    let end = bound(from + 1, len)?;
+    if start > end {
+        return Err(SchemeError::user("Start index is after end index", vec![Atom::Int(start as i32), Atom::Int(end as i32)]))
+    }
+    Ok((start, end))
+}
+
+pub fn scheme_is_vector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Vector(_))))
+}
+
+pub fn scheme_vector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    Ok(Atom::vector(args))
+}
+
+pub fn scheme_make_vector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to make-vector {}", args.len())))
+    }
+    let len = args[0].as_int_result()?;
+    if len < 0 {
+        return Err(SchemeError::user("Vector length must not be negative", vec![args[0].clone()]))
+    }
+    let fill = args.get(1).cloned().unwrap_or(Atom::Unspecified);
+    Ok(Atom::vector(vec![fill; len as usize]))
+}
+
+pub fn scheme_vector_length(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector-length {}", args.len())))
+    }
+    Ok(Atom::Int(args[0].as_vector_result()?.borrow().len() as i32))
+}
+
+pub fn scheme_vector_ref(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector-ref {}", args.len())))
+    }
+    let vector = args[0].as_vector_result()?.borrow();
+    let index = index_operand(&args[1], vector.len())?;
+    Ok(vector[index].clone())
+}
+
+pub fn scheme_vector_set(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector-set! {}", args.len())))
+    }
+    let mut vector = args[0].as_vector_result()?.borrow_mut();
+    let index = index_operand(&args[1], vector.len())?;
+    vector[index] = args[2].clone();
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_vector_fill(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 || args.len() > 4 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector-fill! {}", args.len())))
+    }
+    let mut vector = args[0].as_vector_result()?.borrow_mut();
+    let (start, end) = range_operands(&args, 2, vector.len())?;
+    for atom in &mut vector[start..end] {
+        *atom = args[1].clone();
+    }
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_vector_to_list(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector->list {}", args.len())))
+    }
+    let vector = args[0].as_vector_result()?.borrow();
+    let (start, end) = range_operands(&args, 1, vector.len())?;
+    Ok(Atom::list(vector[start..end].to_vec()))
+}
+
+pub fn scheme_list_to_vector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to list->vector {}", args.len())))
+    }
+    Ok(Atom::vector(args[0].to_vec_result()?))
+}
+
+// Calls the procedure on the elements at each index, up to the length of the shortest vector
+pub fn scheme_vector_map(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to vector-map {}", args.len())))
+    }
+    let func = args[0].as_callable_result()?;
+    // Copy the elements first, since the procedure may modify the vectors
+    let vectors: Vec<Vec<Atom>> = args[1..].iter()
+        .map(|arg| Ok(arg.as_vector_result()?.borrow().clone()))
+        .collect::<Result<_, SchemeError>>()?;
+    let len = vectors.iter().map(Vec::len).min().unwrap_or(0);
+    let mut results = Vec::with_capacity(len);
+    for i in 0..len {
+        let func_args = vectors.iter().map(|vector| vector[i].clone()).collect();
+        results.push(execute_fn(func.clone(), func_args, env.clone())?);
+    }
+    Ok(Atom::vector(results))
+}
+
+pub fn scheme_is_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to bytevector? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Bytevector(_))))
+}
+
+pub fn scheme_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let bytes: Result<Vec<u8>, SchemeError> = args.iter().map(Atom::as_byte_result).collect();
+    Ok(Atom::bytevector(bytes?))
+}
+
+pub fn scheme_make_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to make-bytevector {}", args.len())))
+    }
+    let len = args[0].as_int_result()?;
+    if len < 0 {
+        return Err(SchemeError::user("Bytevector length must not be negative", vec![args[0].clone()]))
+    }
+    let fill = match args.get(1) {
+        Some(fill) => fill.as_byte_result()?,
+        None => 0
+    };
+    Ok(Atom::bytevector(vec![fill; len as usize]))
+}
+
+pub fn scheme_bytevector_length(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to bytevector-length {}", args.len())))
+    }
+    Ok(Atom::Int(args[0].as_bytevector_result()?.borrow().len() as i32))
+}
+
+pub fn scheme_bytevector_u8_ref(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to bytevector-u8-ref {}", args.len())))
+    }
+    let bytes = args[0].as_bytevector_result()?.borrow();
+    let index = index_operand(&args[1], bytes.len())?;
+    Ok(Atom::Int(bytes[index] as i32))
+}
+
+pub fn scheme_bytevector_u8_set(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to bytevector-u8-set! {}", args.len())))
+    }
+    let byte = args[2].as_byte_result()?;
+    let mut bytes = args[0].as_bytevector_result()?.borrow_mut();
+    let index = index_operand(&args[1], bytes.len())?;
+    bytes[index] = byte;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_bytevector_copy(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to bytevector-copy {}", args.len())))
+    }
+    let bytes = args[0].as_bytevector_result()?.borrow();
+    let (start, end) = range_operands(&args, 1, bytes.len())?;
+    Ok(Atom::bytevector(bytes[start..end].to_vec()))
+}
+
+pub fn scheme_utf8_to_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to utf8->string {}", args.len())))
+    }
+    let bytes = args[0].as_bytevector_result()?.borrow();
+    let (start, end) = range_operands(&args, 1, bytes.len())?;
+    match std::str::from_utf8(&bytes[start..end]) {
+        Ok(s) => Ok(Atom::Str(s.into())),
+        Err(_) => Err(SchemeError::user("Invalid UTF-8", vec![args[0].clone()]))
+    }
+}
+
+pub fn scheme_string_to_utf8(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to string->utf8 {}", args.len())))
+    }
+    Ok(Atom::bytevector(args[0].as_str_result()?.as_bytes().to_vec()))
+}
+
+// The equivalence for a table compared by `func`, if it's a builtin that can be hashed natively
+fn builtin_equivalence(func: &SchemeFnWrap) -> Option<Equivalence> {
+    let is = |builtin: SchemeFn| *func == SchemeFnWrap::Fn(builtin);
+    if is(scheme_is_eq) || is(scheme_is_eqv) {
+        Some(Equivalence::Eqv)
+    } else if is(scheme_is_equal) {
+        Some(Equivalence::Equal)
+    } else {
+        None
+    }
+}
+
+pub fn scheme_make_hash_table(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to make-hash-table {}", args.len())))
+    }
+    let equivalence = match (args.first(), args.get(1)) {
+        (None, _) => Equivalence::Equal,
+        (Some(equal), Some(hash)) => Equivalence::Custom {
+            equal: equal.as_callable_result()?.clone(),
+            hash: hash.as_callable_result()?.clone(),
+        },
+        (Some(equal), None) => builtin_equivalence(equal.as_callable_result()?)
+            .ok_or_else(|| SchemeError::user("A custom equality needs a hash function", vec![equal.clone()]))?,
+    };
+    Ok(Atom::hash_table(HashTable::new(equivalence)))
+}
+
+pub fn scheme_is_hash_table(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::HashTable(_))))
+}
+
+// Calls `fail` with no arguments if the key is missing, and `success` on the value if it's found
+pub fn scheme_hash_table_ref(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() < 2 || args.len() > 4 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-ref {}", args.len())))
+    }
+    let table = args[0].as_hash_table_result()?;
+    match (hash_table::get(table, &args[1], &env)?, args.get(2), args.get(3)) {
+        (Some(value), _, Some(success)) => execute_fn(success.as_callable_result()?.clone(), vec![value], env),
+        (Some(value), _, None) => Ok(value),
+        (None, Some(fail), _) => execute_fn(fail.as_callable_result()?.clone(), vec![], env),
+        (None, None, _) => Err(SchemeError::user("Key not found", vec![args[1].clone()])),
+    }
+}
+
+pub fn scheme_hash_table_ref_default(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-ref/default {}", args.len())))
+    }
+    let table = args[0].as_hash_table_result()?;
+    Ok(hash_table::get(table, &args[1], &env)?.unwrap_or_else(|| args[2].clone()))
+}
+
+pub fn scheme_hash_table_set(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-set! {}", args.len())))
+    }
+    hash_table::set(args[0].as_hash_table_result()?, args[1].clone(), args[2].clone(), &env)?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_hash_table_delete(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-delete! {}", args.len())))
+    }
+    hash_table::delete(args[0].as_hash_table_result()?, &args[1], &env)?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_hash_table_exists(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-exists? {}", args.len())))
+    }
+    Ok(Atom::Bool(hash_table::get(args[0].as_hash_table_result()?, &args[1], &env)?.is_some()))
+}
+
+// Sets the key to `proc` applied to its value, or to `default` if it's missing
+pub fn scheme_hash_table_update_default(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 4 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-update!/default {}", args.len())))
+    }
+    let table = args[0].as_hash_table_result()?;
+    let func = args[2].as_callable_result()?;
+    let value = hash_table::get(table, &args[1], &env)?.unwrap_or_else(|| args[3].clone());
+    let value = execute_fn(func.clone(), vec![value], env.clone())?;
+    hash_table::set(table, args[1].clone(), value, &env)?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_hash_table_size(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-size {}", args.len())))
+    }
+    Ok(Atom::Int(args[0].as_hash_table_result()?.borrow().len() as i32))
+}
+
+pub fn scheme_hash_table_keys(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-keys {}", args.len())))
+    }
+    let table = args[0].as_hash_table_result()?.borrow();
+    Ok(Atom::list(table.entries().map(|(key, _)| key.clone()).collect()))
+}
+
+pub fn scheme_hash_table_values(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-values {}", args.len())))
+    }
+    let table = args[0].as_hash_table_result()?.borrow();
+    Ok(Atom::list(table.entries().map(|(_, value)| value.clone()).collect()))
+}
+
+pub fn scheme_hash_table_to_alist(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table->alist {}", args.len())))
+    }
+    let table = args[0].as_hash_table_result()?.borrow();
+    Ok(Atom::list(table.entries().map(|(key, value)| Atom::cons(key.clone(), value.clone())).collect()))
+}
+
+// Calls the procedure on each key and value, in the order they were added
+pub fn scheme_hash_table_walk(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to hash-table-walk {}", args.len())))
+    }
+    let func = args[1].as_callable_result()?;
+    // Copy the entries first, since the procedure may modify the table
+    let entries: Vec<(Atom, Atom)> = args[0].as_hash_table_result()?.borrow().entries()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    for (key, value) in entries {
+        execute_fn(func.clone(), vec![key, value], env.clone())?;
+    }
+    Ok(Atom::Unspecified)
+}
+
+// Reduce a hash below the optional bound operand of `hash`, `string-hash` and `hash-by-identity`
+fn bounded_hash(name: &str, hash: i32, args: &[Atom]) -> Result<Atom, SchemeError> {
+    match args.len() {
+        1 => Ok(Atom::Int(hash)),
+        2 => match args[1].as_int_result()? {
+            bound if bound > 0 => Ok(Atom::Int(hash % bound)),
+            _ => Err(SchemeError::user("Hash bound must be positive", vec![args[1].clone()]))
+        },
+        _ => Err(SchemeError::arity(&format!("Invalid number of operands to {} {}", name, args.len())))
+    }
+}
+
+pub fn scheme_hash(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let hash = args.first().map_or(0, hash_table::equal_hash);
+    bounded_hash("hash", hash, &args)
+}
+
+pub fn scheme_string_hash(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let hash = match args.first() {
+        Some(s) => hash_table::string_hash(s.as_str_result()?),
+        None => 0
+    };
+    bounded_hash("string-hash", hash, &args)
+}
+
+pub fn scheme_hash_by_identity(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let hash = args.first().map_or(0, hash_table::eqv_hash);
+    bounded_hash("hash-by-identity", hash, &args)
+}
+
+pub fn scheme_is_char(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to char? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Char(_))))
+}
+
+pub fn scheme_char_to_integer(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to char->integer {}", args.len())))
+    }
+    Ok(Atom::Int(args[0].as_char_result()? as i32))
+}
+
+pub fn scheme_integer_to_char(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to integer->char {}", args.len())))
+    }
+    std::char::from_u32(args[0].as_int_result()? as u32)
+        .map(Atom::Char)
+        .ok_or_else(|| SchemeError::user("Not a Unicode scalar value", vec![args[0].clone()]))
+}
+
+pub fn scheme_eof_object(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to eof-object {}", args.len())))
+    }
+    Ok(Atom::Eof)
+}
+
+pub fn scheme_is_eof_object(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to eof-object? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0] == Atom::Eof))
+}
+
+// The port operand at `at`, or the current port if it's left out
+fn port_operand(args: &[Atom], at: usize, which: Current) -> Result<Rc<Port>, SchemeError> {
+    match args.get(at) {
+        Some(port) => Ok(port.as_port_result()?.clone()),
+        None => Ok(port::current(which))
+    }
+}
+
+pub fn scheme_current_input_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to current-input-port {}", args.len())))
+    }
+    Ok(Atom::Port(port::current(Current::Input)))
+}
+
+pub fn scheme_current_output_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to current-output-port {}", args.len())))
+    }
+    Ok(Atom::Port(port::current(Current::Output)))
+}
+
+pub fn scheme_current_error_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to current-error-port {}", args.len())))
+    }
+    Ok(Atom::Port(port::current(Current::Error)))
+}
+
+pub fn scheme_is_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to port? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Port(_))))
+}
+
+pub fn scheme_is_input_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to input-port? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Port(ref port) if port.is_input())))
+}
+
+pub fn scheme_is_output_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to output-port? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Port(ref port) if port.is_output())))
+}
+
+pub fn scheme_is_input_port_open(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to input-port-open? {}", args.len())))
+    }
+    let port = args[0].as_port_result()?;
+    Ok(Atom::Bool(port.is_input() && port.is_open()))
+}
+
+pub fn scheme_is_output_port_open(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to output-port-open? {}", args.len())))
+    }
+    let port = args[0].as_port_result()?;
+    Ok(Atom::Bool(port.is_output() && port.is_open()))
+}
+
+pub fn scheme_close_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to close-port {}", args.len())))
+    }
+    args[0].as_port_result()?.close()?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_close_input_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to close-input-port {}", args.len())))
+    }
+    let port = args[0].as_port_result()?;
+    if !port.is_input() {
+        return Err(SchemeError::type_error("Not an input port"))
+    }
+    port.close()?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_close_output_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to close-output-port {}", args.len())))
+    }
+    let port = args[0].as_port_result()?;
+    if !port.is_output() {
+        return Err(SchemeError::type_error("Not an output port"))
+    }
+    port.close()?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_open_input_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-input-string {}", args.len())))
+    }
+    Ok(Atom::Port(Rc::new(Port::input_string(args[0].as_str_result()?))))
+}
+
+pub fn scheme_open_output_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-output-string {}", args.len())))
+    }
+    Ok(Atom::Port(Rc::new(Port::output_string())))
+}
+
+pub fn scheme_get_output_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to get-output-string {}", args.len())))
+    }
+    let contents = args[0].as_port_result()?.output_string_contents();
+    contents.map(|s| Atom::Str(s.into())).ok_or_else(|| SchemeError::type_error("Not a string output port"))
+}
+
+// Calls `thunk` with the current output port sent to `port`, restoring it afterwards
+fn with_output_to(port: Rc<Port>, thunk: &Atom, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let thunk = thunk.as_callable_result()?.clone();
+    let previous = port::set_current(Current::Output, port);
+    let result = execute_fn(thunk, vec![], env);
+    port::set_current(Current::Output, previous);
+    result
+}
+
+// Returns everything the thunk writes to the current output port as a string
+pub fn scheme_with_output_to_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to with-output-to-string {}", args.len())))
+    }
+    let port = Rc::new(Port::output_string());
+    with_output_to(port.clone(), &args[0], env)?;
+    Ok(Atom::Str(port.output_string_contents().unwrap_or_default().into()))
+}
+
+// Returns everything the procedure writes to the string port it's given
+pub fn scheme_call_with_output_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to call-with-output-string {}", args.len())))
+    }
+    let port = Rc::new(Port::output_string());
+    execute_fn(args[0].as_callable_result()?.clone(), vec![Atom::Port(port.clone())], env)?;
+    Ok(Atom::Str(port.output_string_contents().unwrap_or_default().into()))
+}
+
+pub fn scheme_write(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write {}", args.len())))
+    }
+    port_operand(&args, 1, Current::Output)?.write_str(&args[0].to_string())?;
+    Ok(Atom::Unspecified)
+}
+
+// Like `write`, but strings and characters are written as their contents, even inside lists
+pub fn scheme_display(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to display {}", args.len())))
+    }
+    port_operand(&args, 1, Current::Output)?.write_str(&args[0].display().to_string())?;
+    Ok(Atom::Unspecified)
+}
+
+// Like `write`, but labels every pair and vector that appears more than once
+pub fn scheme_write_shared(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write-shared {}", args.len())))
+    }
+    port_operand(&args, 1, Current::Output)?.write_str(&args[0].write_shared().to_string())?;
+    Ok(Atom::Unspecified)
+}
+
+// Like `write`, but never uses labels, so it doesn't return for cyclic data
+pub fn scheme_write_simple(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write-simple {}", args.len())))
+    }
+    port_operand(&args, 1, Current::Output)?.write_str(&args[0].write_simple().to_string())?;
+    Ok(Atom::Unspecified)
+}
+
+// Width `pretty-print` fits its output to
+const PRETTY_WIDTH: usize = 79;
+
+// Like `write`, but breaks long lists over indented lines, followed by a newline
+pub fn scheme_pretty_print(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to pretty-print {}", args.len())))
+    }
+    let port = port_operand(&args, 1, Current::Output)?;
+    port.write_str(&args[0].pretty(PRETTY_WIDTH))?;
+    port.write_str("\n")?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_newline(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to newline {}", args.len())))
+    }
+    port_operand(&args, 0, Current::Output)?.write_str("\n")?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_write_char(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write-char {}", args.len())))
+    }
+    let c = args[0].as_char_result()?;
+    port_operand(&args, 1, Current::Output)?.write_str(c.encode_utf8(&mut [0; 4]))?;
+    Ok(Atom::Unspecified)
+}
+
+// Writes the characters of the string from `start` to `end`
+pub fn scheme_write_string(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 4 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write-string {}", args.len())))
+    }
+    let s = args[0].as_str_result()?;
+    let (start, end) = range_operands(&args, 2, s.chars().count())?;
+    let part: String = s.chars().skip(start).take(end - start).collect();
+    port_operand(&args, 1, Current::Output)?.write_str(&part)?;
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_flush_output_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to flush-output-port {}", args.len())))
+    }
+    port_operand(&args, 0, Current::Output)?.flush()?;
+    Ok(Atom::Unspecified)
+}
+
+fn char_or_eof(c: Option<char>) -> Atom {
+    c.map_or(Atom::Eof, Atom::Char)
+}
+
+pub fn scheme_read_char(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to read-char {}", args.len())))
+    }
+    Ok(char_or_eof(port_operand(&args, 0, Current::Input)?.read_char()?))
+}
+
+pub fn scheme_peek_char(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to peek-char {}", args.len())))
+    }
+    Ok(char_or_eof(port_operand(&args, 0, Current::Input)?.peek_char()?))
+}
+
+pub fn scheme_read_line(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to read-line {}", args.len())))
+    }
+    let line = port_operand(&args, 0, Current::Input)?.read_line()?;
+    Ok(line.map_or(Atom::Eof, |line| Atom::Str(line.into())))
+}
+
+pub fn scheme_is_char_ready(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to char-ready? {}", args.len())))
+    }
+    Ok(Atom::Bool(port_operand(&args, 0, Current::Input)?.is_ready()?))
+}
+
+pub fn scheme_read(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to read {}", args.len())))
+    }
+    port_operand(&args, 0, Current::Input)?.read()
+}
+
+pub fn scheme_is_textual_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to textual-port? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Port(ref port) if !port.is_binary())))
+}
+
+pub fn scheme_is_binary_port(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to binary-port? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0], Atom::Port(ref port) if port.is_binary())))
+}
+
+pub fn scheme_open_input_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-input-bytevector {}", args.len())))
+    }
+    let bytes = args[0].as_bytevector_result()?.borrow().clone();
+    Ok(Atom::Port(Rc::new(Port::input_bytes(bytes))))
+}
+
+pub fn scheme_open_output_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-output-bytevector {}", args.len())))
+    }
+    Ok(Atom::Port(Rc::new(Port::output_bytes())))
+}
+
+pub fn scheme_get_output_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to get-output-bytevector {}", args.len())))
+    }
+    let contents = args[0].as_port_result()?.output_bytes_contents();
+    contents.map(Atom::bytevector).ok_or_else(|| SchemeError::type_error("Not a bytevector output port"))
+}
+
+fn byte_or_eof(byte: Option<u8>) -> Atom {
+    byte.map_or(Atom::Eof, |byte| Atom::Int(byte as i32))
+}
+
+pub fn scheme_read_u8(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to read-u8 {}", args.len())))
+    }
+    Ok(byte_or_eof(port_operand(&args, 0, Current::Input)?.read_u8()?))
+}
+
+pub fn scheme_peek_u8(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to peek-u8 {}", args.len())))
+    }
+    Ok(byte_or_eof(port_operand(&args, 0, Current::Input)?.peek_u8()?))
+}
+
+pub fn scheme_is_u8_ready(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() > 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to u8-ready? {}", args.len())))
+    }
+    Ok(Atom::Bool(port_operand(&args, 0, Current::Input)?.is_ready()?))
+}
+
+// Reads up to `k` bytes into a new bytevector, or returns the eof object if there are none left
+pub fn scheme_read_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to read-bytevector {}", args.len())))
+    }
+    let k = match args[0].as_int_result()? {
+        k if k >= 0 => k as usize,
+        _ => return Err(SchemeError::user("Invalid byte count", vec![args[0].clone()]))
+    };
+    let bytes = port_operand(&args, 1, Current::Input)?.read_bytes(k)?;
+    Ok(bytes.map_or(Atom::Eof, Atom::bytevector))
+}
+
+pub fn scheme_write_u8(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write-u8 {}", args.len())))
+    }
+    let byte = args[0].as_byte_result()?;
+    port_operand(&args, 1, Current::Output)?.write_bytes(&[byte])?;
+    Ok(Atom::Unspecified)
+}
+
+// Writes the bytes of the bytevector from `start` to `end`
+pub fn scheme_write_bytevector(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.is_empty() || args.len() > 4 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to write-bytevector {}", args.len())))
+    }
+    let bytes = args[0].as_bytevector_result()?.borrow().clone();
+    let (start, end) = range_operands(&args, 2, bytes.len())?;
+    port_operand(&args, 1, Current::Output)?.write_bytes(&bytes[start..end])?;
+    Ok(Atom::Unspecified)
+}
+
+fn open_file(args: &[Atom], input: bool, binary: bool) -> Result<Atom, SchemeError> {
+    let path = args[0].as_str_result()?;
+    let port = if input { Port::open_input_file(path, binary)? } else { Port::open_output_file(path, binary)? };
+    Ok(Atom::Port(Rc::new(port)))
+}
+
+pub fn scheme_open_input_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-input-file {}", args.len())))
+    }
+    open_file(&args, true, false)
+}
+
+pub fn scheme_open_binary_input_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-binary-input-file {}", args.len())))
+    }
+    open_file(&args, true, true)
+}
+
+pub fn scheme_open_output_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-output-file {}", args.len())))
+    }
+    open_file(&args, false, false)
+}
+
+pub fn scheme_open_binary_output_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to open-binary-output-file {}", args.len())))
+    }
+    open_file(&args, false, true)
+}
+
+// Calls the procedure with a port on the file, closing it once the procedure returns
+fn call_with_file(args: &[Atom], input: bool, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let func = args[1].as_callable_result()?.clone();
+    let port = open_file(args, input, false)?;
+    let result = execute_fn(func, vec![port.clone()], env)?;
+    port.as_port_result()?.close()?;
+    Ok(result)
+}
+
+pub fn scheme_call_with_input_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to call-with-input-file {}", args.len())))
+    }
+    call_with_file(&args, true, env)
+}
+
+pub fn scheme_call_with_output_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to call-with-output-file {}", args.len())))
+    }
+    call_with_file(&args, false, env)
+}
+
+pub fn scheme_with_input_from_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to with-input-from-file {}", args.len())))
+    }
+    let thunk = args[1].as_callable_result()?.clone();
+    let port = Rc::new(Port::open_input_file(args[0].as_str_result()?, false)?);
+    let previous = port::set_current(Current::Input, port.clone());
+    let result = execute_fn(thunk, vec![], env);
+    port::set_current(Current::Input, previous);
+    port.close()?;
+    result
+}
+
+pub fn scheme_with_output_to_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to with-output-to-file {}", args.len())))
+    }
+    let port = Rc::new(Port::open_output_file(args[0].as_str_result()?, false)?);
+    let result = with_output_to(port.clone(), &args[1], env);
+    port.close()?;
+    result
+}
+
+pub fn scheme_file_exists(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to file-exists? {}", args.len())))
+    }
+    Ok(Atom::Bool(Path::new(args[0].as_str_result()?).exists()))
+}
+
+pub fn scheme_delete_file(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to delete-file {}", args.len())))
+    }
+    let path = Path::new(args[0].as_str_result()?);
+    fs::remove_file(path).map_err(|_| file_error("Unable to delete file", path))?;
+    Ok(Atom::Unspecified)
+}
+
+// The names of the entries in a directory, sorted and without `.` and `..`
+pub fn scheme_directory_files(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to directory-files {}", args.len())))
+    }
+    let path = Path::new(args[0].as_str_result()?);
+    let unreadable = || file_error("Unable to read directory", path);
+    let mut names = vec![];
+    for entry in fs::read_dir(path).map_err(|_| unreadable())? {
+        let entry = entry.map_err(|_| unreadable())?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(Atom::list(names.into_iter().map(|name| Atom::Str(name.into())).collect()))
+}
+
+pub fn scheme_raise(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to raise {}", args.len())))
+    }
+    raise(args[0].clone(), false, env)
+}
+
+pub fn scheme_raise_continuable(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to raise-continuable {}", args.len())))
+    }
+    raise(args[0].clone(), true, env)
+}
+
+// Only used outside the VM, which runs the thunk in a frame of its own
+pub fn scheme_with_exception_handler(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to with-exception-handler {}", args.len())))
+    }
+    let handler = args[0].as_callable_result()?.clone();
+    let thunk = args[1].as_callable_result()?.clone();
+    with_exception_handler(handler, thunk, env)
+}
+
+pub fn scheme_error(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (message, irritants) = args.split_first().ok_or_else(|| SchemeError::arity("error requires a message"))?;
+    let message = message.as_str_result()?;
+    let err = SchemeError::user(message, irritants.to_vec());
+    raise(Atom::error(err), false, env)
+}
+
+pub fn scheme_is_error_object(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to error-object? {}", args.len())))
+    }
+    Ok(Atom::Bool(args[0].as_error_result().is_ok()))
+}
+
+pub fn scheme_error_object_message(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to error-object-message {}", args.len())))
+    }
+    Ok(Atom::Str(args[0].as_error_result()?.message.as_str().into()))
+}
+
+pub fn scheme_error_object_irritants(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to error-object-irritants {}", args.len())))
+    }
+    Ok(Atom::list(args[0].as_error_result()?.irritants.clone()))
+}
+
+pub fn scheme_is_file_error(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to file-error? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0].as_error_result(), Ok(err) if err.kind == ErrorKind::File)))
+}
+
+pub fn scheme_is_read_error(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to read-error? {}", args.len())))
+    }
+    Ok(Atom::Bool(matches!(args[0].as_error_result(), Ok(err) if err.kind == ErrorKind::Read)))
+}
+
+pub fn scheme_command_line(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to command-line {}", args.len())))
+    }
+    Ok(Atom::list(command_line().into_iter().map(|arg| Atom::Str(arg.into())).collect()))
+}
+
+pub fn scheme_exit(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let code = match args.first() {
+        None | Some(&Atom::Bool(true)) => 0,
+        Some(&Atom::Bool(false)) => 1,
+        Some(atom) => atom.as_int_result()?,
+    };
+    Err(SchemeError::exit(code))
+}
+
+// Evaluate a file into the given environment, or the top-level one the caller runs in
+pub fn scheme_load(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let target = match args.len() {
+        1 => env_root(env),
+        2 => args[1].as_environment_result()?.clone(),
+        _ => return Err(SchemeError::arity(&format!("Invalid number of operands to load {}", args.len())))
+    };
+    load_file(args[0].as_str_result()?, target, false)
+}
+
+pub fn scheme_eval(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to eval {}", args.len())))
+    }
+    evaluate(args[0].clone(), args[1].as_environment_result()?.clone())
+}
+
+// A new environment holding only the bindings of the given import sets
+pub fn scheme_environment(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let new_env = env_alloc(Environment::new());
+    import(&args, new_env.clone())?;
+    Ok(Atom::Environment(new_env))
+}
+
+pub fn scheme_interaction_environment(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to interaction-environment {}", args.len())))
+    }
+    Ok(Atom::Environment(env_root(env)))
+}
+
+// The procedures `define-record-type` defines call these with the record type as an operand, so
+// they aren't bound to names of their own
+
+pub fn scheme_make_record(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let (record_type, fields) = args.split_first().ok_or_else(|| SchemeError::arity("Invalid number of operands to make-record 0"))?;
+    Ok(Atom::record(record_type.as_record_type_result()?.clone(), fields.to_vec()))
+}
+
+pub fn scheme_is_record_of(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to record-of? {}", args.len())))
+    }
+    let record_type = args[1].as_record_type_result()?;
+    Ok(Atom::Bool(matches!(args[0], Atom::Record(ref record) if record.is_a(record_type))))
+}
+
+// The record operand at `at`, which must be of the record type after it, and the field index
+// after that
+fn record_field_operands(args: &[Atom], at: usize) -> Result<(&Rc<Record>, usize), SchemeError> {
+    let record_type = args[at + 1].as_record_type_result()?;
+    let record = match args[at] {
+        Atom::Record(ref record) if record.is_a(record_type) => record,
+        _ => return Err(SchemeError::type_error(&format!("Not a {} record", record_type.display_name())))
+    };
+    Ok((record, index_operand(&args[at + 2], record_type.fields.len())?))
+}
+
+pub fn scheme_record_ref(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to record-ref {}", args.len())))
+    }
+    let (record, index) = record_field_operands(&args, 0)?;
+    Ok(record.fields.borrow()[index].clone())
+}
+
+pub fn scheme_record_set(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if args.len() != 4 {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to record-set! {}", args.len())))
+    }
+    let (record, index) = record_field_operands(&args, 0)?;
+    record.fields.borrow_mut()[index] = args[3].clone();
+    Ok(Atom::Unspecified)
+}
+
+pub fn scheme_gc(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to gc {}", args.len())))
+    }
+    Ok(Atom::Int(gc::collect() as i32))
+}
+
+pub fn scheme_gc_stats(env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    if !args.is_empty() {
+        return Err(SchemeError::arity(&format!("Invalid number of operands to gc-stats {}", args.len())))
+    }
+    let stats = gc::stats();
+    let entry = |name: &str, value: usize| Atom::cons(Atom::Symbol(name.into()), Atom::Int(value as i32));
+    Ok(Atom::list(vec![
+        entry("collections", stats.collections),
+        entry("live", stats.live),
+        entry("collected", stats.collected),
+    ]))
+}
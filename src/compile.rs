@@ -0,0 +1,260 @@
+// Compiles analyzed expressions to bytecode for the VM in `vm.rs`
+use std::rc::Rc;
+use analyze::{Expr, GuardExpr, LambdaExpr, Reference, Variable};
+use atom::Atom;
+use environment::GlobalRef;
+use symbol::Symbol;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    // Push `constants[i]`
+    Constant(u32),
+    // Push slot `index` of the frame `depth` levels out
+    Local(u32, u32),
+    // Push slot `index` of a frame kept on the stack
+    Slot(u32),
+    // Push the variable `globals[i]`
+    Global(u32),
+    // Pop a value into a variable, then push the unspecified value
+    DefineLocal(u32, u32),
+    DefineGlobal(Symbol),
+    Jump(u32),
+    // Pop a boolean and jump if it's false
+    JumpIfFalse(u32),
+    Pop,
+    // Push a closure over the current environment for `lambdas[i]`
+    Closure(u32),
+    // Call the procedure below the given number of arguments on the stack
+    Call(u32),
+    // Like `Call`, but replaces the current frame when calling a lambda
+    TailCall(u32),
+    // Call the procedure in the variable `globals[i]` with the given number of arguments on the
+    // stack, which saves pushing the procedure
+    CallGlobal(u32, u32),
+    TailCallGlobal(u32, u32),
+    // Like `CallGlobal` with two arguments, which are read where they are instead of being pushed
+    // first, so that arithmetic on them can be worked out without touching the stack
+    CallGlobal2(u32, Operand, Operand),
+    Return,
+    // Tail call the procedure below the top value with the values it holds
+    ApplyValues,
+    // Run `guards[i]` and push its value
+    Guard(u32),
+    // Evaluate the special form `forms[i]`, which needs the unevaluated data
+    Import(u32),
+    DefineLibrary(u32),
+    Include(u32, bool),
+    // Enter and leave the extent of the `dynamic-wind` running in the current frame
+    Wind,
+    Unwind,
+}
+
+// An argument that can be read without running any code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    // `constants[i]`
+    Constant(u16),
+    // Slot `i` of a frame kept on the stack
+    Slot(u16),
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Atom>,
+    pub lambdas: Vec<Rc<LambdaExpr>>,
+    pub globals: Vec<GlobalRef>,
+    // Guard forms, with their bodies compiled to run in the same frame
+    pub guards: Vec<(Rc<GuardExpr>, Rc<Chunk>)>,
+    pub forms: Vec<Vec<Atom>>,
+    // Whether the chunk is the body of a lambda whose frame is kept on the stack, which its
+    // own variables are read from instead of an environment
+    pub on_stack: bool,
+}
+
+impl Chunk {
+    // Call `f` on everything the chunk references that the cycle collector tracks
+    pub fn for_each_reference(&self, f: &mut dyn FnMut(Reference)) {
+        for constant in &self.constants {
+            f(Reference::Constant(constant));
+        }
+        for lambda in &self.lambdas {
+            f(Reference::Lambda(lambda));
+        }
+        for global in &self.globals {
+            if let Some(value) = global.cached_value() {
+                f(Reference::Constant(&value));
+            }
+        }
+        // The clauses belong to the guard expression, so only the compiled body is visited here
+        for (_, body) in &self.guards {
+            body.for_each_reference(f);
+        }
+    }
+
+    // Drop the values of global variables cached by the chunk
+    pub fn forget_globals(&self) {
+        for global in &self.globals {
+            global.forget();
+        }
+        for (_, body) in &self.guards {
+            body.forget_globals();
+        }
+    }
+}
+
+// Compile a sequence of expressions that returns the value of the last one
+pub fn compile(body: &[Expr]) -> Chunk {
+    let mut chunk = Chunk::default();
+    compile_sequence(&mut chunk, body, true);
+    chunk.code.push(Op::Return);
+    chunk
+}
+
+// The lambda's body as bytecode, compiled the first time it's needed
+pub fn lambda_chunk(lambda: &LambdaExpr) -> Rc<Chunk> {
+    lambda.chunk.get_or_init(|| {
+        let mut chunk = Chunk { on_stack: lambda.on_stack, ..Chunk::default() };
+        compile_sequence(&mut chunk, &lambda.body, true);
+        chunk.code.push(Op::Return);
+        Rc::new(chunk)
+    }).clone()
+}
+
+fn push_constant(chunk: &mut Chunk, atom: Atom) {
+    chunk.constants.push(atom);
+    chunk.code.push(Op::Constant(chunk.constants.len() as u32 - 1));
+}
+
+fn push_form(chunk: &mut Chunk, form: Vec<Atom>) -> u32 {
+    chunk.forms.push(form);
+    chunk.forms.len() as u32 - 1
+}
+
+// Point the jump at `at` to the next instruction
+fn patch_jump(chunk: &mut Chunk, at: usize) {
+    let target = chunk.code.len() as u32;
+    chunk.code[at] = match chunk.code[at] {
+        Op::Jump(_) => Op::Jump(target),
+        Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+        op => op
+    };
+}
+
+fn compile_sequence(chunk: &mut Chunk, body: &[Expr], tail: bool) {
+    match body.split_last() {
+        None => push_constant(chunk, Atom::Unspecified),
+        Some((last, init)) => {
+            for expr in init {
+                compile_expr(chunk, expr, false);
+                chunk.code.push(Op::Pop);
+            }
+            compile_expr(chunk, last, tail);
+        }
+    }
+}
+
+// `expr` as an operand, if it's a constant or one of the slots of a frame on the stack
+fn operand(chunk: &mut Chunk, expr: &Expr) -> Option<Operand> {
+    match *expr {
+        Expr::Constant(ref atom) if chunk.constants.len() <= u16::MAX as usize => {
+            chunk.constants.push(atom.clone());
+            Some(Operand::Constant(chunk.constants.len() as u16 - 1))
+        },
+        Expr::Variable(Variable::Local(0, index)) if chunk.on_stack && index <= u16::MAX as usize => Some(Operand::Slot(index as u16)),
+        _ => None
+    }
+}
+
+// Compile `expr`, which is in tail position if its value is returned from the chunk
+fn compile_expr(chunk: &mut Chunk, expr: &Expr, tail: bool) {
+    match *expr {
+        Expr::Constant(ref atom) => push_constant(chunk, atom.clone()),
+        Expr::Variable(Variable::Local(depth, index)) => chunk.code.push(match (chunk.on_stack, depth) {
+            (true, 0) => Op::Slot(index as u32),
+            // The environment of a frame on the stack is the one the lambda was created in
+            (true, _) => Op::Local(depth as u32 - 1, index as u32),
+            (false, _) => Op::Local(depth as u32, index as u32),
+        }),
+        Expr::Variable(Variable::Global(symbol)) => {
+            chunk.globals.push(GlobalRef::new(symbol));
+            chunk.code.push(Op::Global(chunk.globals.len() as u32 - 1));
+        },
+        Expr::Define(variable, ref value) => {
+            compile_expr(chunk, value, false);
+            chunk.code.push(match variable {
+                Variable::Local(depth, index) => Op::DefineLocal(depth as u32, index as u32),
+                Variable::Global(symbol) => Op::DefineGlobal(symbol),
+            });
+        },
+        Expr::If(ref test, ref consequent, ref alternative) => {
+            compile_expr(chunk, test, false);
+            let to_alternative = chunk.code.len();
+            chunk.code.push(Op::JumpIfFalse(0));
+            compile_expr(chunk, consequent, tail);
+            let to_end = chunk.code.len();
+            chunk.code.push(Op::Jump(0));
+            patch_jump(chunk, to_alternative);
+            match *alternative {
+                Some(ref alternative) => compile_expr(chunk, alternative, tail),
+                None => push_constant(chunk, Atom::Unspecified),
+            }
+            patch_jump(chunk, to_end);
+        },
+        Expr::Lambda(ref lambda) => {
+            chunk.lambdas.push(lambda.clone());
+            chunk.code.push(Op::Closure(chunk.lambdas.len() as u32 - 1));
+        },
+        Expr::Sequence(ref body) => compile_sequence(chunk, body, tail),
+        // The procedure in a global variable is looked up after the arguments are evaluated
+        Expr::Call(ref func, ref args) => {
+            let global = match **func {
+                Expr::Variable(Variable::Global(symbol)) => {
+                    chunk.globals.push(GlobalRef::new(symbol));
+                    Some(chunk.globals.len() as u32 - 1)
+                },
+                _ => {
+                    compile_expr(chunk, func, false);
+                    None
+                }
+            };
+            if let (Some(global), false, [a, b]) = (global, tail, &args[..]) {
+                let constants = chunk.constants.len();
+                if let (Some(a), Some(b)) = (operand(chunk, a), operand(chunk, b)) {
+                    chunk.code.push(Op::CallGlobal2(global, a, b));
+                    return
+                }
+                // Drop any constant added for the first argument
+                chunk.constants.truncate(constants);
+            }
+            for arg in args {
+                compile_expr(chunk, arg, false);
+            }
+            let argc = args.len() as u32;
+            chunk.code.push(match (global, tail) {
+                (Some(global), false) => Op::CallGlobal(global, argc),
+                (Some(global), true) => Op::TailCallGlobal(global, argc),
+                (None, false) => Op::Call(argc),
+                (None, true) => Op::TailCall(argc),
+            });
+        },
+        Expr::Guard(ref guard) => {
+            chunk.guards.push((guard.clone(), Rc::new(compile(&guard.body))));
+            chunk.code.push(Op::Guard(chunk.guards.len() as u32 - 1));
+        },
+        Expr::Import(ref sets) => {
+            let index = push_form(chunk, sets.clone());
+            chunk.code.push(Op::Import(index));
+        },
+        Expr::DefineLibrary(ref name, ref declarations) => {
+            let mut form = vec![name.clone()];
+            form.extend(declarations.iter().cloned());
+            let index = push_form(chunk, form);
+            chunk.code.push(Op::DefineLibrary(index));
+        },
+        Expr::Include(ref files, case_insensitive) => {
+            let index = push_form(chunk, files.clone());
+            chunk.code.push(Op::Include(index, case_insensitive));
+        },
+    }
+}
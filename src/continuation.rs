@@ -0,0 +1,150 @@
+// Continuations and the `dynamic-wind` extents they move between.
+//
+// The VM captures a continuation by copying its stack and frames, along with the installed
+// exception handlers, so it can be resumed any number of times while the `vm::run` that captured
+// it is still running, even after `call/cc` has returned. Calling a continuation unwinds the Rust
+// stack with an `ErrorKind::Escape` error until it reaches that run.
+//
+// `guard`, `with-exception-handler`, `dynamic-wind`, `apply`, `map` and `for-each` run the code
+// they're given in the VM, so continuations captured there can be resumed too. Other builtins
+// that take a procedure, like `sort` or `vector-map`, call it from Rust in a run of its own, and
+// continuations captured by the tree walker or by a run that has since returned can only escape.
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use atom::Atom;
+use environment::{Environment, SchemeFnWrap};
+use error::{ErrorKind, SchemeError};
+use gc::{self, Object};
+use interpreter::{Handler, execute_fn};
+use vm::Frame;
+
+// An active `dynamic-wind`, linked to the one it was entered from
+pub struct Winder {
+    pub before: SchemeFnWrap,
+    pub after: SchemeFnWrap,
+    pub parent: Winders,
+    depth: usize,
+}
+
+pub type Winders = Option<Rc<Winder>>;
+
+pub struct Continuation {
+    // The VM run, or `call/cc` call in the tree walker, that the continuation returns to
+    pub(crate) extent: usize,
+    // Whether that run evaluates a top-level form, whose continuation is the rest of the form
+    pub(crate) top_level: bool,
+    // Saved VM state, with the frame to resume last, and the exception handlers that were
+    // installed. Empty for continuations that only escape
+    pub(crate) stack: Vec<Atom>,
+    pub(crate) frames: Vec<Frame>,
+    pub(crate) handlers: Vec<Handler>,
+    pub(crate) winders: Winders,
+}
+
+thread_local! {
+    // Innermost active `dynamic-wind`
+    static WINDERS: RefCell<Winders> = const { RefCell::new(None) };
+    static NEXT_EXTENT: Cell<usize> = const { Cell::new(0) };
+}
+
+// A new ID for something a continuation can return to
+pub fn next_extent() -> usize {
+    NEXT_EXTENT.with(|next| {
+        let extent = next.get();
+        next.set(extent + 1);
+        extent
+    })
+}
+
+pub fn winders() -> Winders {
+    WINDERS.with(|winders| winders.borrow().clone())
+}
+
+fn set_winders(to: Winders) {
+    WINDERS.with(|winders| *winders.borrow_mut() = to);
+}
+
+pub fn push_winder(before: SchemeFnWrap, after: SchemeFnWrap) {
+    let parent = winders();
+    let depth = parent.as_ref().map_or(0, |parent| parent.depth + 1);
+    set_winders(Some(Rc::new(Winder { before, after, parent, depth })));
+}
+
+pub fn pop_winder() {
+    let parent = winders().and_then(|winder| winder.parent.clone());
+    set_winders(parent);
+}
+
+// Leave the active `dynamic-wind` extents that aren't in `to`, innermost first, then enter the
+// ones in `to` that aren't active, outermost first
+pub fn rewind(to: &Winders, env: &Rc<RefCell<Environment>>) -> Result<(), SchemeError> {
+    let depth = |winders: &Winders| winders.as_ref().map_or(0, |winder| winder.depth + 1);
+    let mut entering = vec![];
+    let mut target = to.clone();
+    loop {
+        let current = winders();
+        let leave = match (&current, &target) {
+            (None, None) => break,
+            (Some(current), Some(target)) if Rc::ptr_eq(current, target) => break,
+            _ => depth(&current) >= depth(&target),
+        };
+        if leave {
+            let winder = current.expect("Leaving an extent with none active");
+            set_winders(winder.parent.clone());
+            execute_fn(winder.after.clone(), vec![], env.clone())?;
+        } else {
+            let winder = target.expect("Entering an extent past the outermost");
+            target = winder.parent.clone();
+            entering.push(winder);
+        }
+    }
+    for winder in entering.into_iter().rev() {
+        execute_fn(winder.before.clone(), vec![], env.clone())?;
+        set_winders(Some(winder));
+    }
+    Ok(())
+}
+
+// The continuation an escape error is returning to, and the values passed to it
+pub fn escaped(err: &SchemeError) -> Option<(&Rc<Continuation>, &[Atom])> {
+    if err.kind != ErrorKind::Escape {
+        return None
+    }
+    match err.irritants.split_first() {
+        Some((Atom::Callable(SchemeFnWrap::Continuation(k)), values)) => Some((k, values)),
+        _ => None
+    }
+}
+
+impl Continuation {
+    // A continuation that returns from the `call/cc` call identified by `extent`
+    pub fn escape_only(extent: usize) -> Continuation {
+        Continuation { extent, top_level: false, stack: vec![], frames: vec![], handlers: vec![], winders: winders() }
+    }
+
+    pub fn is_resumable(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    // Wrap the continuation as a procedure
+    pub fn into_atom(self) -> Atom {
+        let k = Rc::new(self);
+        gc::track(Object::Continuation(k.clone()));
+        Atom::Callable(SchemeFnWrap::Continuation(k))
+    }
+
+    // Call the continuation, returning the error that carries `values` back to it
+    pub fn invoke(k: &Rc<Continuation>, values: Vec<Atom>, env: &Rc<RefCell<Environment>>) -> SchemeError {
+        if let Err(err) = rewind(&k.winders, env) {
+            return err
+        }
+        let mut irritants = vec![Atom::Callable(SchemeFnWrap::Continuation(k.clone()))];
+        irritants.extend(values);
+        SchemeError { irritants, ..SchemeError::new(ErrorKind::Escape, "Continuation called after its extent ended") }
+    }
+
+    // The value `call/cc` returns when the continuation is called with `values`
+    pub fn result(values: &[Atom]) -> Atom {
+        Atom::values(values.to_vec())
+    }
+}
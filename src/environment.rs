@@ -1,129 +1,539 @@
-#![allow(unused_variables)]
-use std::collections::HashMap;
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::mem;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use analyze::LambdaExpr;
 use atom::Atom;
-use interpreter::evaluate;
+use continuation::Continuation;
+use error::{ErrorKind, SchemeError};
+use gc::{self, Object};
+use interpreter::{Engine, engine, execute_sequence};
+use symbol::{Symbol, SymbolMap};
+use vm;
 use builtins::*;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub struct SchemeLambda {
-    pub name: String,
-    pub arg_list: Vec<String>,
-    pub body: Vec<Atom>,
+    pub code: Rc<LambdaExpr>,
+    // Environment the lambda was created in, which its frames extend
+    pub env: Rc<RefCell<Environment>>,
 }
 
-pub type SchemeFn = fn(env: Rc<RefCell<Environment>>, Vec<Atom>) -> Result<Atom, String>;
+pub type SchemeFn = fn(env: Rc<RefCell<Environment>>, Vec<Atom>) -> Result<Atom, SchemeError>;
 
 // Necessary because I could not find a way to implement clone for SchemeFn
 // https://github.com/rust-lang/rust/issues/24000
 pub enum SchemeFnWrap {
     Fn(SchemeFn),
-    Lambda(SchemeLambda)
+    // Shared so that copying a procedure value doesn't copy its body
+    Lambda(Rc<SchemeLambda>),
+    Continuation(Rc<Continuation>),
 }
 
 impl Clone for SchemeFnWrap {
     fn clone(&self) -> SchemeFnWrap {
-        match self {
-            &SchemeFnWrap::Fn(func) => SchemeFnWrap::Fn(func),
-            &SchemeFnWrap::Lambda(ref lambda) => SchemeFnWrap::Lambda(lambda.clone())
+        match *self {
+            SchemeFnWrap::Fn(func) => SchemeFnWrap::Fn(func),
+            SchemeFnWrap::Lambda(ref lambda) => SchemeFnWrap::Lambda(lambda.clone()),
+            SchemeFnWrap::Continuation(ref k) => SchemeFnWrap::Continuation(k.clone()),
         }
     }
 }
 
+// Procedures are equal only to themselves
 impl PartialEq for SchemeFnWrap {
     fn eq(&self, other: &SchemeFnWrap) -> bool {
-        unsafe {
-            mem::transmute::<_, usize>(self) == mem::transmute::<_, usize>(other)
+        match (self, other) {
+            (SchemeFnWrap::Fn(a), SchemeFnWrap::Fn(b)) => *a as usize == *b as usize,
+            (SchemeFnWrap::Lambda(a), SchemeFnWrap::Lambda(b)) => Rc::ptr_eq(a, b),
+            (SchemeFnWrap::Continuation(a), SchemeFnWrap::Continuation(b)) => Rc::ptr_eq(a, b),
+            _ => false
         }
     }
 }
 
+impl SchemeFnWrap {
+    pub fn lambda(lambda: SchemeLambda) -> SchemeFnWrap {
+        let lambda = Rc::new(lambda);
+        gc::track(Object::Lambda(lambda.clone()));
+        SchemeFnWrap::Lambda(lambda)
+    }
+}
+
 impl SchemeLambda {
-    pub fn new(name: String, arg_list: Vec<String>, body: Vec<Atom>) -> SchemeLambda {
-        SchemeLambda {name: name, arg_list: arg_list, body: body}
+    pub fn name(&self) -> &str {
+        &self.code.name
+    }
+
+    pub fn evaluate(&self, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+        self.evaluate_body(args).map_err(|err| err.push_trace(self.name()))
     }
 
-    pub fn evaluate(self, env: Rc<RefCell<Environment>>, args: Vec<Atom>) -> Result<Atom, String> {
-        if args.len() != self.arg_list.len() {
-            return Err(format!("{} requires {} arguments", self.name, self.arg_list.len()))
+    fn evaluate_body(&self, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+        match engine() {
+            Engine::Tree => execute_sequence(&self.code.body, &self.bind(args)?),
+            Engine::Vm => vm::run_lambda(self, args),
         }
-        let new_env = env_spawn_child(env);
-        let mut i = 0;
-        for name in self.arg_list.into_iter() {
-            env_set(new_env.clone(), name, args[i].clone());
-            i += 1;
+    }
+
+    // Fails unless the lambda takes `argc` arguments
+    #[inline]
+    pub fn check_arity(&self, argc: usize) -> Result<(), SchemeError> {
+        let code = &self.code;
+        if argc < code.required || (!code.rest && argc != code.required) {
+            let at_least = if code.rest { "at least " } else { "" };
+            return Err(SchemeError::arity(&format!("{} requires {}{} arguments", code.name, at_least, code.required)))
         }
-        let mut body_iter = self.body.iter();
-        for statement in body_iter.by_ref().take(self.body.len()-1) {
-            try!(evaluate(statement.clone(), new_env.clone()));
+        Ok(())
+    }
+
+    // A new frame for a call with `args`, after checking there are the right number of them
+    pub fn bind(&self, args: Vec<Atom>) -> Result<Rc<RefCell<Environment>>, SchemeError> {
+        self.check_arity(args.len())?;
+        let code = &self.code;
+        let mut values = args;
+        if code.rest {
+            let rest = values.split_off(code.required);
+            values.push(Atom::list(rest));
         }
-        evaluate(body_iter.last().unwrap_or(&Atom::Nil).clone(), new_env)
+        values.resize(code.frame_size, Atom::Unspecified);
+        Ok(env_spawn_frame(self.env.clone(), values))
     }
 }
 
-#[derive(PartialEq)]
+// A library name such as `(scheme base)` and the procedures it exports
+pub type BuiltinLibrary = (&'static [&'static str], &'static [(&'static str, SchemeFn)]);
+
+// Builtin procedures, grouped by the R7RS library that exports them
+pub const BUILTIN_LIBRARIES: &[BuiltinLibrary] = &[
+    (&["scheme", "base"], &[
+        ("+", scheme_add),
+        ("*", scheme_multiply),
+        ("-", scheme_subtract),
+        ("/", scheme_divide),
+        (">", scheme_gt),
+        ("<", scheme_lt),
+        (">=", scheme_ge),
+        ("<=", scheme_le),
+        ("=", scheme_eq),
+        ("floor/", scheme_floor_divide),
+        ("floor-quotient", scheme_floor_quotient),
+        ("floor-remainder", scheme_floor_remainder),
+        ("truncate/", scheme_truncate_divide),
+        ("truncate-quotient", scheme_truncate_quotient),
+        ("truncate-remainder", scheme_truncate_remainder),
+        ("exact-integer-sqrt", scheme_exact_integer_sqrt),
+        ("abs", scheme_abs),
+        ("append", scheme_append),
+        ("apply", scheme_apply),
+        ("call-with-current-continuation", scheme_call_cc),
+        ("call/cc", scheme_call_cc),
+        ("dynamic-wind", scheme_dynamic_wind),
+        ("values", scheme_values),
+        ("call-with-values", scheme_call_with_values),
+        ("car", scheme_car),
+        ("cdr", scheme_cdr),
+        ("cons", scheme_cons),
+        ("set-car!", scheme_set_car),
+        ("set-cdr!", scheme_set_cdr),
+        ("eq?", scheme_is_eq),
+        ("eqv?", scheme_is_eqv),
+        ("equal?", scheme_is_equal),
+        ("list", scheme_list),
+        ("list?", scheme_is_list),
+        ("symbol?", scheme_is_symbol),
+        ("symbol->string", scheme_symbol_to_string),
+        ("string->symbol", scheme_string_to_symbol),
+        ("symbol=?", scheme_is_symbol_eq),
+        ("let", scheme_let),
+        ("null?", scheme_is_null),
+        ("pair?", scheme_is_pair),
+        ("length", scheme_length),
+        ("reverse", scheme_reverse),
+        ("list-copy", scheme_list_copy),
+        ("list-tail", scheme_list_tail),
+        ("list-ref", scheme_list_ref),
+        ("memq", scheme_memq),
+        ("memv", scheme_memv),
+        ("member", scheme_member),
+        ("assq", scheme_assq),
+        ("assv", scheme_assv),
+        ("assoc", scheme_assoc),
+        ("map", scheme_map),
+        ("for-each", scheme_for_each),
+        ("vector?", scheme_is_vector),
+        ("vector", scheme_vector),
+        ("make-vector", scheme_make_vector),
+        ("vector-length", scheme_vector_length),
+        ("vector-ref", scheme_vector_ref),
+        ("vector-set!", scheme_vector_set),
+        ("vector-fill!", scheme_vector_fill),
+        ("vector->list", scheme_vector_to_list),
+        ("list->vector", scheme_list_to_vector),
+        ("vector-map", scheme_vector_map),
+        ("bytevector?", scheme_is_bytevector),
+        ("bytevector", scheme_bytevector),
+        ("make-bytevector", scheme_make_bytevector),
+        ("bytevector-length", scheme_bytevector_length),
+        ("bytevector-u8-ref", scheme_bytevector_u8_ref),
+        ("bytevector-u8-set!", scheme_bytevector_u8_set),
+        ("bytevector-copy", scheme_bytevector_copy),
+        ("utf8->string", scheme_utf8_to_string),
+        ("string->utf8", scheme_string_to_utf8),
+        ("char?", scheme_is_char),
+        ("char->integer", scheme_char_to_integer),
+        ("integer->char", scheme_integer_to_char),
+        ("eof-object", scheme_eof_object),
+        ("eof-object?", scheme_is_eof_object),
+        ("current-input-port", scheme_current_input_port),
+        ("current-output-port", scheme_current_output_port),
+        ("current-error-port", scheme_current_error_port),
+        ("port?", scheme_is_port),
+        ("input-port?", scheme_is_input_port),
+        ("output-port?", scheme_is_output_port),
+        ("input-port-open?", scheme_is_input_port_open),
+        ("output-port-open?", scheme_is_output_port_open),
+        ("close-port", scheme_close_port),
+        ("close-input-port", scheme_close_input_port),
+        ("close-output-port", scheme_close_output_port),
+        ("open-input-string", scheme_open_input_string),
+        ("open-output-string", scheme_open_output_string),
+        ("get-output-string", scheme_get_output_string),
+        ("textual-port?", scheme_is_textual_port),
+        ("binary-port?", scheme_is_binary_port),
+        ("open-input-bytevector", scheme_open_input_bytevector),
+        ("open-output-bytevector", scheme_open_output_bytevector),
+        ("get-output-bytevector", scheme_get_output_bytevector),
+        ("newline", scheme_newline),
+        ("write-char", scheme_write_char),
+        ("write-string", scheme_write_string),
+        ("flush-output-port", scheme_flush_output_port),
+        ("read-char", scheme_read_char),
+        ("peek-char", scheme_peek_char),
+        ("read-line", scheme_read_line),
+        ("char-ready?", scheme_is_char_ready),
+        ("read-u8", scheme_read_u8),
+        ("peek-u8", scheme_peek_u8),
+        ("u8-ready?", scheme_is_u8_ready),
+        ("read-bytevector", scheme_read_bytevector),
+        ("write-u8", scheme_write_u8),
+        ("write-bytevector", scheme_write_bytevector),
+        ("raise", scheme_raise),
+        ("raise-continuable", scheme_raise_continuable),
+        ("with-exception-handler", scheme_with_exception_handler),
+        ("error", scheme_error),
+        ("error-object?", scheme_is_error_object),
+        ("error-object-message", scheme_error_object_message),
+        ("error-object-irritants", scheme_error_object_irritants),
+        ("file-error?", scheme_is_file_error),
+        ("read-error?", scheme_is_read_error),
+    ]),
+    (&["scheme", "file"], &[
+        ("open-input-file", scheme_open_input_file),
+        ("open-binary-input-file", scheme_open_binary_input_file),
+        ("open-output-file", scheme_open_output_file),
+        ("open-binary-output-file", scheme_open_binary_output_file),
+        ("call-with-input-file", scheme_call_with_input_file),
+        ("call-with-output-file", scheme_call_with_output_file),
+        ("with-input-from-file", scheme_with_input_from_file),
+        ("with-output-to-file", scheme_with_output_to_file),
+        ("file-exists?", scheme_file_exists),
+        ("delete-file", scheme_delete_file),
+    ]),
+    (&["scheme", "read"], &[
+        ("read", scheme_read),
+    ]),
+    (&["scheme", "write"], &[
+        ("display", scheme_display),
+        ("write", scheme_write),
+        ("write-shared", scheme_write_shared),
+        ("write-simple", scheme_write_simple),
+    ]),
+    (&["scheme", "process-context"], &[
+        ("command-line", scheme_command_line),
+        ("exit", scheme_exit),
+    ]),
+    (&["scheme", "load"], &[
+        ("load", scheme_load),
+    ]),
+    (&["scheme", "eval"], &[
+        ("eval", scheme_eval),
+        ("environment", scheme_environment),
+    ]),
+    (&["scheme", "repl"], &[
+        ("interaction-environment", scheme_interaction_environment),
+    ]),
+    // Not part of R7RS
+    (&["srfi", "1"], &[
+        ("last-pair", scheme_last_pair),
+        ("iota", scheme_iota),
+        ("filter", scheme_filter),
+        ("remove", scheme_remove),
+        ("partition", scheme_partition),
+        ("fold", scheme_fold),
+        ("fold-right", scheme_fold_right),
+        ("reduce", scheme_reduce),
+        ("append-map", scheme_append_map),
+        ("filter-map", scheme_filter_map),
+        ("find", scheme_find),
+        ("find-tail", scheme_find_tail),
+        ("any", scheme_any),
+        ("every", scheme_every),
+        ("list-index", scheme_list_index),
+        ("delete", scheme_delete),
+        ("delete-duplicates", scheme_delete_duplicates),
+    ]),
+    (&["srfi", "69"], &[
+        ("make-hash-table", scheme_make_hash_table),
+        ("hash-table?", scheme_is_hash_table),
+        ("hash-table-ref", scheme_hash_table_ref),
+        ("hash-table-ref/default", scheme_hash_table_ref_default),
+        ("hash-table-set!", scheme_hash_table_set),
+        ("hash-table-delete!", scheme_hash_table_delete),
+        ("hash-table-exists?", scheme_hash_table_exists),
+        ("hash-table-update!/default", scheme_hash_table_update_default),
+        ("hash-table-size", scheme_hash_table_size),
+        ("hash-table-keys", scheme_hash_table_keys),
+        ("hash-table-values", scheme_hash_table_values),
+        ("hash-table->alist", scheme_hash_table_to_alist),
+        ("hash-table-walk", scheme_hash_table_walk),
+        ("hash", scheme_hash),
+        ("string-hash", scheme_string_hash),
+        ("hash-by-identity", scheme_hash_by_identity),
+    ]),
+    (&["srfi", "95"], &[
+        ("sort", scheme_sort),
+        ("sort!", scheme_sort_in_place),
+        ("merge", scheme_merge),
+    ]),
+    (&["srfi", "132"], &[
+        ("list-sort", scheme_list_sort),
+        ("vector-sort", scheme_vector_sort),
+    ]),
+    (&["rust-scheme", "file"], &[
+        ("directory-files", scheme_directory_files),
+    ]),
+    (&["rust-scheme", "gc"], &[
+        ("gc", scheme_gc),
+        ("gc-stats", scheme_gc_stats),
+    ]),
+    (&["rust-scheme", "port"], &[
+        ("with-output-to-string", scheme_with_output_to_string),
+        ("call-with-output-string", scheme_call_with_output_string),
+    ]),
+    (&["rust-scheme", "pretty-print"], &[
+        ("pretty-print", scheme_pretty_print),
+    ]),
+    (&["rust-scheme", "symbol"], &[
+        ("gensym", scheme_gensym),
+        ("generate-uninterned-symbol", scheme_gensym),
+    ]),
+];
+
+// The name a builtin procedure is bound to, for printing it
+pub fn builtin_name(func: SchemeFn) -> Option<&'static str> {
+    BUILTIN_LIBRARIES.iter()
+        .flat_map(|&(_, procedures)| procedures)
+        .find(|&&(_, builtin)| builtin as usize == func as usize)
+        .map(|&(name, _)| name)
+}
+
 pub struct Environment {
-    parent: Option<Rc<RefCell<Environment>>>,
-    definitions: HashMap<String, Atom>,
+    pub(crate) parent: Option<Rc<RefCell<Environment>>>,
+    // Bindings made at runtime by name, such as top-level definitions
+    pub(crate) definitions: SymbolMap<Atom>,
+    // Slots of a lambda's frame, addressed by the indexes `analyze` assigned
+    pub(crate) values: Vec<Atom>,
 }
 
 // Wrappers to avoid working directly with Rc/RefCell
-pub fn env_get(env: &Rc<RefCell<Environment>>, s: &String) -> Result<Atom, String>  {
-    env.as_ref().borrow().get_symbol(s)
+pub fn env_get<S: Into<Symbol>>(env: &Rc<RefCell<Environment>>, symbol: S) -> Result<Atom, SchemeError>  {
+    env.as_ref().borrow().get_symbol(symbol)
 }
 
-pub fn env_set(env: Rc<RefCell<Environment>>, symbol: String, atom: Atom) {
+pub fn env_set<S: Into<Symbol>>(env: Rc<RefCell<Environment>>, symbol: S, atom: Atom) {
     env.as_ref().borrow_mut().set_symbol(symbol, atom)
 }
 
+// The top-level environment `env` descends from
+pub fn env_root(env: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    let parent = env.borrow().parent.clone();
+    match parent {
+        Some(parent) => env_root(parent),
+        None => env
+    }
+}
+
 pub fn env_spawn_child(env: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
-    Rc::new(RefCell::new(Environment { parent: Some(env), definitions: HashMap::new() }))
+    env_spawn_frame(env, vec![])
+}
+
+pub fn env_spawn_frame(env: Rc<RefCell<Environment>>, values: Vec<Atom>) -> Rc<RefCell<Environment>> {
+    env_alloc(Environment { parent: Some(env), definitions: SymbolMap::default(), values })
+}
+
+// The frame `depth` levels out from `env`
+fn env_frame(env: &Rc<RefCell<Environment>>, depth: usize) -> Rc<RefCell<Environment>> {
+    let mut frame = env.clone();
+    for _ in 0..depth {
+        let parent = frame.borrow().parent.clone().expect("Variable resolved past the outermost frame");
+        frame = parent;
+    }
+    frame
+}
+
+pub fn env_get_local(env: &Rc<RefCell<Environment>>, depth: usize, index: usize) -> Atom {
+    if depth == 0 {
+        return env.borrow().values[index].clone()
+    }
+    env_frame(env, depth).borrow().values[index].clone()
+}
+
+pub fn env_set_local(env: &Rc<RefCell<Environment>>, depth: usize, index: usize, atom: Atom) {
+    env_frame(env, depth).borrow_mut().values[index] = atom;
+}
+
+// The innermost environment from `env` out that has definitions of its own, where looking up
+// a global variable starts to make a difference
+fn env_scope(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    let mut scope = env.clone();
+    loop {
+        let parent = {
+            let env = scope.borrow();
+            match env.parent {
+                Some(ref parent) if env.definitions.is_empty() => parent.clone(),
+                _ => return scope.clone()
+            }
+        };
+        scope = parent;
+    }
+}
+
+// Whether `scope` is the environment `env_scope` finds, without taking references to the
+// environments in between
+fn has_scope(env: &Rc<RefCell<Environment>>, scope: *const RefCell<Environment>) -> bool {
+    let borrowed = env.borrow();
+    match borrowed.parent {
+        Some(ref parent) if borrowed.definitions.is_empty() => has_scope(parent, scope),
+        _ => Rc::as_ptr(env) == scope
+    }
+}
+
+thread_local! {
+    // Counts every definition made, so cached lookups can tell when they might be out of date
+    static DEFINITIONS_MADE: Cell<u64> = const { Cell::new(0) };
+}
+
+// A reference to a global variable that remembers what it found, so running the same code again
+// doesn't look the name up again until something is defined
+pub struct GlobalRef {
+    pub symbol: Symbol,
+    cached: RefCell<Option<Cached>>,
+}
+
+struct Cached {
+    scope: Weak<RefCell<Environment>>,
+    definitions_made: u64,
+    value: Atom,
+}
+
+impl GlobalRef {
+    pub fn new(symbol: Symbol) -> GlobalRef {
+        GlobalRef { symbol, cached: RefCell::new(None) }
+    }
+
+    pub fn get(&self, env: &Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+        self.with(env, |value| Ok(value.clone()))
+    }
+
+    // The result of `f` on the variable's value, which saves copying the value when `f` only needs
+    // part of it
+    #[inline]
+    pub fn with<T, F>(&self, env: &Rc<RefCell<Environment>>, f: F) -> Result<T, SchemeError>
+        where F: FnOnce(&Atom) -> Result<T, SchemeError> {
+        if let Some(ref cached) = *self.cached.borrow() {
+            // Code at the top level, or in a lambda defined there, usually runs right in the scope
+            let scope = Weak::as_ptr(&cached.scope);
+            if cached.definitions_made == DEFINITIONS_MADE.with(Cell::get) && (Rc::as_ptr(env) == scope || has_scope(env, scope)) {
+                return f(&cached.value)
+            }
+        }
+        f(&self.look_up(env)?)
+    }
+
+    #[cold]
+    fn look_up(&self, env: &Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+        let definitions_made = DEFINITIONS_MADE.with(Cell::get);
+        let scope = env_scope(env);
+        let value = env_get(&scope, self.symbol)?;
+        *self.cached.borrow_mut() = Some(Cached { scope: Rc::downgrade(&scope), definitions_made, value: value.clone() });
+        Ok(value)
+    }
+
+    pub fn forget(&self) {
+        *self.cached.borrow_mut() = None;
+    }
+
+    // The value last found, which the cycle collector has to see
+    pub fn cached_value(&self) -> Option<Atom> {
+        self.cached.try_borrow().ok()?.as_ref().map(|cached| cached.value.clone())
+    }
+}
+
+// Move `env` behind an Rc where closures can share it, and where the cycle collector can see it
+pub fn env_alloc(env: Environment) -> Rc<RefCell<Environment>> {
+    let env = Rc::new(RefCell::new(env));
+    gc::track(Object::Env(env.clone()));
+    env
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::new()
+    }
 }
 
 impl Environment {
     pub fn new() -> Environment {
-        Environment { parent: None, definitions: HashMap::new() }
+        Environment { parent: None, definitions: SymbolMap::default(), values: vec![] }
     }
 
     pub fn standard_env() -> Rc<RefCell<Environment>> {
         let mut env = Environment::new();
-        env.set_symbol("+".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_add)));
-        env.set_symbol("*".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_multiply)));
-        env.set_symbol("-".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_subtract)));
-        env.set_symbol("/".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_divide)));
-        env.set_symbol(">".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_gt)));
-        env.set_symbol("<".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_lt)));
-        env.set_symbol(">=".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_ge)));
-        env.set_symbol("<=".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_le)));
-        env.set_symbol("=".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_eq)));
-        env.set_symbol("abs".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_abs)));
-        env.set_symbol("append".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_append)));
-        env.set_symbol("apply".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_apply)));
-        env.set_symbol("begin".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_begin)));
-        env.set_symbol("car".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_car)));
-        env.set_symbol("cdr".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_cdr)));
-        env.set_symbol("cons".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_cons)));
-        env.set_symbol("eq?".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_is_eq)));
-        env.set_symbol("equal?".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_is_equal)));
-        env.set_symbol("list".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_list)));
-        env.set_symbol("list?".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_is_list)));
-        env.set_symbol("let".to_string(), Atom::Callable(SchemeFnWrap::Fn(scheme_let)));
-        Rc::new(RefCell::new(env))
-    }
-
-    pub fn set_symbol(&mut self, symbol: String, atom: Atom) {
-        self.definitions.insert(symbol, atom);
-    }
-
-    pub fn get_symbol(&self, symbol: &String) -> Result<Atom, String> {
-        if let Some(atom) = self.definitions.get(symbol) {
+        for &(_, procedures) in BUILTIN_LIBRARIES {
+            for &(name, func) in procedures {
+                env.set_symbol(name, Atom::Callable(SchemeFnWrap::Fn(func)));
+            }
+        }
+        env_alloc(env)
+    }
+
+    pub fn set_symbol<S: Into<Symbol>>(&mut self, symbol: S, atom: Atom) {
+        self.definitions.insert(symbol.into(), atom);
+        DEFINITIONS_MADE.with(|made| made.set(made.get() + 1));
+    }
+
+    // All symbols visible from this environment, sorted and without duplicates
+    pub fn symbols(&self) -> Vec<String> {
+        // Uninterned symbols can't be typed, so there's no point listing them
+        let mut symbols: Vec<String> = self.definitions.keys()
+            .filter(|key| key.is_interned())
+            .map(|key| key.to_string())
+            .collect();
+        if let Some(ref parent) = self.parent {
+            symbols.extend(parent.borrow().symbols());
+        }
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    pub fn get_symbol<S: Into<Symbol>>(&self, symbol: S) -> Result<Atom, SchemeError> {
+        let symbol = symbol.into();
+        if let Some(atom) = self.definitions.get(&symbol) {
             Ok(atom.clone())
         } else {
             match self.parent {
-                Some(ref parent) => env_get(&parent, symbol),
-                None => Err(format!("Invalid definition {:?}", symbol))
+                Some(ref parent) => env_get(parent, symbol),
+                None => Err(SchemeError::new(ErrorKind::Unbound, &format!("Invalid definition {}", Atom::Symbol(symbol))))
             }
         }
     }
@@ -0,0 +1,148 @@
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std;
+use atom::Atom;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    // Malformed source text
+    Read,
+    // Malformed special form
+    Syntax,
+    Unbound,
+    Type,
+    Arity,
+    // Raised by the Scheme `error` procedure
+    Error,
+    // Raised by `raise` with an arbitrary object, which is the only irritant
+    Raise,
+    // Failure to open, read or delete a file
+    File,
+    // Requested by `exit` with the given status code. Never caught by handlers
+    Exit(i32),
+    // Unwinds to the continuation in the first irritant, carrying the values in the rest.
+    // Never caught by handlers
+    Escape,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub irritants: Vec<Atom>,
+    pub span: Option<Span>,
+    // Source file the span refers to, if the error came from a loaded file
+    pub file: Option<Rc<str>>,
+    // Names of the procedures the error propagated through, innermost first
+    pub trace: Vec<String>,
+    pub continuable: bool,
+    // Lowest index in the handler stack that has already seen this error
+    pub(crate) handled_depth: usize,
+}
+
+impl SchemeError {
+    pub fn new(kind: ErrorKind, message: &str) -> SchemeError {
+        SchemeError {
+            kind,
+            message: message.to_string(),
+            irritants: vec![],
+            span: None,
+            file: None,
+            trace: vec![],
+            continuable: false,
+            handled_depth: usize::MAX,
+        }
+    }
+
+    pub fn read(message: &str, span: Span) -> SchemeError {
+        SchemeError { span: Some(span), ..SchemeError::new(ErrorKind::Read, message) }
+    }
+
+    pub fn syntax(message: &str) -> SchemeError {
+        SchemeError::new(ErrorKind::Syntax, message)
+    }
+
+    pub fn type_error(message: &str) -> SchemeError {
+        SchemeError::new(ErrorKind::Type, message)
+    }
+
+    pub fn arity(message: &str) -> SchemeError {
+        SchemeError::new(ErrorKind::Arity, message)
+    }
+
+    pub fn user(message: &str, irritants: Vec<Atom>) -> SchemeError {
+        SchemeError { irritants, ..SchemeError::new(ErrorKind::Error, message) }
+    }
+
+    pub fn raised(payload: Atom, continuable: bool) -> SchemeError {
+        if let Atom::Error(err) = payload {
+            return SchemeError { continuable, ..(*err).clone() }
+        }
+        SchemeError {
+            irritants: vec![payload],
+            continuable,
+            ..SchemeError::new(ErrorKind::Raise, "Uncaught raise")
+        }
+    }
+
+    pub fn exit(code: i32) -> SchemeError {
+        SchemeError::new(ErrorKind::Exit(code), "exit")
+    }
+
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self.kind, ErrorKind::Exit(_) | ErrorKind::Escape)
+    }
+
+    pub fn with_span(mut self, span: Span) -> SchemeError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    pub fn with_file(mut self, file: &Rc<str>) -> SchemeError {
+        if self.file.is_none() && self.span.is_some() {
+            self.file = Some(file.clone());
+        }
+        self
+    }
+
+    pub fn push_trace(mut self, name: &str) -> SchemeError {
+        self.trace.push(name.to_string());
+        self
+    }
+
+    // The object a handler or `guard` clause receives for this error
+    pub fn condition(&self) -> Atom {
+        match self.kind {
+            ErrorKind::Raise => self.irritants[0].clone(),
+            _ => Atom::error(SchemeError {
+                trace: vec![],
+                handled_depth: usize::MAX,
+                ..self.clone()
+            }),
+        }
+    }
+}
+
+impl Display for SchemeError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        for irritant in &self.irritants {
+            write!(f, " {}", irritant)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+// Formatting Scheme source files.
+//
+// Every top-level form is laid out again the way `pretty` lays out data, but from its tokens
+// rather than the value they read as, so strings, characters, numbers and booleans keep the
+// spelling they were written with. Comments stay on the line they were on, whether they're inside
+// a form or between forms. So does a blank line between groups of forms, though runs of blank
+// lines shrink to one.
+use atom::Atom;
+use error::SchemeError;
+use parse::{Token, datum_len, make_atom, offset_of, read_from_tokens, token_end, tokenize, tokenize_with_datum_comments};
+use print::Node;
+
+// Width formatted code is fitted to
+pub const WIDTH: usize = 79;
+
+#[derive(Clone, Copy)]
+enum Piece<'a> {
+    Comment(&'a str),
+    Form(&'a [Token]),
+}
+
+// The comments in the whitespace-and-comments `gap` between tokens, each with the number of
+// newlines before it, followed by the number of newlines after the last one
+fn comments(gap: &str) -> (Vec<(usize, &str)>, usize) {
+    let mut comments = vec![];
+    let mut newlines = 0;
+    let mut rest = gap;
+    while let Some(c) = rest.chars().next() {
+        let len = if c == ';' {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("#|") {
+            block_comment_len(rest)
+        } else {
+            if c == '\n' {
+                newlines += 1;
+            }
+            rest = &rest[c.len_utf8()..];
+            continue
+        };
+        comments.push((newlines, rest[..len].trim_end()));
+        newlines = 0;
+        rest = &rest[len..];
+    }
+    (comments, newlines)
+}
+
+// The length of the `#| ... |#` comment at the start of `text`, including nested ones
+fn block_comment_len(text: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < text.len() {
+        if text[i..].starts_with("#|") {
+            depth += 1;
+            i += 2;
+        } else if text[i..].starts_with("|#") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i
+            }
+        } else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    text.len()
+}
+
+// The number of tokens in the top-level piece that starts `tokens`. A `#;` datum comment is a
+// piece of its own, apart from the datum after it
+fn piece_len(tokens: &[Token]) -> usize {
+    let len = |tokens: &[Token]| datum_len(tokens).unwrap_or(1);
+    match tokens[0].text.as_ref() {
+        "#;" if !tokens[0].is_string => 1 + len(&tokens[1..]),
+        _ => len(tokens)
+    }
+}
+
+// Makes the node for a form from its tokens and the comments between them
+struct Reader<'a> {
+    source: &'a str,
+    tokens: &'a [Token],
+    next: usize,
+}
+
+impl<'a> Reader<'a> {
+    // The comments between the last token read and the next one
+    fn comments(&self) -> Vec<Node> {
+        let end = token_end(self.source, &self.tokens[self.next - 1]);
+        let start = self.tokens.get(self.next).map_or(self.source.len(), |token| offset_of(self.source, token.span));
+        comments(&self.source[end..start]).0.into_iter()
+            .map(|(newlines, text)| Node::Comment { text: text.to_string(), trailing: newlines == 0 })
+            .collect()
+    }
+
+    fn next_is(&self, text: &str) -> bool {
+        self.tokens.get(self.next).is_some_and(|token| !token.is_string && token.text == text)
+    }
+
+    // The next datum. Comments between a `'` and the datum it quotes are added to `nodes`
+    fn datum(&mut self, nodes: &mut Vec<Node>) -> Node {
+        let tokens = self.tokens;
+        let token = &tokens[self.next];
+        self.next += 1;
+        let text = self.source[offset_of(self.source, token.span)..token_end(self.source, token)].to_string();
+        if token.is_string {
+            return Node::Text(text)
+        }
+        match token.text.as_ref() {
+            "(" | "#(" | "#u8(" => {
+                let mut items = vec![];
+                let mut tail = vec![];
+                let mut dotted = false;
+                loop {
+                    let nodes = if dotted { &mut tail } else { &mut items };
+                    nodes.extend(self.comments());
+                    if self.next >= tokens.len() || self.next_is(")") {
+                        self.next += 1;
+                        break
+                    }
+                    if self.next_is(".") {
+                        self.next += 1;
+                        dotted = true;
+                        continue
+                    }
+                    let node = self.datum(nodes);
+                    nodes.push(node);
+                }
+                Node::List { open: text, items, tail }
+            },
+            "'" | "#;" => {
+                let prefix = if text == "'" { "'" } else { "#;" };
+                nodes.extend(self.comments());
+                Node::Prefixed(prefix, Box::new(self.datum(nodes)))
+            },
+            _ => match make_atom(token) {
+                Ok(Atom::Symbol(_)) => Node::Symbol(text),
+                _ => Node::Text(text),
+            }
+        }
+    }
+}
+
+fn format_form(source: &str, tokens: &[Token]) -> Result<String, SchemeError> {
+    // Reading the form reports any errors in it, unless it's all commented out
+    let text = &source[offset_of(source, tokens[0].span)..token_end(source, &tokens[tokens.len() - 1])];
+    let mut read = tokenize(text)?;
+    if !read.is_empty() {
+        read_from_tokens(&mut read)?;
+    }
+    let mut reader = Reader { source, tokens, next: 0 };
+    let mut nodes = vec![];
+    let form = reader.datum(&mut nodes);
+    nodes.push(form);
+    Ok(nodes.iter().map(|node| node.pretty(WIDTH)).collect::<Vec<_>>().join("\n"))
+}
+
+// The source with each top-level form pretty-printed, ending in a newline unless it's empty
+pub fn format_source(source: &str) -> Result<String, SchemeError> {
+    let tokens = tokenize_with_datum_comments(source)?;
+    // Each piece with the number of newlines before it in the source
+    let mut pieces: Vec<(usize, Piece)> = vec![];
+    let mut end = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let len = piece_len(&tokens[i..]);
+        let form = &tokens[i..i + len];
+        let (gap_comments, newlines) = comments(&source[end..offset_of(source, form[0].span)]);
+        pieces.extend(gap_comments.into_iter().map(|(newlines, text)| (newlines, Piece::Comment(text))));
+        pieces.push((newlines, Piece::Form(form)));
+        end = token_end(source, &form[len - 1]);
+        i += len;
+    }
+    let (gap_comments, _) = comments(&source[end..]);
+    pieces.extend(gap_comments.into_iter().map(|(newlines, text)| (newlines, Piece::Comment(text))));
+
+    let mut out = String::new();
+    for (i, (newlines, piece)) in pieces.into_iter().enumerate() {
+        let text = match piece {
+            Piece::Comment(text) => text.to_string(),
+            Piece::Form(tokens) => format_form(source, tokens)?,
+        };
+        // A comment can follow a form on the same line, but forms always start a new one
+        if i > 0 {
+            match newlines {
+                0 if matches!(piece, Piece::Comment(_)) => out.push(' '),
+                0 | 1 => out.push('\n'),
+                _ => out.push_str("\n\n"),
+            }
+        }
+        out.push_str(&text);
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
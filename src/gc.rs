@@ -0,0 +1,359 @@
+// Cycle collector for values shared through `Rc`.
+//
+// Reference counting frees most values as soon as they're unused, but a closure stored in its
+// own environment or a list made circular with `set-cdr!` keeps itself alive. Every pair,
+// vector, hash table, record, environment, lambda, analyzed lambda body, continuation and error
+// object is tracked here by a weak reference. A collection finds the tracked objects that are
+// only referenced by other tracked objects, and can't be reached from any that are referenced
+// from outside (a Rust variable, an embedder, the handler stack), then empties them so their
+// reference counts drop to zero.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use analyze::{LambdaExpr, Reference};
+use atom::{Atom, Pair};
+use continuation::Continuation;
+use environment::{Environment, SchemeFnWrap, SchemeLambda};
+use error::SchemeError;
+use hash_table::{Equivalence, HashTable};
+use interpreter::Handler;
+use record::Record;
+
+// Collections run automatically once this many objects have been allocated since the last one,
+// or as many as survived it, whichever is larger
+const MIN_THRESHOLD: usize = 10_000;
+
+// A heap object that may be part of a reference cycle
+#[derive(Clone)]
+pub enum Object {
+    Pair(Rc<RefCell<Pair>>),
+    Vector(Rc<RefCell<Vec<Atom>>>),
+    HashTable(Rc<RefCell<HashTable>>),
+    Record(Rc<Record>),
+    Env(Rc<RefCell<Environment>>),
+    Lambda(Rc<SchemeLambda>),
+    // The analyzed code of a lambda, which holds its quoted data
+    Code(Rc<LambdaExpr>),
+    Continuation(Rc<Continuation>),
+    // An error object, which holds its irritants
+    Error(Rc<SchemeError>),
+}
+
+enum Tracked {
+    Pair(Weak<RefCell<Pair>>),
+    Vector(Weak<RefCell<Vec<Atom>>>),
+    HashTable(Weak<RefCell<HashTable>>),
+    Record(Weak<Record>),
+    Env(Weak<RefCell<Environment>>),
+    Lambda(Weak<SchemeLambda>),
+    Code(Weak<LambdaExpr>),
+    Continuation(Weak<Continuation>),
+    Error(Weak<SchemeError>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    // Number of collections run, automatic or requested
+    pub collections: usize,
+    // Tracked objects that are still alive
+    pub live: usize,
+    // Objects freed by collections, over the life of the thread
+    pub collected: usize,
+}
+
+struct Heap {
+    tracked: Vec<Tracked>,
+    allocated: usize,
+    threshold: usize,
+    stats: GcStats,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        tracked: vec![],
+        allocated: 0,
+        threshold: MIN_THRESHOLD,
+        stats: GcStats::default(),
+    });
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match *self {
+            Object::Pair(ref pair) => Rc::as_ptr(pair) as *const (),
+            Object::Vector(ref vector) => Rc::as_ptr(vector) as *const (),
+            Object::HashTable(ref table) => Rc::as_ptr(table) as *const (),
+            Object::Record(ref record) => Rc::as_ptr(record) as *const (),
+            Object::Env(ref env) => Rc::as_ptr(env) as *const (),
+            Object::Lambda(ref lambda) => Rc::as_ptr(lambda) as *const (),
+            Object::Code(ref code) => Rc::as_ptr(code) as *const (),
+            Object::Continuation(ref k) => Rc::as_ptr(k) as *const (),
+            Object::Error(ref err) => Rc::as_ptr(err) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Object::Pair(ref pair) => Rc::strong_count(pair),
+            Object::Vector(ref vector) => Rc::strong_count(vector),
+            Object::HashTable(ref table) => Rc::strong_count(table),
+            Object::Record(ref record) => Rc::strong_count(record),
+            Object::Env(ref env) => Rc::strong_count(env),
+            Object::Lambda(ref lambda) => Rc::strong_count(lambda),
+            Object::Code(ref code) => Rc::strong_count(code),
+            Object::Continuation(ref k) => Rc::strong_count(k),
+            Object::Error(ref err) => Rc::strong_count(err),
+        }
+    }
+
+    fn downgrade(&self) -> Tracked {
+        match *self {
+            Object::Pair(ref pair) => Tracked::Pair(Rc::downgrade(pair)),
+            Object::Vector(ref vector) => Tracked::Vector(Rc::downgrade(vector)),
+            Object::HashTable(ref table) => Tracked::HashTable(Rc::downgrade(table)),
+            Object::Record(ref record) => Tracked::Record(Rc::downgrade(record)),
+            Object::Env(ref env) => Tracked::Env(Rc::downgrade(env)),
+            Object::Lambda(ref lambda) => Tracked::Lambda(Rc::downgrade(lambda)),
+            Object::Code(ref code) => Tracked::Code(Rc::downgrade(code)),
+            Object::Continuation(ref k) => Tracked::Continuation(Rc::downgrade(k)),
+            Object::Error(ref err) => Tracked::Error(Rc::downgrade(err)),
+        }
+    }
+
+    // The objects this one references, or None if it's mutably borrowed and can't be inspected
+    fn children(&self) -> Option<Vec<Object>> {
+        let mut children = vec![];
+        match *self {
+            Object::Pair(ref pair) => {
+                let pair = pair.try_borrow().ok()?;
+                atom_children(&pair.car, &mut children);
+                atom_children(&pair.cdr, &mut children);
+            },
+            Object::Vector(ref vector) => {
+                for atom in vector.try_borrow().ok()?.iter() {
+                    atom_children(atom, &mut children);
+                }
+            },
+            Object::HashTable(ref table) => {
+                let table = table.try_borrow().ok()?;
+                if let Equivalence::Custom { ref equal, ref hash } = table.equivalence {
+                    atom_children(&Atom::Callable(equal.clone()), &mut children);
+                    atom_children(&Atom::Callable(hash.clone()), &mut children);
+                }
+                for (key, value) in table.entries() {
+                    atom_children(key, &mut children);
+                    atom_children(value, &mut children);
+                }
+            },
+            Object::Record(ref record) => {
+                for atom in record.fields.try_borrow().ok()?.iter() {
+                    atom_children(atom, &mut children);
+                }
+            },
+            Object::Env(ref env) => {
+                let env = env.try_borrow().ok()?;
+                if let Some(ref parent) = env.parent {
+                    children.push(Object::Env(parent.clone()));
+                }
+                for atom in env.definitions.values().chain(&env.values) {
+                    atom_children(atom, &mut children);
+                }
+            },
+            Object::Lambda(ref lambda) => {
+                children.push(Object::Env(lambda.env.clone()));
+                children.push(Object::Code(lambda.code.clone()));
+            },
+            Object::Code(ref code) => {
+                let mut add = |reference: Reference| match reference {
+                    Reference::Constant(atom) => atom_children(atom, &mut children),
+                    Reference::Lambda(code) => children.push(Object::Code(code.clone())),
+                };
+                for expr in &code.body {
+                    expr.for_each_reference(&mut add);
+                }
+                // The compiled body holds its own copies of the same references
+                if let Some(chunk) = code.chunk.get() {
+                    chunk.for_each_reference(&mut add);
+                }
+            },
+            Object::Continuation(ref k) => {
+                for atom in &k.stack {
+                    atom_children(atom, &mut children);
+                }
+                for frame in &k.frames {
+                    children.push(Object::Env(frame.env.clone()));
+                    if let Some(ref lambda) = frame.lambda {
+                        children.push(Object::Lambda(lambda.clone()));
+                    }
+                    if let Some(handler) = frame.handler_procedure() {
+                        atom_children(&Atom::Callable(handler.clone()), &mut children);
+                    }
+                }
+                for handler in &k.handlers {
+                    if let Handler::Procedure(ref handler) = *handler {
+                        atom_children(&Atom::Callable(handler.clone()), &mut children);
+                    }
+                }
+            },
+            Object::Error(ref err) => for irritant in &err.irritants {
+                atom_children(irritant, &mut children);
+            },
+        }
+        Some(children)
+    }
+
+    // Drop this object's references. Lambdas, continuations and errors are immutable, but any
+    // cycle through them also passes through an environment, a pair, a vector, a hash table, a
+    // record or the values code has cached. Nothing is freed until `objects` in `collect` is
+    // dropped, since it holds a reference to every tracked object
+    fn clear(&self) {
+        match *self {
+            Object::Pair(ref pair) => {
+                let mut pair = pair.borrow_mut();
+                pair.car = Atom::Nil;
+                pair.cdr = Atom::Nil;
+            },
+            Object::Vector(ref vector) => vector.borrow_mut().clear(),
+            Object::HashTable(ref table) => table.borrow_mut().clear(),
+            Object::Record(ref record) => record.fields.borrow_mut().clear(),
+            Object::Env(ref env) => {
+                let mut env = env.borrow_mut();
+                env.definitions.clear();
+                env.values.clear();
+                env.parent = None;
+            },
+            Object::Code(ref code) => if let Some(chunk) = code.chunk.get() {
+                chunk.forget_globals();
+            },
+            Object::Lambda(_) | Object::Continuation(_) | Object::Error(_) => (),
+        }
+    }
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match *self {
+            Tracked::Pair(ref pair) => pair.upgrade().map(Object::Pair),
+            Tracked::Vector(ref vector) => vector.upgrade().map(Object::Vector),
+            Tracked::HashTable(ref table) => table.upgrade().map(Object::HashTable),
+            Tracked::Record(ref record) => record.upgrade().map(Object::Record),
+            Tracked::Env(ref env) => env.upgrade().map(Object::Env),
+            Tracked::Lambda(ref lambda) => lambda.upgrade().map(Object::Lambda),
+            Tracked::Code(ref code) => code.upgrade().map(Object::Code),
+            Tracked::Continuation(ref k) => k.upgrade().map(Object::Continuation),
+            Tracked::Error(ref err) => err.upgrade().map(Object::Error),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match *self {
+            Tracked::Pair(ref pair) => pair.strong_count() > 0,
+            Tracked::Vector(ref vector) => vector.strong_count() > 0,
+            Tracked::HashTable(ref table) => table.strong_count() > 0,
+            Tracked::Record(ref record) => record.strong_count() > 0,
+            Tracked::Env(ref env) => env.strong_count() > 0,
+            Tracked::Lambda(ref lambda) => lambda.strong_count() > 0,
+            Tracked::Code(ref code) => code.strong_count() > 0,
+            Tracked::Continuation(ref k) => k.strong_count() > 0,
+            Tracked::Error(ref err) => err.strong_count() > 0,
+        }
+    }
+}
+
+fn atom_children(atom: &Atom, children: &mut Vec<Object>) {
+    match *atom {
+        Atom::Pair(ref pair) => children.push(Object::Pair(pair.clone())),
+        Atom::Vector(ref vector) => children.push(Object::Vector(vector.clone())),
+        Atom::HashTable(ref table) => children.push(Object::HashTable(table.clone())),
+        Atom::Record(ref record) => children.push(Object::Record(record.clone())),
+        Atom::Environment(ref env) => children.push(Object::Env(env.clone())),
+        Atom::Callable(SchemeFnWrap::Lambda(ref lambda)) => children.push(Object::Lambda(lambda.clone())),
+        Atom::Callable(SchemeFnWrap::Continuation(ref k)) => children.push(Object::Continuation(k.clone())),
+        Atom::Error(ref err) => children.push(Object::Error(err.clone())),
+        _ => ()
+    }
+}
+
+// Register a newly allocated object with the collector, collecting first if enough have piled up
+pub fn track(object: Object) {
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.push(object.downgrade());
+        heap.allocated += 1;
+        heap.allocated >= heap.threshold
+    });
+    drop(object);
+    if due {
+        collect();
+    }
+}
+
+// Free every tracked object that is only kept alive by a reference cycle, returning how many
+pub fn collect() -> usize {
+    let objects: Vec<Object> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.retain(Tracked::is_alive);
+        heap.tracked.iter().filter_map(Tracked::upgrade).collect()
+    });
+    let index: HashMap<*const (), usize> = objects.iter().enumerate()
+        .map(|(i, object)| (object.address(), i))
+        .collect();
+
+    // Count the references to each object from outside the tracked set, not including `objects`
+    let mut external: Vec<usize> = objects.iter().map(|object| object.strong_count() - 1).collect();
+    let children: Vec<Option<Vec<Object>>> = objects.iter().map(Object::children).collect();
+    for child in children.iter().flatten().flatten() {
+        if let Some(&i) = index.get(&child.address()) {
+            external[i] -= 1;
+        }
+    }
+
+    // Anything reachable from an externally referenced object is alive. Borrowed objects
+    // are in use by the interpreter, so they count as external too
+    let mut reachable = vec![false; objects.len()];
+    let mut pending: Vec<usize> = (0..objects.len())
+        .filter(|&i| external[i] > 0 || children[i].is_none())
+        .collect();
+    while let Some(i) = pending.pop() {
+        if reachable[i] {
+            continue
+        }
+        reachable[i] = true;
+        for child in children[i].iter().flatten() {
+            if let Some(&j) = index.get(&child.address()) {
+                if !reachable[j] {
+                    pending.push(j);
+                }
+            }
+        }
+    }
+    drop(children);
+
+    let mut collected = 0;
+    for (object, _) in objects.iter().zip(&reachable).filter(|&(_, &reachable)| !reachable) {
+        object.clear();
+        collected += 1;
+    }
+    let survivors = objects.len() - collected;
+    drop(objects);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.retain(Tracked::is_alive);
+        heap.allocated = 0;
+        heap.threshold = survivors.max(MIN_THRESHOLD);
+        heap.stats.collections += 1;
+        heap.stats.collected += collected;
+    });
+    collected
+}
+
+pub fn stats() -> GcStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        GcStats {
+            live: heap.tracked.iter().filter(|tracked| tracked.is_alive()).count(),
+            ..heap.stats
+        }
+    })
+}
@@ -0,0 +1,233 @@
+// Hash tables for SRFI 69.
+//
+// Entries are kept in the order they were added, so walking a table is deterministic. Tables
+// keyed by `eq?`, `eqv?` or `equal?` hash keys natively, while a table made with custom equality
+// and hash procedures calls them for every lookup. Those procedures can run any Scheme code, so
+// the table is never borrowed while they're called.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+use atom::Atom;
+use environment::{Environment, SchemeFnWrap};
+use error::SchemeError;
+use interpreter::execute_fn;
+
+// How much of a structure `equal_hash` looks at, so long lists and cycles still hash quickly
+const HASH_BUDGET: usize = 64;
+
+// How a table compares keys
+#[derive(Clone)]
+pub enum Equivalence {
+    // `eq?` and `eqv?`, which are the same for every type this interpreter has
+    Eqv,
+    Equal,
+    Custom { equal: SchemeFnWrap, hash: SchemeFnWrap },
+}
+
+struct Entry {
+    hash: i32,
+    key: Atom,
+    value: Atom,
+}
+
+pub struct HashTable {
+    pub(crate) equivalence: Equivalence,
+    // Entries in the order they were added, with None left behind by deleted ones
+    entries: Vec<Option<Entry>>,
+    // Indexes into `entries` for each key hash
+    buckets: HashMap<i32, Vec<usize>>,
+    len: usize,
+}
+
+impl Equivalence {
+    fn hash(&self, key: &Atom, env: &Rc<RefCell<Environment>>) -> Result<i32, SchemeError> {
+        match *self {
+            Equivalence::Eqv => Ok(eqv_hash(key)),
+            Equivalence::Equal => Ok(equal_hash(key)),
+            Equivalence::Custom { ref hash, .. } => execute_fn(hash.clone(), vec![key.clone()], env.clone())?.as_int_result(),
+        }
+    }
+
+    fn is_equal(&self, a: &Atom, b: &Atom, env: &Rc<RefCell<Environment>>) -> Result<bool, SchemeError> {
+        match *self {
+            Equivalence::Eqv => Ok(a.is_eqv(b)),
+            Equivalence::Equal => Ok(a.is_equal(b)),
+            Equivalence::Custom { ref equal, .. } => {
+                Ok(execute_fn(equal.clone(), vec![a.clone(), b.clone()], env.clone())?.is_truthy())
+            },
+        }
+    }
+}
+
+impl HashTable {
+    pub fn new(equivalence: Equivalence) -> HashTable {
+        HashTable { equivalence, entries: vec![], buckets: HashMap::new(), len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // The keys and values, in the order they were added
+    pub fn entries(&self) -> impl Iterator<Item = (&Atom, &Atom)> {
+        self.entries.iter().flatten().map(|entry| (&entry.key, &entry.value))
+    }
+
+    // Drop every entry and the equivalence procedures, for the cycle collector
+    pub fn clear(&mut self) {
+        self.equivalence = Equivalence::Eqv;
+        self.entries.clear();
+        self.buckets.clear();
+        self.len = 0;
+    }
+
+    fn insert(&mut self, hash: i32, key: Atom, value: Atom) {
+        self.buckets.entry(hash).or_default().push(self.entries.len());
+        self.entries.push(Some(Entry { hash, key, value }));
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        let entry = match self.entries.get_mut(index).and_then(Option::take) {
+            Some(entry) => entry,
+            None => return
+        };
+        if let Some(bucket) = self.buckets.get_mut(&entry.hash) {
+            bucket.retain(|&i| i != index);
+            if bucket.is_empty() {
+                self.buckets.remove(&entry.hash);
+            }
+        }
+        self.len -= 1;
+        // Drop the deleted entries once they outnumber the live ones
+        if self.len < self.entries.len() / 2 {
+            self.entries.retain(Option::is_some);
+            self.buckets.clear();
+            for (i, entry) in self.entries.iter().flatten().enumerate() {
+                self.buckets.entry(entry.hash).or_default().push(i);
+            }
+        }
+    }
+}
+
+// The hash of `key` and the index of its entry, if it has one
+fn find(table: &RefCell<HashTable>, key: &Atom, env: &Rc<RefCell<Environment>>) -> Result<(i32, Option<usize>), SchemeError> {
+    let equivalence = table.borrow().equivalence.clone();
+    let hash = equivalence.hash(key, env)?;
+    let candidates: Vec<(usize, Atom)> = {
+        let table = table.borrow();
+        let bucket = table.buckets.get(&hash).map_or(&[][..], Vec::as_slice);
+        bucket.iter().filter_map(|&i| table.entries[i].as_ref().map(|entry| (i, entry.key.clone()))).collect()
+    };
+    for (i, candidate) in candidates {
+        if equivalence.is_equal(key, &candidate, env)? {
+            return Ok((hash, Some(i)))
+        }
+    }
+    Ok((hash, None))
+}
+
+pub fn get(table: &RefCell<HashTable>, key: &Atom, env: &Rc<RefCell<Environment>>) -> Result<Option<Atom>, SchemeError> {
+    let (_, index) = find(table, key, env)?;
+    let table = table.borrow();
+    Ok(index.and_then(|i| table.entries.get(i)?.as_ref()).map(|entry| entry.value.clone()))
+}
+
+pub fn set(table: &RefCell<HashTable>, key: Atom, value: Atom, env: &Rc<RefCell<Environment>>) -> Result<(), SchemeError> {
+    let (hash, index) = find(table, &key, env)?;
+    let mut table = table.borrow_mut();
+    // The entry may be gone if a custom equality procedure changed the table
+    match index.and_then(|i| table.entries.get_mut(i)?.as_mut()) {
+        Some(entry) => entry.value = value,
+        None => table.insert(hash, key, value),
+    }
+    Ok(())
+}
+
+pub fn delete(table: &RefCell<HashTable>, key: &Atom, env: &Rc<RefCell<Environment>>) -> Result<(), SchemeError> {
+    if let (_, Some(index)) = find(table, key, env)? {
+        table.borrow_mut().remove(index);
+    }
+    Ok(())
+}
+
+fn finish(hasher: DefaultHasher) -> i32 {
+    hasher.finish() as i32 & i32::MAX
+}
+
+// A hash consistent with `eqv?`, which only looks at the identity of shared values
+pub fn eqv_hash(atom: &Atom) -> i32 {
+    let mut hasher = DefaultHasher::new();
+    hash_identity(atom, &mut hasher);
+    finish(hasher)
+}
+
+// A hash consistent with `equal?`
+pub fn equal_hash(atom: &Atom) -> i32 {
+    let mut hasher = DefaultHasher::new();
+    hash_structure(atom, &mut hasher, &mut HASH_BUDGET.clone());
+    finish(hasher)
+}
+
+pub fn string_hash(s: &str) -> i32 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    finish(hasher)
+}
+
+fn hash_identity(atom: &Atom, hasher: &mut DefaultHasher) {
+    mem::discriminant(atom).hash(hasher);
+    match *atom {
+        Atom::Bool(b) => b.hash(hasher),
+        Atom::Int(n) => n.hash(hasher),
+        Atom::Char(c) => c.hash(hasher),
+        Atom::Symbol(symbol) => symbol.hash(hasher),
+        Atom::Str(ref s) => Rc::as_ptr(s).hash(hasher),
+        Atom::Pair(ref pair) => Rc::as_ptr(pair).hash(hasher),
+        Atom::Vector(ref vector) => Rc::as_ptr(vector).hash(hasher),
+        Atom::Bytevector(ref bytes) => Rc::as_ptr(bytes).hash(hasher),
+        Atom::HashTable(ref table) => Rc::as_ptr(table).hash(hasher),
+        Atom::Port(ref port) => Rc::as_ptr(port).hash(hasher),
+        Atom::Record(ref record) => Rc::as_ptr(record).hash(hasher),
+        Atom::RecordType(ref record_type) => Rc::as_ptr(record_type).hash(hasher),
+        Atom::Environment(ref env) => Rc::as_ptr(env).hash(hasher),
+        Atom::Callable(SchemeFnWrap::Fn(func)) => (func as *const ()).hash(hasher),
+        Atom::Callable(SchemeFnWrap::Lambda(ref lambda)) => Rc::as_ptr(lambda).hash(hasher),
+        Atom::Callable(SchemeFnWrap::Continuation(ref k)) => Rc::as_ptr(k).hash(hasher),
+        Atom::Error(ref err) => Rc::as_ptr(err).hash(hasher),
+        Atom::Values(ref values) => Rc::as_ptr(values).hash(hasher),
+        Atom::Nil | Atom::Unspecified | Atom::Eof => (),
+    }
+}
+
+// Hash the contents of strings, pairs and vectors, spending `budget` on each pair and element
+fn hash_structure(atom: &Atom, hasher: &mut DefaultHasher, budget: &mut usize) {
+    if *budget == 0 {
+        return
+    }
+    *budget -= 1;
+    match *atom {
+        Atom::Str(ref s) => s.hash(hasher),
+        Atom::Pair(ref pair) => {
+            let pair = pair.borrow();
+            hash_structure(&pair.car, hasher, budget);
+            hash_structure(&pair.cdr, hasher, budget);
+        },
+        Atom::Vector(ref vector) => {
+            let vector = vector.borrow();
+            vector.len().hash(hasher);
+            for atom in vector.iter() {
+                hash_structure(atom, hasher, budget);
+            }
+        },
+        Atom::Bytevector(ref bytes) => bytes.borrow().hash(hasher),
+        _ => hash_identity(atom, hasher),
+    }
+}
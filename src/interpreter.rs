@@ -1,119 +1,304 @@
-
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::slice;
+use analyze::{Clause, Expr, GuardExpr, Variable, analyze};
 use atom::Atom;
-use environment::{Environment, SchemeFnWrap, SchemeLambda, env_get, env_set};
-use parse::{tokenize, read_from_tokens};
+use compile::compile;
+use continuation::Continuation;
+use environment::{Environment, SchemeFnWrap, SchemeLambda, env_set, env_get, env_get_local, env_set_local, env_spawn_frame};
+use error::{SchemeError, Span};
+use library::{define_library, import};
+use load::include;
+use parse::{Token, tokenize, read_from_tokens};
+use vm;
+
+// How analyzed code is run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    // Walk the `Expr` tree directly
+    Tree,
+    // Compile to bytecode and run it on the VM, which is faster and doesn't recurse in Rust for
+    // calls between lambdas
+    Vm,
+}
 
-pub fn run_program(program: &str) -> Result<String, String> {
+pub fn run_program(program: &str) -> Result<String, SchemeError> {
     let env = Environment::standard_env();
-    let mut tokens = tokenize(program);
-    let ast = try!(read_from_tokens(&mut tokens));
-    let result = try!(evaluate(ast, env));
+    if tokenize(program)?.is_empty() {
+        return Err(SchemeError::read("Empty program", Span { line: 1, column: 0 }))
+    }
+    let result = eval_program(program, env)?;
     Ok(format!("{}", result))
 }
 
-pub fn evaluate<'a>(atom: Atom, env: Rc<RefCell<Environment>>) -> Result<Atom, String> {
-    match atom {
-        Atom::Nil => Ok(atom),
-        Atom::Bool(_) => Ok(atom),
-        Atom::Int(_) => Ok(atom),
-        Atom::Callable(_) => Ok(atom),
-        Atom::Symbol(s) => env_get(&env, &s),
-        Atom::List(list) => {
-            let list_clone = list.clone();
-            let mut list_iter = list.into_iter();
-            let first: Atom = try!(list_iter.next().ok_or("Ill-formed expression".to_string()));
-            if let Some(result) = try!(check_special_forms(first.clone(), list_clone, env.clone())) {
-                return Ok(result)
-            }
+// Read and evaluate every form in `program`, returning the value of the last one
+pub fn eval_program(program: &str, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    eval_tokens(tokenize(program)?, env)
+}
+
+pub fn eval_tokens(mut tokens: Vec<Token>, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let mut result = Atom::Unspecified;
+    while let Some(span) = tokens.first().map(|token| token.span) {
+        let ast = read_from_tokens(&mut tokens)?;
+        result = evaluate(ast, env.clone()).map_err(|err| err.with_span(span))?;
+    }
+    Ok(result)
+}
 
-            let callable = try!(evaluate(first.clone(), env.clone()));
+pub fn evaluate(atom: Atom, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let expr = analyze(&atom)?;
+    match engine() {
+        Engine::Tree => execute(&expr, &env),
+        Engine::Vm => vm::run_top_level(Rc::new(compile(slice::from_ref(&expr))), env),
+    }
+}
+
+// Evaluate each top-level form in order, returning the value of the last one
+pub fn evaluate_sequence(body: &[Atom], env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let mut result = Atom::Unspecified;
+    for statement in body {
+        result = evaluate(statement.clone(), env.clone())?;
+    }
+    Ok(result)
+}
+
+pub fn execute(expr: &Expr, env: &Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    match *expr {
+        Expr::Constant(ref atom) => Ok(atom.clone()),
+        Expr::Variable(Variable::Local(depth, index)) => Ok(env_get_local(env, depth, index)),
+        Expr::Variable(Variable::Global(symbol)) => env_get(env, symbol),
+        Expr::Define(variable, ref value) => {
+            let value = execute(value, env)?;
+            match variable {
+                Variable::Local(depth, index) => env_set_local(env, depth, index, value),
+                Variable::Global(symbol) => env_set(env.clone(), symbol, value),
+            }
+            Ok(Atom::Unspecified)
+        },
+        Expr::If(ref test, ref consequent, ref alternative) => {
+            // Every value but #f counts as true
+            match execute(test, env)? {
+                Atom::Bool(false) => match *alternative {
+                    Some(ref alternative) => execute(alternative, env),
+                    None => Ok(Atom::Unspecified)
+                },
+                _ => execute(consequent, env)
+            }
+        },
+        Expr::Lambda(ref code) => {
+            let lambda = SchemeLambda { code: code.clone(), env: env.clone() };
+            Ok(Atom::Callable(SchemeFnWrap::lambda(lambda)))
+        },
+        Expr::Sequence(ref body) => execute_sequence(body, env),
+        Expr::Call(ref func, ref args) => {
+            let callable = execute(func, env)?;
             if let Atom::Callable(func_wrap) = callable {
-                let mut args: Vec<Atom> = Vec::new();
-                for arg in list_iter {
-                    args.push(try!(evaluate(arg, env.clone())));
+                let mut values: Vec<Atom> = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(execute(arg, env)?);
                 }
-                execute_fn(func_wrap, args, env)
+                execute_fn(func_wrap, values, env.clone())
             } else {
-                Err(format!("Expected function, found {:?}", first))
+                Err(SchemeError::type_error(&format!("Expected function, found {}", callable)))
             }
         },
-        _ => Err(format!("Expected atom {:?}", atom))
+        Expr::Guard(ref guard) => eval_guard(env, guard, || execute_sequence(&guard.body, env)),
+        Expr::Import(ref sets) => import(sets, env.clone()),
+        Expr::DefineLibrary(ref name, ref declarations) => define_library(name, declarations),
+        Expr::Include(ref files, case_insensitive) => include(files, env.clone(), case_insensitive),
     }
 }
 
-pub fn execute_fn(func_wrap: SchemeFnWrap, args: Vec<Atom>, env: Rc<RefCell<Environment>>) -> Result<Atom, String> {
+pub fn execute_fn(func_wrap: SchemeFnWrap, args: Vec<Atom>, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
     match func_wrap {
         SchemeFnWrap::Fn(func) => func(env, args),
-        SchemeFnWrap::Lambda(lambda) => lambda.evaluate(env, args)
+        SchemeFnWrap::Lambda(lambda) => lambda.evaluate(args),
+        SchemeFnWrap::Continuation(k) => Err(Continuation::invoke(&k, args, &env)),
+    }
+}
+
+// Execute each expression in order, returning the value of the last one
+pub fn execute_sequence(body: &[Expr], env: &Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let mut result = Atom::Unspecified;
+    for statement in body {
+        result = execute(statement, env)?;
     }
+    Ok(result)
+}
+
+#[derive(Clone)]
+pub(crate) enum Handler {
+    Procedure(SchemeFnWrap),
+    // Marks a `guard` form, which catches raised objects as they propagate back up
+    Guard,
+}
+
+thread_local! {
+    // Dynamic stack of installed exception handlers, innermost last
+    static HANDLERS: RefCell<Vec<Handler>> = const { RefCell::new(vec![]) };
+    // Arguments returned by `command-line`, starting with the program name
+    static COMMAND_LINE: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    static ENGINE: Cell<Engine> = const { Cell::new(Engine::Vm) };
+}
+
+pub fn set_engine(engine: Engine) {
+    ENGINE.with(|current| current.set(engine));
+}
+
+pub fn engine() -> Engine {
+    ENGINE.with(Cell::get)
+}
+
+pub fn set_command_line(args: Vec<String>) {
+    COMMAND_LINE.with(|command_line| *command_line.borrow_mut() = args);
+}
+
+pub fn command_line() -> Vec<String> {
+    COMMAND_LINE.with(|command_line| command_line.borrow().clone())
 }
 
-fn eval_define(env: Rc<RefCell<Environment>>, arg_list: &Atom, body: Vec<Atom>) -> Result<Atom, String> {
-    match arg_list.clone() {
-        Atom::Symbol(sym) => {
-            println!("Define var");
-            if body.len() == 0 {
-                env_set(env, sym, Atom::Nil)
-            } else if body.len() == 1 {
-                env_set(env, sym, body[0].clone())
+pub(crate) fn handler_depth() -> usize {
+    HANDLERS.with(|handlers| handlers.borrow().len())
+}
+
+pub(crate) fn push_handler(handler: Handler) {
+    HANDLERS.with(|handlers| handlers.borrow_mut().push(handler));
+}
+
+pub(crate) fn truncate_handlers(depth: usize) {
+    HANDLERS.with(|handlers| handlers.borrow_mut().truncate(depth));
+}
+
+// The installed handlers, which continuations save and restore
+pub(crate) fn handlers() -> Vec<Handler> {
+    HANDLERS.with(|handlers| handlers.borrow().clone())
+}
+
+pub(crate) fn set_handlers(to: Vec<Handler>) {
+    HANDLERS.with(|handlers| *handlers.borrow_mut() = to);
+}
+
+// Run `f` with only the handlers below `depth` installed, as R7RS requires for handler calls
+fn with_outer_handlers<F>(depth: usize, f: F) -> Result<Atom, SchemeError>
+        where F: FnOnce() -> Result<Atom, SchemeError> {
+    let saved = HANDLERS.with(|handlers| handlers.borrow_mut().split_off(depth));
+    let result = f();
+    HANDLERS.with(|handlers| {
+        let mut handlers = handlers.borrow_mut();
+        handlers.truncate(depth);
+        handlers.extend(saved);
+    });
+    result.map_err(|mut err| {
+        err.handled_depth = err.handled_depth.min(depth);
+        err
+    })
+}
+
+fn returned_from_handler(obj: Atom, depth: usize) -> SchemeError {
+    let mut err = SchemeError::user("Exception handler returned from non-continuable raise", vec![obj]);
+    err.handled_depth = depth;
+    err
+}
+
+pub fn raise(obj: Atom, continuable: bool, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let depth = handler_depth();
+    let handler = HANDLERS.with(|handlers| match handlers.borrow().last() {
+        Some(Handler::Procedure(handler)) => Some(handler.clone()),
+        _ => None
+    });
+    match handler {
+        Some(handler) => {
+            let result = with_outer_handlers(depth - 1, || execute_fn(handler, vec![obj.clone()], env))?;
+            if continuable {
+                Ok(result)
             } else {
-                return Err("Ill formed define!".to_string())
+                Err(returned_from_handler(obj, depth - 1))
             }
-            Ok(Atom::Nil)
         },
-        Atom::List(args) => {
-            println!("Define function");
-            let mut args_iter = args.into_iter();
-            let name_atom = try!(args_iter.next().ok_or("define needs a name".to_string()));
-            let name = try!(name_atom.as_symbol().ok_or("define name must be a symbol".to_string()));
-            let mut arg_names: Vec<String> = Vec::new();
-            for arg_name_atom in args_iter {
-                arg_names.push(try!(arg_name_atom.as_symbol().ok_or("Non-symbol in defing arg list")).clone());
-            }
-            let lambda = SchemeFnWrap::Lambda(SchemeLambda {name: name.clone(), arg_list: arg_names, body: body});
-            env_set(env, name.clone(), Atom::Callable(lambda));
-            Ok(Atom::Nil)
+        None => {
+            let mut err = SchemeError::raised(obj, continuable);
+            err.handled_depth = depth;
+            Err(err)
         }
-        _ => Err("First argument to define must be a symbol or list".to_string())
     }
 }
 
-fn eval_if(env: Rc<RefCell<Environment>>, condition: &Atom, body: Vec<Atom>) -> Result<Atom, String> {
-    let evaluated_condition = try!(evaluate(condition.clone(), env.clone()));
-    if let Atom::Bool(cond) = evaluated_condition {
-        if cond {
-            evaluate(body[0].clone(), env.clone())
-        } else {
-            if body.len() == 2 {
-                evaluate(body[1].clone(), env)
-            } else {
-                Err("Too many arguments to 'if'".to_string())
-            }
+pub fn with_exception_handler(handler: SchemeFnWrap, thunk: SchemeFnWrap, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    let depth = handler_depth();
+    push_handler(Handler::Procedure(handler.clone()));
+    let result = execute_fn(thunk, vec![], env.clone());
+    truncate_handlers(depth);
+    result.map_err(|err| deliver_to_handler(&handler, depth, err, &env))
+}
+
+// Errors signalled by the interpreter itself never passed through `raise`, so they're delivered
+// to the handler installed at `depth` once the stack has unwound to it. Other errors pass through
+pub(crate) fn deliver_to_handler(handler: &SchemeFnWrap, depth: usize, err: SchemeError, env: &Rc<RefCell<Environment>>) -> SchemeError {
+    if err.handled_depth <= depth || !err.is_catchable() {
+        return err
+    }
+    let condition = err.condition();
+    match with_outer_handlers(depth, || execute_fn(handler.clone(), vec![condition.clone()], env.clone())) {
+        Ok(_) => returned_from_handler(condition, depth),
+        Err(err) => err
+    }
+}
+
+// Execute a guard clause, returning None if its test fails
+fn eval_clause(env: &Rc<RefCell<Environment>>, clause: &Clause) -> Result<Option<Atom>, SchemeError> {
+    let (test, body) = match *clause {
+        Clause::Else(ref body) => return Ok(Some(execute_sequence(body, env)?)),
+        Clause::Test(ref test) => (test, None),
+        Clause::Body(ref test, ref body) => (test, Some(body)),
+        Clause::Arrow(ref test, _) => (test, None),
+    };
+    let result = execute(test, env)?;
+    if !result.is_truthy() {
+        return Ok(None)
+    }
+    if let Clause::Arrow(_, ref receiver) = *clause {
+        let receiver = execute(receiver, env)?;
+        let receiver = receiver.as_callable_result()?.clone();
+        return Ok(Some(execute_fn(receiver, vec![result], env.clone())?))
+    }
+    match body {
+        Some(body) => Ok(Some(execute_sequence(body, env)?)),
+        None => Ok(Some(result))
+    }
+}
+
+// Run a guard form whose body is evaluated by `body`
+pub fn eval_guard<F>(env: &Rc<RefCell<Environment>>, guard: &GuardExpr, body: F) -> Result<Atom, SchemeError>
+        where F: FnOnce() -> Result<Atom, SchemeError> {
+    let depth = handler_depth();
+    push_handler(Handler::Guard);
+    let result = body();
+    truncate_handlers(depth);
+    match result {
+        Ok(atom) => Ok(atom),
+        Err(err) => guard_clauses(env, guard, err)
+    }
+}
+
+// Handle an error that ended the body of a guard form, once the handlers the body installed are
+// gone. Errors that can't be caught, or that no clause accepts, are passed on
+pub(crate) fn guard_clauses(env: &Rc<RefCell<Environment>>, guard: &GuardExpr, err: SchemeError) -> Result<Atom, SchemeError> {
+    if !err.is_catchable() {
+        return Err(err)
+    }
+    let mut values = vec![err.condition()];
+    values.resize(guard.frame_size, Atom::Unspecified);
+    let guard_env = env_spawn_frame(env.clone(), values);
+    for clause in &guard.clauses {
+        if let Some(result) = eval_clause(&guard_env, clause)? {
+            return Ok(result)
         }
+    }
+    // No clause matched, so re-raise to the next handler out
+    let has_handler = HANDLERS.with(|handlers| matches!(handlers.borrow().last(), Some(Handler::Procedure(_))));
+    if has_handler {
+        raise(err.condition(), err.continuable, env.clone())
     } else {
-        Err("First argument to 'if' must be a boolean".to_string())
-    }
-}
-
-fn check_special_forms(atom_sym: Atom, args: Vec<Atom>, env: Rc<RefCell<Environment>>) -> Result<Option<Atom>, String> {
-    if let Some(sym) = atom_sym.as_symbol() {
-        let mut args_iter = args.into_iter();
-        args_iter.next();
-        match sym.as_ref() { // Handle special forms
-            "define" => {
-                let arg_list = try!(args_iter.next().ok_or("define takes 2 arguments".to_string()));
-                let body: Vec<Atom> = args_iter.collect();
-                return Ok(Some(try!(eval_define(env, &arg_list, body))))
-            },
-            "if" => {
-                let condition = try!(args_iter.next().ok_or("if requires at least 2 arguments".to_string()));
-                let body: Vec<Atom> = args_iter.collect();
-                return Ok(Some(try!(eval_if(env, &condition, body))))
-            }
-            _ => return Ok(None)
-        }
+        Err(err)
     }
-    return Ok(None)
 }
@@ -1,6 +1,20 @@
 #![allow(dead_code)]
-mod environment;
+pub mod analyze;
+pub mod compile;
+pub mod continuation;
+pub mod environment;
 mod builtins;
 pub mod interpreter;
-mod parse;
-mod atom;
\ No newline at end of file
+pub mod vm;
+pub mod load;
+pub mod library;
+pub mod error;
+pub mod format;
+pub mod gc;
+pub mod hash_table;
+pub mod parse;
+pub mod port;
+pub mod print;
+pub mod record;
+pub mod symbol;
+pub mod atom;
@@ -0,0 +1,201 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use atom::Atom;
+use environment::{BUILTIN_LIBRARIES, Environment, SchemeFnWrap, env_alloc, env_set};
+use error::SchemeError;
+use interpreter::evaluate_sequence;
+use load::{include, load_file, loading_dir};
+use symbol::Symbol;
+
+struct Library {
+    env: Rc<RefCell<Environment>>,
+    // (internal name, exported name) pairs
+    exports: Vec<(Symbol, Symbol)>,
+}
+
+thread_local! {
+    // Libraries defined so far, keyed by their name parts
+    static LIBRARIES: RefCell<HashMap<Vec<String>, Rc<Library>>> = RefCell::new(HashMap::new());
+    // Directories searched for library files that haven't been defined yet
+    static LIBRARY_PATH: RefCell<Vec<PathBuf>> = const { RefCell::new(vec![]) };
+}
+
+pub fn add_library_path(path: PathBuf) {
+    LIBRARY_PATH.with(|paths| paths.borrow_mut().push(path));
+}
+
+fn library_name(name: &Atom) -> Result<Vec<String>, SchemeError> {
+    let parts = name.to_vec().ok_or_else(|| SchemeError::syntax("Library name must be a list"))?;
+    if parts.is_empty() {
+        return Err(SchemeError::syntax("Library name must not be empty"))
+    }
+    parts.iter().map(|part| match *part {
+        Atom::Symbol(sym) => Ok(sym.to_string()),
+        Atom::Int(n) if n >= 0 => Ok(n.to_string()),
+        _ => Err(SchemeError::syntax("Library name parts must be symbols or integers"))
+    }).collect()
+}
+
+fn display_name(name: &[String]) -> Atom {
+    Atom::Str(format!("({})", name.join(" ")).into())
+}
+
+fn builtin_library(name: &[String]) -> Option<Library> {
+    let &(_, procedures) = BUILTIN_LIBRARIES.iter().find(|&&(lib_name, _)| lib_name == name)?;
+    let mut env = Environment::new();
+    let mut exports = vec![];
+    for &(proc_name, func) in procedures {
+        env.set_symbol(proc_name, Atom::Callable(SchemeFnWrap::Fn(func)));
+        exports.push((Symbol::intern(proc_name), Symbol::intern(proc_name)));
+    }
+    Some(Library { env: env_alloc(env), exports })
+}
+
+// Look for `name` as a file like `dir/scheme/base.sld` in the library search path
+fn find_library_file(name: &[String]) -> Option<PathBuf> {
+    let relative: PathBuf = name.iter().collect();
+    let mut dirs: Vec<PathBuf> = loading_dir().into_iter().collect();
+    LIBRARY_PATH.with(|paths| dirs.extend(paths.borrow().iter().cloned()));
+    dirs.push(PathBuf::from("."));
+    for dir in dirs {
+        for extension in &["sld", "scm"] {
+            let path = dir.join(&relative).with_extension(extension);
+            if path.is_file() {
+                return Some(path)
+            }
+        }
+    }
+    None
+}
+
+fn find_library(name: &[String]) -> Result<Rc<Library>, SchemeError> {
+    if let Some(library) = LIBRARIES.with(|libraries| libraries.borrow().get(name).cloned()) {
+        return Ok(library)
+    }
+    if let Some(library) = builtin_library(name) {
+        let library = Rc::new(library);
+        LIBRARIES.with(|libraries| libraries.borrow_mut().insert(name.to_vec(), library.clone()));
+        return Ok(library)
+    }
+    let unknown = || {
+        let mut err = SchemeError::syntax("Unknown library");
+        err.irritants.push(display_name(name));
+        err
+    };
+    let path = find_library_file(name).ok_or_else(unknown)?;
+    load_file(&path.to_string_lossy(), Environment::standard_env(), false)?;
+    LIBRARIES.with(|libraries| libraries.borrow().get(name).cloned()).ok_or_else(unknown)
+}
+
+fn missing_identifier(name: Symbol) -> SchemeError {
+    let mut err = SchemeError::syntax("Identifier not found in import set");
+    err.irritants.push(Atom::Symbol(name));
+    err
+}
+
+// Resolve an import set such as `(prefix (only (scheme base) car) base:)` to the bindings it names
+fn import_set(set: &Atom) -> Result<Vec<(Symbol, Atom)>, SchemeError> {
+    let parts = set.to_vec().ok_or_else(|| SchemeError::syntax("Import set must be a list"))?;
+    let modifier = parts.first().and_then(|first| first.as_symbol()).map(|sym| sym.name());
+    let inner = || parts.get(1).ok_or_else(|| SchemeError::syntax("Import modifier requires an import set"));
+    let identifiers = || -> Result<Vec<Symbol>, SchemeError> {
+        parts[2..].iter().map(|id| id.as_symbol_result()).collect()
+    };
+    match modifier.as_deref() {
+        Some("only") => {
+            let bindings = import_set(inner()?)?;
+            let mut kept = vec![];
+            for id in identifiers()? {
+                let binding = bindings.iter().find(|&&(name, _)| name == id).ok_or_else(|| missing_identifier(id))?;
+                kept.push(binding.clone());
+            }
+            Ok(kept)
+        },
+        Some("except") => {
+            let mut bindings = import_set(inner()?)?;
+            for id in identifiers()? {
+                let index = bindings.iter().position(|&(name, _)| name == id).ok_or_else(|| missing_identifier(id))?;
+                bindings.remove(index);
+            }
+            Ok(bindings)
+        },
+        Some("prefix") => {
+            let prefix = parts.get(2).ok_or_else(|| SchemeError::syntax("prefix requires an identifier"))?.as_symbol_result()?;
+            Ok(import_set(inner()?)?.into_iter().map(|(name, value)| (Symbol::from(format!("{}{}", prefix, name)), value)).collect())
+        },
+        Some("rename") => {
+            let mut bindings = import_set(inner()?)?;
+            for rename in &parts[2..] {
+                let pair = rename.to_vec().filter(|pair| pair.len() == 2)
+                    .ok_or_else(|| SchemeError::syntax("rename expects (old new) pairs"))?;
+                let old = pair[0].as_symbol_result()?;
+                let new = pair[1].as_symbol_result()?;
+                let binding = bindings.iter_mut().find(|&&mut (name, _)| name == old).ok_or_else(|| missing_identifier(old))?;
+                binding.0 = new;
+            }
+            Ok(bindings)
+        },
+        _ => {
+            let library = find_library(&library_name(set)?)?;
+            let env = library.env.borrow();
+            library.exports.iter().map(|&(internal, external)| {
+                Ok((external, env.get_symbol(internal)?))
+            }).collect()
+        }
+    }
+}
+
+// Evaluate `(import set...)`, binding each imported identifier in `env`
+pub fn import(sets: &[Atom], env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    for set in sets {
+        for (name, value) in import_set(set)? {
+            env_set(env.clone(), name, value);
+        }
+    }
+    Ok(Atom::Unspecified)
+}
+
+fn export_spec(spec: &Atom) -> Result<(Symbol, Symbol), SchemeError> {
+    if let Some(sym) = spec.as_symbol() {
+        return Ok((sym, sym))
+    }
+    match spec.to_vec() {
+        Some(parts) if parts.len() == 3 && parts[0].as_symbol().is_some_and(|sym| sym == "rename") => {
+            Ok((parts[1].as_symbol_result()?, parts[2].as_symbol_result()?))
+        },
+        _ => Err(SchemeError::syntax("export expects identifiers or (rename internal external)"))
+    }
+}
+
+// Evaluate `(define-library name declaration...)` and register the result for later imports
+pub fn define_library(name: &Atom, declarations: &[Atom]) -> Result<Atom, SchemeError> {
+    let name = library_name(name)?;
+    let env = env_alloc(Environment::new());
+    let mut exports = vec![];
+    for declaration in declarations {
+        let parts = declaration.to_vec().ok_or_else(|| SchemeError::syntax("Library declaration must be a list"))?;
+        let (keyword, rest) = parts.split_first().ok_or_else(|| SchemeError::syntax("Empty library declaration"))?;
+        match keyword.as_symbol().map(|sym| sym.name()).as_deref() {
+            Some("export") => for spec in rest {
+                exports.push(export_spec(spec)?);
+            },
+            Some("import") => { import(rest, env.clone())?; },
+            Some("begin") => { evaluate_sequence(rest, env.clone())?; },
+            Some("include") => { include(rest, env.clone(), false)?; },
+            Some("include-ci") => { include(rest, env.clone(), true)?; },
+            _ => return Err(SchemeError::syntax(&format!("Unknown library declaration {}", keyword)))
+        }
+    }
+    for &(internal, _) in &exports {
+        if env.borrow().get_symbol(internal).is_err() {
+            let mut err = SchemeError::syntax("Exported identifier is not defined");
+            err.irritants.push(Atom::Symbol(internal));
+            return Err(err)
+        }
+    }
+    let library = Rc::new(Library { env, exports });
+    LIBRARIES.with(|libraries| libraries.borrow_mut().insert(name, library));
+    Ok(Atom::Unspecified)
+}
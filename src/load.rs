@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use atom::Atom;
+use environment::Environment;
+use error::{ErrorKind, SchemeError};
+use interpreter::eval_tokens;
+use parse::{tokenize, fold_case};
+
+thread_local! {
+    // Files currently being loaded or included, innermost last
+    static LOADING: RefCell<Vec<PathBuf>> = const { RefCell::new(vec![]) };
+}
+
+pub fn file_error(message: &str, path: &Path) -> SchemeError {
+    let mut err = SchemeError::new(ErrorKind::File, message);
+    err.irritants.push(Atom::Str(path.display().to_string().into()));
+    err
+}
+
+// Directory of the file currently being loaded, if any
+pub fn loading_dir() -> Option<PathBuf> {
+    LOADING.with(|loading| {
+        loading.borrow().last().and_then(|file| file.parent().map(|dir| dir.to_path_buf()))
+    })
+}
+
+// Resolve `path` against the directory of the file currently being loaded, if any
+fn resolve_path(path: &str) -> Result<PathBuf, SchemeError> {
+    let path = Path::new(path);
+    let resolved = match loading_dir() {
+        Some(ref dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf()
+    };
+    resolved.canonicalize().map_err(|_| file_error("File not found", &resolved))
+}
+
+// Read and evaluate every form in the file at `path` in `env`, returning the value of the last one.
+// Relative paths are resolved against the file currently being loaded, and a file that
+// (directly or indirectly) loads itself is reported as an error.
+pub fn load_file(path: &str, env: Rc<RefCell<Environment>>, case_insensitive: bool) -> Result<Atom, SchemeError> {
+    let path = resolve_path(path)?;
+    if LOADING.with(|loading| loading.borrow().contains(&path)) {
+        return Err(file_error("Include cycle detected", &path))
+    }
+    let mut program = String::new();
+    File::open(&path).and_then(|mut file| file.read_to_string(&mut program))
+        .map_err(|_| file_error("Unable to read file", &path))?;
+
+    let file_name: Rc<str> = Rc::from(path.display().to_string());
+    LOADING.with(|loading| loading.borrow_mut().push(path));
+    let result = tokenize(&program).and_then(|mut tokens| {
+        if case_insensitive {
+            fold_case(&mut tokens);
+        }
+        eval_tokens(tokens, env)
+    });
+    LOADING.with(|loading| loading.borrow_mut().pop());
+    result.map_err(|err| err.with_file(&file_name))
+}
+
+// Evaluate `include` and `include-ci` forms, which splice each file's contents in place
+pub fn include(files: &[Atom], env: Rc<RefCell<Environment>>, case_insensitive: bool) -> Result<Atom, SchemeError> {
+    if files.is_empty() {
+        return Err(SchemeError::syntax("include requires at least one file name"))
+    }
+    let mut result = Atom::Unspecified;
+    for file in files {
+        let path = file.as_str().ok_or_else(|| SchemeError::syntax("include file names must be strings"))?;
+        result = load_file(path, env.clone(), case_insensitive)?;
+    }
+    Ok(result)
+}
@@ -1,6 +1,15 @@
 use std::io;
 
-use atom::Atom;
+use atom::{Atom, CHAR_NAMES};
+use error::{SchemeError, Span};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub span: Span,
+    // String literals keep their quotes out of `text`, so they must be flagged
+    pub is_string: bool,
+}
 
 pub fn read_stdin_into(input: &mut String) -> &str {
     match io::stdin().read_line(input) {
@@ -12,54 +21,370 @@ pub fn read_stdin_into(input: &mut String) -> &str {
     }
 }
 
-pub fn tokenize(input: &str) -> Vec<String> {
-    let toks: Vec<String> = input.replace("(", "( ").replace(")", " )")
-        .replace("'", "' ").split_whitespace().into_iter()
-        .map(|s| s.trim().to_string()).collect();
-    toks
+// Character stream that keeps track of the current line and column
+#[derive(Clone)]
+struct SourceChars<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    pos: Span,
+}
+
+impl<'a> SourceChars<'a> {
+    fn new(input: &'a str) -> SourceChars<'a> {
+        SourceChars { chars: input.chars().peekable(), pos: Span { line: 1, column: 0 } }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+}
+
+impl<'a> Iterator for SourceChars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 0;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(c)
+    }
+}
+
+fn read_string_literal(chars: &mut SourceChars, span: Span) -> Result<String, SchemeError> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('a') => s.push('\u{7}'),
+                Some('b') => s.push('\u{8}'),
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                // A hex scalar value, ended by a semicolon
+                Some('x') => {
+                    let mut hex = String::new();
+                    while let Some(c) = chars.next().filter(|&c| c != ';') {
+                        hex.push(c);
+                    }
+                    let c = u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32)
+                        .ok_or_else(|| SchemeError::read(&format!("Invalid string escape \\x{}", hex), span))?;
+                    s.push(c);
+                },
+                // A backslash at the end of a line joins it to the next, without the indentation
+                Some(c) if c == '\n' || c.is_whitespace() && skip_to_newline(chars) => {
+                    while chars.peek().is_some_and(|c| c != '\n' && c.is_whitespace()) {
+                        chars.next();
+                    }
+                },
+                Some(c) => s.push(c),
+                None => break,
+            },
+            Some(c) => s.push(c),
+            None => break,
+        }
+    }
+    Err(SchemeError::read("Unterminated string", span))
 }
 
-fn make_atom(input: &str) -> Result<Atom, String> {
-    match input.parse::<i32>() {
+// Skip the whitespace before a newline and the newline itself, returning false and skipping
+// nothing if there's something else first
+fn skip_to_newline(chars: &mut SourceChars) -> bool {
+    let mut ahead = chars.clone();
+    while let Some(c) = ahead.next() {
+        if c == '\n' {
+            *chars = ahead;
+            return true
+        }
+        if !c.is_whitespace() {
+            return false
+        }
+    }
+    false
+}
+
+// Skip to the end of a `#| ... |#` comment, which may contain nested ones
+fn skip_block_comment(chars: &mut SourceChars, span: Span) -> Result<(), SchemeError> {
+    let mut depth = 1;
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('|', Some('#')) => {
+                chars.next();
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(())
+                }
+            },
+            ('#', Some('|')) => {
+                chars.next();
+                depth += 1;
+            },
+            _ => ()
+        }
+    }
+    Err(SchemeError::read("Unterminated block comment", span))
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, SchemeError> {
+    strip_datum_comments(tokenize_with_datum_comments(input)?)
+}
+
+// Tokens including each `#;` datum comment and the datum it comments out, which `tokenize` leaves
+// out. The formatter keeps them
+pub fn tokenize_with_datum_comments(input: &str) -> Result<Vec<Token>, SchemeError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut chars = SourceChars::new(input);
+
+    while let Some(c) = chars.next() {
+        let pos = chars.pos;
+        // The character after `#\` is part of the token, even if it's a delimiter
+        if let Some(ref mut token) = current {
+            if token.text == "#\\" {
+                token.text.push(c);
+                continue
+            }
+            if token.text == "#" && c == '|' {
+                skip_block_comment(&mut chars, token.span)?;
+                current = None;
+                continue
+            }
+            if token.text == "#" && c == ';' {
+                token.text.push(c);
+                tokens.extend(current.take());
+                continue
+            }
+        }
+        let is_delimiter = c.is_whitespace() || c == '(' || c == ')' || c == '\'' || c == '"' || c == ';';
+        if is_delimiter {
+            if let Some(token) = current.take() {
+                // `#(` and `#u8(` open vectors and bytevectors
+                if c == '(' && (token.text == "#" || token.text == "#u8") {
+                    tokens.push(Token { text: format!("{}(", token.text), ..token });
+                    continue
+                }
+                tokens.push(token);
+            }
+        }
+        match c {
+            '(' | ')' | '\'' => tokens.push(Token { text: c.to_string(), span: pos, is_string: false }),
+            '"' => {
+                let text = read_string_literal(&mut chars, pos)?;
+                tokens.push(Token { text, span: pos, is_string: true });
+            },
+            ';' => {
+                while chars.peek().is_some_and(|next| next != '\n') {
+                    chars.next();
+                }
+            },
+            _ if c.is_whitespace() => (),
+            _ => match current {
+                Some(ref mut token) => token.text.push(c),
+                None => current = Some(Token { text: c.to_string(), span: pos, is_string: false }),
+            }
+        }
+    }
+    if let Some(token) = current.take() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// The number of tokens in the datum that starts `tokens`, including any `'` before it and any
+// datum comments before it. None for a `)` or the end of the tokens, where there's no datum
+pub fn datum_len(tokens: &[Token]) -> Option<usize> {
+    let token = tokens.first()?;
+    if token.is_string {
+        return Some(1)
+    }
+    match token.text.as_ref() {
+        ")" => None,
+        "'" => Some(1 + datum_len(&tokens[1..]).unwrap_or(0)),
+        "#;" => {
+            let comment = 1 + datum_len(&tokens[1..]).unwrap_or(0);
+            Some(comment + datum_len(&tokens[comment..]).unwrap_or(0))
+        },
+        "(" | "#(" | "#u8(" => {
+            let mut len = 1;
+            while let Some(item) = datum_len(&tokens[len..]) {
+                len += item;
+            }
+            // Include the `)`, if the list is closed
+            Some((len + 1).min(tokens.len()))
+        },
+        _ => Some(1)
+    }
+}
+
+// Leave out each `#;` and the datum after it
+fn strip_datum_comments(tokens: Vec<Token>) -> Result<Vec<Token>, SchemeError> {
+    let mut kept = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        if token.is_string || token.text != "#;" {
+            kept.push(token.clone());
+            i += 1;
+            continue
+        }
+        match datum_len(&tokens[i + 1..]) {
+            Some(len) => i += 1 + len,
+            None => return Err(SchemeError::read("Expected datum after #;", token.span))
+        }
+    }
+    Ok(kept)
+}
+
+// The byte offset in `text` of the character at `span`
+pub fn offset_of(text: &str, span: Span) -> usize {
+    let line_start: usize = text.split_inclusive('\n').take(span.line as usize - 1).map(str::len).sum();
+    let column: usize = text[line_start..].chars().take(span.column as usize - 1).map(char::len_utf8).sum();
+    line_start + column
+}
+
+// The byte offset in `text` just past `token`
+pub fn token_end(text: &str, token: &Token) -> usize {
+    let start = offset_of(text, token.span);
+    if !token.is_string {
+        return start + token.text.len()
+    }
+    // Find the closing quote, skipping escaped characters
+    let mut chars = text[start..].char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '"' => return start + i + 1,
+            _ => ()
+        }
+    }
+    text.len()
+}
+
+// Lowercase every token except string literals and characters, as `include-ci` and `#!fold-case`
+// require. Character names like `#\SPACE` are still folded
+pub fn fold_case(tokens: &mut [Token]) {
+    let is_char = |token: &Token| token.text.starts_with("#\\") && token.text.chars().count() == 3;
+    for token in tokens.iter_mut().filter(|token| !token.is_string && !is_char(token)) {
+        token.text = token.text.to_lowercase();
+    }
+}
+
+// True if `input` holds no unterminated strings or unclosed parens, so it can be read
+pub fn is_complete(input: &str) -> bool {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(_) => return false
+    };
+    let mut depth = 0;
+    for token in tokens.iter().filter(|token| !token.is_string) {
+        match token.text.as_ref() {
+            "(" | "#(" | "#u8(" => depth += 1,
+            ")" => depth -= 1,
+            _ => ()
+        }
+    }
+    depth <= 0
+}
+
+// The character written `#\name`, as a single character, a name or `x` and a hex code
+fn make_char(name: &str, span: Span) -> Result<Atom, SchemeError> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(Atom::Char(c))
+    }
+    if let Some(&(_, c)) = CHAR_NAMES.iter().find(|&&(known, _)| known == name) {
+        return Ok(Atom::Char(c))
+    }
+    name.strip_prefix('x')
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(::std::char::from_u32)
+        .map(Atom::Char)
+        .ok_or_else(|| SchemeError::read(&format!("Unknown character #\\{}", name), span))
+}
+
+pub fn make_atom(token: &Token) -> Result<Atom, SchemeError> {
+    if token.is_string {
+        return Ok(Atom::Str(token.text.as_str().into()))
+    }
+    if let Some(name) = token.text.strip_prefix("#\\") {
+        return make_char(name, token.span)
+    }
+    match token.text.as_ref() {
+        "#t" | "#true" => return Ok(Atom::Bool(true)),
+        "#f" | "#false" => return Ok(Atom::Bool(false)),
+        _ => ()
+    }
+    match token.text.parse::<i32>() {
         Ok(atom) => Ok(Atom::Int(atom)),
-        Err(_) => Ok(Atom::Symbol(input.to_string())),
+        Err(_) => Ok(Atom::Symbol(token.text.as_str().into())),
     }
 }
 
-fn read_list(mut tokens: &mut Vec<String>) -> Result<Vec<Atom>, String> {
+fn read_list(tokens: &mut Vec<Token>, open: Span) -> Result<Atom, SchemeError> {
     let mut list: Vec<Atom> = Vec::new();
-    let mut compare: String = tokens[0].clone();
-    while compare != ")" {
-        list.push(try!(read_from_tokens(tokens)));
-        if tokens.len() == 0 {
-            return Err("Missing right paren".to_string())
+    let mut tail = Atom::Nil;
+    loop {
+        if tokens.is_empty() {
+            return Err(SchemeError::read("Missing right paren", open))
+        }
+        if tokens[0].is_string {
+            list.push(read_from_tokens(tokens)?);
+            continue
+        }
+        match tokens[0].text.as_ref() {
+            ")" => break,
+            "." => {
+                let dot = tokens.remove(0);
+                if list.is_empty() || tokens.is_empty() {
+                    return Err(SchemeError::read("Ill-formed dotted list", dot.span))
+                }
+                tail = read_from_tokens(tokens)?;
+                if tokens.is_empty() || tokens[0].text != ")" || tokens[0].is_string {
+                    return Err(SchemeError::read("Expected ) after dotted list tail", dot.span))
+                }
+            },
+            _ => list.push(read_from_tokens(tokens)?)
         }
-        compare = tokens[0].clone();
     }
     tokens.remove(0); // Remove ')'
-    Ok(list)
+    Ok(Atom::list_with_tail(list, tail))
 }
 
-pub fn read_from_tokens(mut tokens: &mut Vec<String>) -> Result<Atom, String> {
-    if tokens.len() == 0 {
-        return Err("Empty program".to_string());
+pub fn read_from_tokens(tokens: &mut Vec<Token>) -> Result<Atom, SchemeError> {
+    if tokens.is_empty() {
+        return Err(SchemeError::read("Empty program", Span { line: 1, column: 0 }));
     }
 
     let token = tokens.remove(0);
-    match token.as_ref() {
-        "(" => {
-            Ok(Atom::List(try!(read_list(tokens))))
+    if token.is_string {
+        return make_atom(&token)
+    }
+    match token.text.as_ref() {
+        "(" => read_list(tokens, token.span),
+        "#(" => {
+            let list = read_list(tokens, token.span)?;
+            let atoms = list.to_vec().ok_or_else(|| SchemeError::read("Ill-formed vector", token.span))?;
+            Ok(Atom::vector(atoms))
+        },
+        "#u8(" => {
+            let list = read_list(tokens, token.span)?;
+            let bytes: Option<Vec<u8>> = list.to_vec().and_then(|atoms| {
+                atoms.iter().map(|atom| atom.as_byte_result().ok()).collect()
+            });
+            let bytes = bytes.ok_or_else(|| SchemeError::read("Bytevector elements must be bytes", token.span))?;
+            Ok(Atom::bytevector(bytes))
         },
         "'" => {
-            let t = tokens.remove(0);
-            if t != "(" {
-                return Err("Expected ( after quote".to_string())
+            if tokens.is_empty() {
+                return Err(SchemeError::read("Expected datum after quote", token.span))
             }
-            let mut list = try!(read_list(tokens));
-            list.insert(0, Atom::Symbol("list".to_string()));
-            Ok(Atom::List(list))
+            let datum = read_from_tokens(tokens)?;
+            Ok(Atom::list(vec![Atom::Symbol("quote".into()), datum]))
         },
-        ")" => Err("Unexpected right paren".to_string()),
-        _ => make_atom(token.as_ref()),
+        ")" => Err(SchemeError::read("Unexpected right paren", token.span)),
+        _ => make_atom(&token),
     }
 }
@@ -0,0 +1,388 @@
+// Ports for reading and writing text or bytes.
+//
+// Each thread has a current input, output and error port, which start out as the process's
+// standard streams. A host embedding the interpreter can point them at string ports or at its own
+// readers and writers, to feed Scheme code input or capture what it prints.
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use atom::Atom;
+use error::{ErrorKind, SchemeError};
+use load::file_error;
+use parse::{is_complete, read_from_tokens, token_end, tokenize};
+
+enum Source {
+    // Everything is already in the buffer
+    Str,
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+enum Sink {
+    Stdout,
+    Stderr,
+    Str(String),
+    Bytes(Vec<u8>),
+    Writer(Box<dyn Write>),
+}
+
+enum State {
+    // Input is read a line at a time into `buffer`, and `pos` is how much of it has been used
+    Input { source: Source, buffer: Vec<u8>, pos: usize },
+    Output(Sink),
+    Closed,
+}
+
+pub struct Port {
+    input: bool,
+    binary: bool,
+    state: RefCell<State>,
+}
+
+// The port `current-input-port` and friends return
+#[derive(Clone, Copy)]
+pub enum Current {
+    Input,
+    Output,
+    Error,
+}
+
+thread_local! {
+    static CURRENT: RefCell<[Rc<Port>; 3]> = RefCell::new([
+        Rc::new(Port::stdin()),
+        Rc::new(Port::stdout()),
+        Rc::new(Port::stderr()),
+    ]);
+}
+
+pub fn current(which: Current) -> Rc<Port> {
+    CURRENT.with(|current| current.borrow()[which as usize].clone())
+}
+
+// Replace one of the current ports, returning the one it replaced
+pub fn set_current(which: Current, port: Rc<Port>) -> Rc<Port> {
+    CURRENT.with(|current| ::std::mem::replace(&mut current.borrow_mut()[which as usize], port))
+}
+
+fn io_error(err: io::Error) -> SchemeError {
+    SchemeError::new(ErrorKind::File, &err.to_string())
+}
+
+fn closed() -> SchemeError {
+    SchemeError::user("Port is closed", vec![])
+}
+
+// The length of the UTF-8 sequence starting with `byte`, treating invalid bytes as one character
+fn utf8_width(byte: u8) -> usize {
+    match byte {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1
+    }
+}
+
+impl Port {
+    fn input(source: Source, buffer: Vec<u8>, binary: bool) -> Port {
+        Port { input: true, binary, state: RefCell::new(State::Input { source, buffer, pos: 0 }) }
+    }
+
+    fn output(sink: Sink, binary: bool) -> Port {
+        Port { input: false, binary, state: RefCell::new(State::Output(sink)) }
+    }
+
+    pub fn stdin() -> Port {
+        Port::input(Source::Stdin, vec![], false)
+    }
+
+    pub fn stdout() -> Port {
+        Port::output(Sink::Stdout, false)
+    }
+
+    pub fn stderr() -> Port {
+        Port::output(Sink::Stderr, false)
+    }
+
+    pub fn input_string(s: &str) -> Port {
+        Port::input(Source::Str, s.as_bytes().to_vec(), false)
+    }
+
+    // An output port that collects what's written to it, for `output_string_contents`
+    pub fn output_string() -> Port {
+        Port::output(Sink::Str(String::new()), false)
+    }
+
+    pub fn input_bytes(bytes: Vec<u8>) -> Port {
+        Port::input(Source::Str, bytes, true)
+    }
+
+    // A binary output port that collects what's written to it, for `output_bytes_contents`
+    pub fn output_bytes() -> Port {
+        Port::output(Sink::Bytes(vec![]), true)
+    }
+
+    pub fn reader(reader: Box<dyn BufRead>, binary: bool) -> Port {
+        Port::input(Source::Reader(reader), vec![], binary)
+    }
+
+    pub fn writer(writer: Box<dyn Write>, binary: bool) -> Port {
+        Port::output(Sink::Writer(writer), binary)
+    }
+
+    pub fn open_input_file(path: &str, binary: bool) -> Result<Port, SchemeError> {
+        let file = File::open(path).map_err(|_| file_error("Unable to open file", Path::new(path)))?;
+        Ok(Port::reader(Box::new(BufReader::new(file)), binary))
+    }
+
+    // Open a file for writing, replacing it if it exists
+    pub fn open_output_file(path: &str, binary: bool) -> Result<Port, SchemeError> {
+        let file = File::create(path).map_err(|_| file_error("Unable to open file", Path::new(path)))?;
+        Ok(Port::writer(Box::new(BufWriter::new(file)), binary))
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.input
+    }
+
+    pub fn is_output(&self) -> bool {
+        !self.input
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.borrow(), State::Closed)
+    }
+
+    // Close the port, first writing out anything still buffered
+    pub fn close(&self) -> Result<(), SchemeError> {
+        let state = ::std::mem::replace(&mut *self.state.borrow_mut(), State::Closed);
+        match state {
+            State::Output(Sink::Writer(mut writer)) => writer.flush().map_err(io_error),
+            _ => Ok(())
+        }
+    }
+
+    fn expect_textual(&self) -> Result<(), SchemeError> {
+        if self.binary {
+            return Err(SchemeError::type_error("Not a textual port"))
+        }
+        Ok(())
+    }
+
+    fn expect_binary(&self) -> Result<(), SchemeError> {
+        if !self.binary {
+            return Err(SchemeError::type_error("Not a binary port"))
+        }
+        Ok(())
+    }
+
+    // What's been written to a port made by `output_string`
+    pub fn output_string_contents(&self) -> Option<String> {
+        match *self.state.borrow() {
+            State::Output(Sink::Str(ref s)) => Some(s.clone()),
+            _ => None
+        }
+    }
+
+    // What's been written to a port made by `output_bytes`
+    pub fn output_bytes_contents(&self) -> Option<Vec<u8>> {
+        match *self.state.borrow() {
+            State::Output(Sink::Bytes(ref bytes)) => Some(bytes.clone()),
+            _ => None
+        }
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<(), SchemeError> {
+        self.expect_textual()?;
+        self.write(s.as_bytes())
+    }
+
+    pub fn write_bytes(&self, bytes: &[u8]) -> Result<(), SchemeError> {
+        self.expect_binary()?;
+        self.write(bytes)
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), SchemeError> {
+        let mut state = self.state.borrow_mut();
+        let sink = match *state {
+            State::Output(ref mut sink) => sink,
+            State::Input { .. } => return Err(SchemeError::type_error("Not an output port")),
+            State::Closed => return Err(closed()),
+        };
+        match *sink {
+            // Flushed right away, so output appears before a prompt or a crash
+            Sink::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(bytes).and_then(|_| stdout.flush()).map_err(io_error)
+            },
+            Sink::Stderr => io::stderr().write_all(bytes).map_err(io_error),
+            // Only text is written to string ports
+            Sink::Str(ref mut buffer) => {
+                buffer.push_str(&String::from_utf8_lossy(bytes));
+                Ok(())
+            },
+            Sink::Bytes(ref mut buffer) => {
+                buffer.extend_from_slice(bytes);
+                Ok(())
+            },
+            Sink::Writer(ref mut writer) => writer.write_all(bytes).map_err(io_error),
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), SchemeError> {
+        match *self.state.borrow_mut() {
+            State::Output(Sink::Stdout) => io::stdout().flush().map_err(io_error),
+            State::Output(Sink::Writer(ref mut writer)) => writer.flush().map_err(io_error),
+            State::Output(_) => Ok(()),
+            State::Input { .. } => Err(SchemeError::type_error("Not an output port")),
+            State::Closed => Err(closed()),
+        }
+    }
+
+    // Call `f` with the unread input, reading another line into it whenever `f` returns None.
+    // At the end of the input `f` is told there's no more to come
+    fn with_input<T, F>(&self, mut f: F) -> Result<T, SchemeError>
+        where F: FnMut(&[u8], bool) -> Option<(T, usize)>
+    {
+        let mut state = self.state.borrow_mut();
+        let (source, buffer, pos) = match *state {
+            State::Input { ref mut source, ref mut buffer, ref mut pos } => (source, buffer, pos),
+            State::Output(_) => return Err(SchemeError::type_error("Not an input port")),
+            State::Closed => return Err(closed()),
+        };
+        let mut at_end = false;
+        loop {
+            if let Some((result, used)) = f(&buffer[*pos..], at_end) {
+                *pos += used;
+                return Ok(result)
+            }
+            buffer.drain(..*pos);
+            *pos = 0;
+            let read = match *source {
+                Source::Str => 0,
+                Source::Stdin => io::stdin().lock().read_until(b'\n', buffer).map_err(io_error)?,
+                Source::Reader(ref mut reader) => reader.read_until(b'\n', buffer).map_err(io_error)?,
+            };
+            at_end = read == 0;
+        }
+    }
+
+    pub fn peek_char(&self) -> Result<Option<char>, SchemeError> {
+        self.expect_textual()?;
+        self.with_input(|input, at_end| decode_char(input, at_end).map(|(c, _)| (c, 0)))
+    }
+
+    pub fn read_char(&self) -> Result<Option<char>, SchemeError> {
+        self.expect_textual()?;
+        self.with_input(decode_char)
+    }
+
+    // The next line without its line ending, or None at the end of the input
+    pub fn read_line(&self) -> Result<Option<String>, SchemeError> {
+        self.expect_textual()?;
+        self.with_input(|input, at_end| {
+            let (line, used) = match input.iter().position(|&byte| byte == b'\n') {
+                Some(end) => (&input[..end], end + 1),
+                None if at_end && input.is_empty() => return Some((None, 0)),
+                None if at_end => (input, input.len()),
+                None => return None
+            };
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            Some((Some(String::from_utf8_lossy(line).into_owned()), used))
+        })
+    }
+
+    // True if a character or byte can be read without waiting for more input
+    pub fn is_ready(&self) -> Result<bool, SchemeError> {
+        match *self.state.borrow() {
+            State::Input { ref source, ref buffer, pos } => Ok(pos < buffer.len() || !matches!(*source, Source::Stdin)),
+            State::Output(_) => Err(SchemeError::type_error("Not an input port")),
+            State::Closed => Err(closed()),
+        }
+    }
+
+    // Read the next datum, or the end of file object if there are none left
+    pub fn read(&self) -> Result<Atom, SchemeError> {
+        self.expect_textual()?;
+        self.with_input(|input, at_end| {
+            let text = String::from_utf8_lossy(input);
+            let mut tokens = match tokenize(&text) {
+                Ok(tokens) => tokens,
+                Err(_) if !at_end => return None,
+                Err(err) => return Some((Err(err), input.len())),
+            };
+            // A datum can continue on later lines until its parens are closed
+            let dangling_quote = tokens.last().is_some_and(|token| token.text == "'" && !token.is_string);
+            if !at_end && (tokens.is_empty() || dangling_quote || !is_complete(&text)) {
+                return None
+            }
+            if tokens.is_empty() {
+                return Some((Ok(Atom::Eof), input.len()))
+            }
+            let all_tokens = tokens.clone();
+            let datum = read_from_tokens(&mut tokens);
+            // Leave whatever follows the datum for the next read
+            let used = match all_tokens.get(all_tokens.len() - tokens.len() - 1) {
+                Some(last) if datum.is_ok() => token_end(&text, last),
+                _ => input.len()
+            };
+            Some((datum, used))
+        })?
+    }
+
+    pub fn peek_u8(&self) -> Result<Option<u8>, SchemeError> {
+        self.expect_binary()?;
+        self.with_input(|input, at_end| match input.first() {
+            Some(&byte) => Some((Some(byte), 0)),
+            None if at_end => Some((None, 0)),
+            None => None
+        })
+    }
+
+    pub fn read_u8(&self) -> Result<Option<u8>, SchemeError> {
+        self.expect_binary()?;
+        self.with_input(|input, at_end| match input.first() {
+            Some(&byte) => Some((Some(byte), 1)),
+            None if at_end => Some((None, 0)),
+            None => None
+        })
+    }
+
+    // Up to `k` bytes, fewer only at the end of the input, or None if there are none left
+    pub fn read_bytes(&self, k: usize) -> Result<Option<Vec<u8>>, SchemeError> {
+        self.expect_binary()?;
+        self.with_input(|input, at_end| {
+            if input.len() < k && !at_end {
+                return None
+            }
+            let n = k.min(input.len());
+            if n == 0 && k > 0 {
+                return Some((None, 0))
+            }
+            Some((Some(input[..n].to_vec()), n))
+        })
+    }
+}
+
+// The character at the start of `input` and its length, or None if more input is needed
+fn decode_char(input: &[u8], at_end: bool) -> Option<(Option<char>, usize)> {
+    let first = match input.first() {
+        Some(&first) => first,
+        None if at_end => return Some((None, 0)),
+        None => return None
+    };
+    let width = utf8_width(first);
+    if input.len() < width && !at_end {
+        return None
+    }
+    let width = width.min(input.len());
+    let c = ::std::str::from_utf8(&input[..width]).ok()
+        .and_then(|s| s.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER);
+    Some((Some(c), width))
+}
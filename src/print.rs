@@ -0,0 +1,567 @@
+// Printing values in the external representation R7RS describes.
+//
+// `write` prints data so that `read` gives back an equal value: strings and characters are
+// escaped, and a pair or vector that is part of a cycle gets a datum label, as in `#0=(a . #0#)`,
+// so printing always ends. `display` prints strings and characters as their contents instead.
+// `write-shared` labels every pair and vector that appears more than once, and `write-simple`
+// never labels, so it doesn't terminate on cyclic data.
+//
+// `pretty` lays out the `write` form over several lines when it doesn't fit a given width,
+// indenting the way Scheme code is usually written.
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use atom::{Atom, CHAR_NAMES};
+use environment::{SchemeFnWrap, builtin_name};
+
+// Which pairs and vectors get datum labels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Labels {
+    Never,
+    Cycles,
+    Shared,
+}
+
+// An atom formatted by `write` or `display`, made by the methods below
+pub struct Printed<'a> {
+    atom: &'a Atom,
+    display: bool,
+    labels: Labels,
+    // Whether `(quote x)` is printed as `'x`
+    abbreviate: bool,
+}
+
+impl Atom {
+    // The atom as `display` prints it. Formatting the atom itself prints it as `write` does
+    pub fn display(&self) -> Printed<'_> {
+        Printed { atom: self, display: true, labels: Labels::Cycles, abbreviate: false }
+    }
+
+    pub fn write_shared(&self) -> Printed<'_> {
+        Printed { atom: self, display: false, labels: Labels::Shared, abbreviate: false }
+    }
+
+    pub fn write_simple(&self) -> Printed<'_> {
+        Printed { atom: self, display: false, labels: Labels::Never, abbreviate: false }
+    }
+
+    // The atom as `write` prints it, broken over lines to fit within `width` columns where
+    // possible, and with `(quote x)` written as `'x`. Cyclic data is printed on one line
+    pub fn pretty(&self, width: usize) -> String {
+        if !labeled_objects(self, Labels::Cycles).is_empty() {
+            return self.to_string()
+        }
+        Node::from_atom(self).pretty(width)
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Printed { atom: self, display: false, labels: Labels::Cycles, abbreviate: false }.fmt(f)
+    }
+}
+
+impl<'a> Display for Printed<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut printer = Printer {
+            f,
+            display: self.display,
+            abbreviate: self.abbreviate,
+            labeled: labeled_objects(self.atom, self.labels),
+            numbers: HashMap::new(),
+        };
+        printer.print(self.atom)
+    }
+}
+
+// The address of a pair or vector, which are the only values that can be labeled
+fn address(atom: &Atom) -> Option<*const ()> {
+    match *atom {
+        Atom::Pair(ref pair) => Some(pair.as_ptr() as *const ()),
+        Atom::Vector(ref vector) => Some(vector.as_ptr() as *const ()),
+        _ => None
+    }
+}
+
+fn children(atom: &Atom) -> Vec<Atom> {
+    match *atom {
+        Atom::Pair(ref pair) => {
+            let pair = pair.borrow();
+            vec![pair.car.clone(), pair.cdr.clone()]
+        },
+        Atom::Vector(ref vector) => vector.borrow().clone(),
+        _ => vec![]
+    }
+}
+
+enum Step {
+    Enter(Atom),
+    Leave(*const ()),
+}
+
+// The pairs and vectors in `atom` that need labels. Every cycle passes through one of the objects
+// a depth-first walk finds while it's still inside them, so labeling those is enough to print
+// cyclic data. The walk keeps its own stack, since long lists are as deep as they are long
+fn labeled_objects(atom: &Atom, labels: Labels) -> HashSet<*const ()> {
+    let mut labeled = HashSet::new();
+    if labels == Labels::Never {
+        return labeled
+    }
+    let mut seen = HashSet::new();
+    let mut inside = HashSet::new();
+    let mut steps = vec![Step::Enter(atom.clone())];
+    while let Some(step) = steps.pop() {
+        let atom = match step {
+            Step::Enter(atom) => atom,
+            Step::Leave(address) => {
+                inside.remove(&address);
+                continue
+            }
+        };
+        let address = match address(&atom) {
+            Some(address) => address,
+            None => continue
+        };
+        if inside.contains(&address) || (labels == Labels::Shared && seen.contains(&address)) {
+            labeled.insert(address);
+        }
+        if !seen.insert(address) {
+            continue
+        }
+        inside.insert(address);
+        steps.push(Step::Leave(address));
+        steps.extend(children(&atom).into_iter().rev().map(Step::Enter));
+    }
+    labeled
+}
+
+struct Printer<'f, 'a: 'f> {
+    f: &'f mut Formatter<'a>,
+    display: bool,
+    abbreviate: bool,
+    labeled: HashSet<*const ()>,
+    // The label numbers of the objects printed so far, in the order they were printed
+    numbers: HashMap<*const (), usize>,
+}
+
+impl<'f, 'a> Printer<'f, 'a> {
+    // Print `#n#` if the object has already been printed with a label, or `#n=` if this is the
+    // first time. True if the object itself still needs printing
+    fn label(&mut self, atom: &Atom) -> Result<bool, fmt::Error> {
+        let address = match address(atom) {
+            Some(address) if self.labeled.contains(&address) => address,
+            _ => return Ok(true)
+        };
+        if let Some(&n) = self.numbers.get(&address) {
+            write!(self.f, "#{}#", n)?;
+            return Ok(false)
+        }
+        let n = self.numbers.len();
+        self.numbers.insert(address, n);
+        write!(self.f, "#{}=", n)?;
+        Ok(true)
+    }
+
+    fn is_labeled(&self, atom: &Atom) -> bool {
+        address(atom).is_some_and(|address| self.labeled.contains(&address))
+    }
+
+    fn print(&mut self, atom: &Atom) -> fmt::Result {
+        if !self.label(atom)? {
+            return Ok(())
+        }
+        match *atom {
+            Atom::Bool(true) => write!(self.f, "#t"),
+            Atom::Bool(false) => write!(self.f, "#f"),
+            Atom::Int(n) => write!(self.f, "{}", n),
+            Atom::Char(c) if self.display => write!(self.f, "{}", c),
+            Atom::Char(c) => write_char(self.f, c),
+            Atom::Symbol(s) if self.display => write!(self.f, "{}", s),
+            Atom::Symbol(s) => write_symbol(self.f, &s.name()),
+            Atom::Str(ref s) if self.display => write!(self.f, "{}", s),
+            Atom::Str(ref s) => write_escaped(self.f, s),
+            Atom::Pair(_) if self.abbreviate && quoted(atom).is_some() => {
+                write!(self.f, "'")?;
+                self.print(&quoted(atom).unwrap_or(Atom::Nil))
+            },
+            Atom::Pair(ref pair) => {
+                let (car, mut rest) = {
+                    let pair = pair.borrow();
+                    (pair.car.clone(), pair.cdr.clone())
+                };
+                write!(self.f, "(")?;
+                self.print(&car)?;
+                // The rest of the list is printed in place, unless it needs its own label
+                loop {
+                    rest = match rest {
+                        Atom::Nil => break,
+                        Atom::Pair(ref pair) if !self.is_labeled(&rest) => {
+                            let pair = pair.borrow();
+                            write!(self.f, " ")?;
+                            self.print(&pair.car)?;
+                            pair.cdr.clone()
+                        },
+                        ref tail => {
+                            write!(self.f, " . ")?;
+                            self.print(tail)?;
+                            break
+                        }
+                    }
+                }
+                write!(self.f, ")")
+            },
+            Atom::Vector(ref vector) => {
+                let atoms = vector.borrow().clone();
+                write!(self.f, "#(")?;
+                for (i, atom) in atoms.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, " ")?;
+                    }
+                    self.print(atom)?;
+                }
+                write!(self.f, ")")
+            },
+            Atom::Bytevector(ref bytes) => {
+                write!(self.f, "#u8(")?;
+                for (i, byte) in bytes.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, " ")?;
+                    }
+                    write!(self.f, "{}", byte)?;
+                }
+                write!(self.f, ")")
+            },
+            Atom::HashTable(_) => write!(self.f, "#<hash-table>"),
+            Atom::Port(ref port) if port.is_input() => write!(self.f, "#<input-port>"),
+            Atom::Port(_) => write!(self.f, "#<output-port>"),
+            Atom::Record(ref record) => write!(self.f, "#<record {}>", record.record_type.display_name()),
+            Atom::RecordType(ref record_type) => write!(self.f, "#<record-type {}>", record_type.display_name()),
+            Atom::Environment(_) => write!(self.f, "#<environment>"),
+            Atom::Callable(ref func) => write_procedure(self.f, func),
+            Atom::Error(ref err) => write!(self.f, "#<error {}>", err),
+            Atom::Nil => write!(self.f, "()"),
+            Atom::Unspecified => write!(self.f, "#<unspecified>"),
+            Atom::Eof => write!(self.f, "#<eof>"),
+            Atom::Values(ref values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, " ")?;
+                    }
+                    self.print(value)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+// The datum in a `(quote datum)` form
+fn quoted(atom: &Atom) -> Option<Atom> {
+    let pair = match *atom {
+        Atom::Pair(ref pair) => pair.borrow(),
+        _ => return None
+    };
+    if pair.car != Atom::Symbol("quote".into()) {
+        return None
+    }
+    match pair.cdr {
+        Atom::Pair(ref rest) if rest.borrow().cdr == Atom::Nil => Some(rest.borrow().car.clone()),
+        _ => None
+    }
+}
+
+fn write_char(f: &mut Formatter, c: char) -> fmt::Result {
+    match CHAR_NAMES.iter().find(|&&(_, named)| named == c) {
+        Some(&(name, _)) => write!(f, "#\\{}", name),
+        None => write!(f, "#\\{}", c),
+    }
+}
+
+pub fn write_escaped(f: &mut Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// True if `name` reads back as the symbol with that name
+fn is_identifier(name: &str) -> bool {
+    let special = |c: char| c.is_whitespace() || c.is_control() || "()'\";|".contains(c);
+    !name.is_empty() && name != "." && !name.starts_with('#') && !name.contains(special) && name.parse::<i32>().is_err()
+}
+
+// Symbols that aren't identifiers are written between bars, like `|a b|`
+fn write_symbol(f: &mut Formatter, name: &str) -> fmt::Result {
+    if is_identifier(name) {
+        return write!(f, "{}", name)
+    }
+    write!(f, "|")?;
+    for c in name.chars() {
+        match c {
+            '|' => write!(f, "\\|")?,
+            '\\' => write!(f, "\\\\")?,
+            c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "|")
+}
+
+// Procedures print with the name they were defined or bound with, if they have one
+fn write_procedure(f: &mut Formatter, func: &SchemeFnWrap) -> fmt::Result {
+    let name = match *func {
+        SchemeFnWrap::Fn(func) => builtin_name(func),
+        SchemeFnWrap::Lambda(ref lambda) if lambda.code.name != "lambda" => Some(lambda.code.name.as_str()),
+        SchemeFnWrap::Lambda(_) => None,
+        SchemeFnWrap::Continuation(_) => return write!(f, "#<continuation>"),
+    };
+    match name {
+        Some(name) => write!(f, "#<procedure {}>", name),
+        None => write!(f, "#<procedure>"),
+    }
+}
+
+// How many operands of each special form go on the first line, before the indented body
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("begin", 0),
+    ("case", 1),
+    ("define", 1),
+    ("define-record-type", 2),
+    ("define-syntax", 1),
+    ("define-values", 1),
+    ("do", 2),
+    ("guard", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("let*-values", 1),
+    ("let-syntax", 1),
+    ("let-values", 1),
+    ("letrec", 1),
+    ("letrec*", 1),
+    ("parameterize", 1),
+    ("receive", 2),
+    ("syntax-rules", 1),
+    ("unless", 1),
+    ("when", 1),
+];
+
+// Indentation of the body of a special form, relative to its open paren
+const BODY_INDENT: usize = 2;
+
+// The atom on one line, as `pretty` prints it
+fn flat(atom: &Atom) -> String {
+    Printed { atom, display: false, labels: Labels::Never, abbreviate: true }.to_string()
+}
+
+// A datum to lay out, with each number, string, character and so on already written as text.
+// `pretty` makes one from an atom, and `fmt` from source, keeping the tokens as they were written
+// and the comments between them
+pub enum Node {
+    Symbol(String),
+    Text(String),
+    // A datum after `'`, or after `#;`, which comments it out
+    Prefixed(&'static str, Box<Node>),
+    // A list, vector or bytevector, with the nodes after the dot of an improper list in `tail`
+    List { open: String, items: Vec<Node>, tail: Vec<Node> },
+    // A comment, and whether it's on the same line as what comes before it
+    Comment { text: String, trailing: bool },
+}
+
+impl Node {
+    pub fn from_atom(atom: &Atom) -> Node {
+        if let Some(datum) = quoted(atom) {
+            return Node::Prefixed("'", Box::new(Node::from_atom(&datum)))
+        }
+        match *atom {
+            Atom::Pair(_) => {
+                let (atoms, tail) = atom.list_parts();
+                Node::List {
+                    open: "(".to_string(),
+                    items: atoms.iter().map(Node::from_atom).collect(),
+                    tail: if tail == Atom::Nil { vec![] } else { vec![Node::from_atom(&tail)] },
+                }
+            },
+            Atom::Vector(ref vector) => Node::List {
+                open: "#(".to_string(),
+                items: vector.borrow().iter().map(Node::from_atom).collect(),
+                tail: vec![],
+            },
+            Atom::Symbol(_) => Node::Symbol(flat(atom)),
+            _ => Node::Text(flat(atom)),
+        }
+    }
+
+    // The node on one line, or None if it holds a `;` comment, which runs to the end of its line
+    fn flat(&self) -> Option<String> {
+        match *self {
+            Node::Symbol(ref text) | Node::Text(ref text) => Some(text.clone()),
+            Node::Prefixed(prefix, ref datum) => Some(format!("{}{}", prefix, datum.flat()?)),
+            Node::List { ref open, ref items, ref tail } => {
+                let mut parts = items.iter().map(Node::flat).collect::<Option<Vec<_>>>()?;
+                if !tail.is_empty() {
+                    parts.push(".".to_string());
+                    parts.extend(tail.iter().map(Node::flat).collect::<Option<Vec<_>>>()?);
+                }
+                Some(format!("{}{})", open, parts.join(" ")))
+            },
+            Node::Comment { ref text, .. } if is_line_comment(text) => None,
+            Node::Comment { ref text, .. } => Some(text.clone()),
+        }
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(*self, Node::Comment { .. })
+    }
+
+    pub fn pretty(&self, width: usize) -> String {
+        let mut layout = Layout { width, out: String::new(), after_comment: false };
+        layout.node(self);
+        layout.out
+    }
+}
+
+fn is_line_comment(text: &str) -> bool {
+    text.starts_with(';')
+}
+
+// Where an element goes relative to what's before it
+#[derive(Clone, Copy, PartialEq)]
+enum Place {
+    // Right after an open paren
+    Adjacent,
+    SameLine,
+    NewLine,
+}
+
+struct Layout {
+    width: usize,
+    out: String,
+    // Whether the line ends in a `;` comment, so nothing else can go on it
+    after_comment: bool,
+}
+
+impl Layout {
+    fn column(&self) -> usize {
+        self.out.rsplit('\n').next().map_or(0, |line| line.chars().count())
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.extend(::std::iter::repeat_n(' ', indent));
+        self.after_comment = false;
+    }
+
+    fn fits(&self, node: &Node, column: usize) -> bool {
+        node.flat().is_some_and(|flat| column + flat.chars().count() <= self.width)
+    }
+
+    fn node(&mut self, node: &Node) {
+        let column = self.column();
+        match *node {
+            Node::Comment { ref text, .. } => {
+                self.out.push_str(text);
+                self.after_comment = is_line_comment(text);
+            },
+            _ if self.fits(node, column) => self.out.push_str(&node.flat().unwrap_or_default()),
+            Node::Prefixed(prefix, ref datum) => {
+                self.out.push_str(prefix);
+                self.node(datum)
+            },
+            Node::List { ref open, ref items, ref tail } => self.list(open, items, tail),
+            Node::Symbol(ref text) | Node::Text(ref text) => self.out.push_str(text),
+        }
+    }
+
+    // An element of a list, placed as asked unless a comment decides otherwise. Comments stay on
+    // the line they were written on, and a `;` comment ends its line
+    fn item(&mut self, node: &Node, indent: usize, place: Place) {
+        let place = match *node {
+            _ if self.after_comment => Place::NewLine,
+            Node::Comment { trailing: true, .. } if place != Place::Adjacent => Place::SameLine,
+            Node::Comment { trailing: false, .. } if place != Place::Adjacent => Place::NewLine,
+            _ => place,
+        };
+        match place {
+            Place::Adjacent => (),
+            Place::SameLine => self.out.push(' '),
+            Place::NewLine => self.newline(indent),
+        }
+        self.node(node);
+    }
+
+    // Each of `nodes` on its own line, starting on the current one. Runs of numbers, symbols and
+    // the like fill each line instead
+    fn lines(&mut self, nodes: &[Node], indent: usize) {
+        let fill = nodes.iter().all(|node| matches!(*node, Node::Symbol(_) | Node::Text(_) | Node::Comment { .. }));
+        for (i, node) in nodes.iter().enumerate() {
+            let place = if i == 0 {
+                Place::Adjacent
+            } else if fill && self.fits(node, self.column() + 1) {
+                Place::SameLine
+            } else {
+                Place::NewLine
+            };
+            self.item(node, indent, place);
+        }
+    }
+
+    fn list(&mut self, open: &str, items: &[Node], tail: &[Node]) {
+        let start = self.column();
+        self.out.push_str(open);
+        match items.first() {
+            Some(Node::Symbol(head)) if open == "(" => {
+                self.out.push_str(head);
+                let operands = &items[1..];
+                match body_form(head, operands) {
+                    // The operands that go with the name, then the body
+                    Some(mut n) => for operand in operands {
+                        let place = if n > 0 { Place::SameLine } else { Place::NewLine };
+                        if !operand.is_comment() {
+                            n = n.saturating_sub(1);
+                        }
+                        self.item(operand, start + BODY_INDENT, place);
+                    },
+                    // A call, with its operands lined up under the first
+                    None if !operands.is_empty() => {
+                        self.out.push(' ');
+                        let indent = self.column();
+                        self.lines(operands, indent);
+                    },
+                    None => (),
+                }
+            },
+            // Data, with every element lined up under the first
+            _ => self.lines(items, start + open.chars().count()),
+        }
+        if !tail.is_empty() {
+            self.newline(start + 1);
+            self.out.push('.');
+            for node in tail {
+                self.item(node, start + 3, Place::SameLine);
+            }
+        }
+        if self.after_comment {
+            self.newline(start + 1);
+        }
+        self.out.push(')');
+    }
+}
+
+// The number of operands before the body, if `head` names a special form with a body. A named
+// `let` has its name as well as its bindings on the first line
+fn body_form(head: &str, operands: &[Node]) -> Option<usize> {
+    let &(_, n) = BODY_FORMS.iter().find(|&&(name, _)| name == head)?;
+    match operands.iter().find(|operand| !operand.is_comment()) {
+        Some(&Node::Symbol(_)) if head == "let" => Some(2),
+        _ => Some(n)
+    }
+}
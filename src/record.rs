@@ -0,0 +1,37 @@
+// Records made by `define-record-type`.
+//
+// A record type names its fields, and each record of the type holds one value for each of them.
+// Records are a type of their own, not vectors or pairs, and are only `eqv?` or `equal?` to
+// themselves. The procedures `define-record-type` defines are lambdas around the builtins below,
+// which check they're given a record of the right type.
+use std::cell::RefCell;
+use std::rc::Rc;
+use atom::Atom;
+use symbol::Symbol;
+
+pub struct RecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub fields: RefCell<Vec<Atom>>,
+}
+
+impl RecordType {
+    // The name without the angle brackets record types are often written with, as in `<point>`
+    pub fn display_name(&self) -> String {
+        let name = self.name.to_string();
+        match name.strip_prefix('<').and_then(|name| name.strip_suffix('>')) {
+            Some(inner) if !inner.is_empty() => inner.to_string(),
+            _ => name
+        }
+    }
+}
+
+impl Record {
+    pub fn is_a(&self, record_type: &Rc<RecordType>) -> bool {
+        Rc::ptr_eq(&self.record_type, record_type)
+    }
+}
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::rc::Rc;
+use std;
+
+// An interned name. Symbols with the same name share an ID, so comparing and hashing them
+// doesn't look at the name at all
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+struct SymbolTable {
+    // Names indexed by symbol ID, including uninterned symbols
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>,
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable { names: vec![], ids: HashMap::new() });
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(&symbol) = table.ids.get(name) {
+                return symbol
+            }
+            let symbol = Symbol(table.names.len() as u32);
+            let name: Rc<str> = name.into();
+            table.names.push(name.clone());
+            table.ids.insert(name, symbol);
+            symbol
+        })
+    }
+
+    // A fresh symbol that isn't equal to any other, even one with the same name
+    pub fn uninterned(prefix: &str) -> Symbol {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            let symbol = Symbol(table.names.len() as u32);
+            table.names.push(format!("{}{}", prefix, symbol.0).into());
+            symbol
+        })
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        SYMBOLS.with(|table| table.borrow().names[self.0 as usize].clone())
+    }
+
+    pub fn is_interned(&self) -> bool {
+        SYMBOLS.with(|table| {
+            let table = table.borrow();
+            table.ids.get(&table.names[self.0 as usize]) == Some(self)
+        })
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Symbol {
+        Symbol::intern(&name)
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, name: &&str) -> bool {
+        *self.name() == **name
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Symbol IDs are already unique small integers, so they don't need a strong hash
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 << 8 | byte as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (n as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
+
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
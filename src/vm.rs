@@ -0,0 +1,681 @@
+// Runs bytecode from `compile.rs`. Calls between lambdas push a frame onto `frames` instead of
+// recursing in Rust, and calls in tail position replace the caller's frame, so a loop written as
+// a tail-recursive procedure runs in constant space. `call/cc`, `dynamic-wind`, `apply`, `map`,
+// `for-each`, `with-exception-handler` and `guard` are handled here too, so the continuations
+// captured in a run cover everything it has called.
+//
+// Calls are kept cheap: a lambda that nothing closes over keeps its frame in slots on the stack
+// instead of allocating an environment, global variables remember what they were last found to be,
+// and arithmetic and comparisons on two integers don't collect their arguments into a Vec.
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::mem;
+use std::rc::Rc;
+use atom::Atom;
+use analyze::analyze;
+use builtins::*;
+use compile::{Chunk, Op, Operand, lambda_chunk};
+use continuation::{Continuation, Winders, escaped, next_extent, pop_winder, push_winder, rewind, winders};
+use environment::{Environment, SchemeFn, SchemeFnWrap, SchemeLambda, env_alloc, env_get, env_get_local, env_set, env_set_local, env_spawn_frame};
+use error::SchemeError;
+use interpreter::{Handler, deliver_to_handler, execute, guard_clauses, handler_depth, handlers, push_handler, set_handlers, truncate_handlers};
+use library::{define_library, import};
+use load::include;
+use parse::{read_from_tokens, tokenize};
+
+#[derive(Clone)]
+pub struct Frame {
+    chunk: Rc<Chunk>,
+    pc: usize,
+    pub(crate) env: Rc<RefCell<Environment>>,
+    // Height of the stack when the frame was entered
+    base: usize,
+    // The lambda being run, which is named in stack traces
+    pub(crate) lambda: Option<Rc<SchemeLambda>>,
+    // The exception handler installed for the code the frame runs, if it's a `guard` body or
+    // the thunk given to `with-exception-handler`. Boxed, since few frames have one
+    handler: Option<Box<Installed>>,
+}
+
+#[derive(Clone)]
+struct Installed {
+    catch: Catch,
+    // Number of handlers installed outside this one
+    depth: usize,
+    // The `dynamic-wind` extents the frame was entered in
+    winders: Winders,
+}
+
+#[derive(Clone)]
+enum Catch {
+    // The body of `guards[i]` in the calling frame's chunk
+    Guard(usize),
+    Procedure(SchemeFnWrap),
+}
+
+impl Frame {
+    // The handler procedure the frame installed, which the cycle collector has to see
+    pub(crate) fn handler_procedure(&self) -> Option<&SchemeFnWrap> {
+        match self.handler.as_deref() {
+            Some(Installed { catch: Catch::Procedure(handler), .. }) => Some(handler),
+            _ => None
+        }
+    }
+}
+
+struct Vm {
+    // Identifies this run to the continuations it captures
+    extent: usize,
+    top_level: bool,
+    stack: Vec<Atom>,
+    frame: Frame,
+    // Frames of the callers waiting on `frame`, innermost last
+    frames: Vec<Frame>,
+}
+
+// Builtins that need access to the VM's state
+enum Control {
+    CallCc,
+    DynamicWind,
+    CallWithValues,
+    Apply,
+    Map,
+    ForEach,
+    WithExceptionHandler,
+}
+
+fn control(func: SchemeFn) -> Option<Control> {
+    let is = |builtin: SchemeFn| func as usize == builtin as usize;
+    if is(scheme_call_cc) {
+        Some(Control::CallCc)
+    } else if is(scheme_dynamic_wind) {
+        Some(Control::DynamicWind)
+    } else if is(scheme_call_with_values) {
+        Some(Control::CallWithValues)
+    } else if is(scheme_apply) {
+        Some(Control::Apply)
+    } else if is(scheme_map) {
+        Some(Control::Map)
+    } else if is(scheme_for_each) {
+        Some(Control::ForEach)
+    } else if is(scheme_with_exception_handler) {
+        Some(Control::WithExceptionHandler)
+    } else {
+        None
+    }
+}
+
+thread_local! {
+    // Runs `dynamic-wind` in a frame whose slots hold before, thunk, after and the thunk's result
+    static DYNAMIC_WIND: Rc<Chunk> = Rc::new(Chunk {
+        code: vec![
+            Op::Local(0, 0), Op::Call(0), Op::Pop,
+            Op::Wind,
+            Op::Local(0, 1), Op::Call(0), Op::DefineLocal(0, 3), Op::Pop,
+            Op::Unwind,
+            Op::Local(0, 2), Op::Call(0), Op::Pop,
+            Op::Local(0, 3), Op::Return,
+        ],
+        ..Chunk::default()
+    });
+    // Runs `call-with-values` in a frame whose slots hold the producer and consumer
+    static CALL_WITH_VALUES: Rc<Chunk> = Rc::new(Chunk {
+        code: vec![Op::Local(0, 1), Op::Local(0, 0), Op::Call(0), Op::ApplyValues, Op::Return],
+        ..Chunk::default()
+    });
+    // Runs the thunk in the frame's only slot, for `with-exception-handler`
+    static CALL_THUNK: Rc<Chunk> = Rc::new(Chunk {
+        code: vec![Op::Local(0, 0), Op::Call(0), Op::Return],
+        ..Chunk::default()
+    });
+    // `map` and `for-each` over the rows of arguments `procedure_and_lists` makes. The results of
+    // `map` are consed up as it goes, so resuming a continuation captured by the procedure leaves
+    // the lists `map` has already returned alone
+    static MAP: SchemeFnWrap = scheme_procedure("map", "(define (map f rows results)
+        (if (null? rows) (reverse results) (map f (cdr rows) (cons (apply f (car rows)) results))))");
+    static FOR_EACH: SchemeFnWrap = scheme_procedure("for-each", "(define (for-each f rows)
+        (if (pair? rows) (begin (apply f (car rows)) (for-each f (cdr rows)))))");
+}
+
+// Builtins on two integers that the VM works out on its stack, without collecting the arguments
+// into a Vec. Each gives the same result as the builtin, or None if the builtin fails because the
+// result overflows
+const INTEGER_BUILTINS: &[(SchemeFn, IntegerOp)] = &[
+    (scheme_add, IntegerOp::Add),
+    (scheme_subtract, IntegerOp::Subtract),
+    (scheme_multiply, IntegerOp::Multiply),
+    (scheme_lt, IntegerOp::Compare(Ordering::Less, false)),
+    (scheme_gt, IntegerOp::Compare(Ordering::Greater, false)),
+    (scheme_le, IntegerOp::Compare(Ordering::Greater, true)),
+    (scheme_ge, IntegerOp::Compare(Ordering::Less, true)),
+    (scheme_eq, IntegerOp::Compare(Ordering::Equal, false)),
+];
+
+#[derive(Clone, Copy)]
+enum IntegerOp {
+    Add,
+    Subtract,
+    Multiply,
+    // True if the arguments compare as the ordering, or if `negate` is set, as anything else
+    Compare(Ordering, bool),
+}
+
+impl IntegerOp {
+    #[inline]
+    fn compare(ordering: Ordering, negate: bool, a: i32, b: i32) -> bool {
+        (a.cmp(&b) == ordering) != negate
+    }
+}
+
+fn integer_builtin(func: SchemeFn) -> Option<IntegerOp> {
+    INTEGER_BUILTINS.iter().find(|&&(builtin, _)| func as usize == builtin as usize).map(|&(_, op)| op)
+}
+
+// The builtins the procedures above call, which are bound as constants so that redefining them
+// doesn't change the procedures
+const PROCEDURE_BUILTINS: &[(&str, SchemeFn)] = &[
+    ("apply", scheme_apply),
+    ("car", scheme_car),
+    ("cdr", scheme_cdr),
+    ("cons", scheme_cons),
+    ("null?", scheme_is_null),
+    ("pair?", scheme_is_pair),
+    ("reverse", scheme_reverse),
+];
+
+// The procedure `name` that `source` defines in an environment of its own
+fn scheme_procedure(name: &str, source: &str) -> SchemeFnWrap {
+    let env = env_alloc(Environment::new());
+    let form = tokenize(source).and_then(|mut tokens| read_from_tokens(&mut tokens))
+        .expect("Unreadable builtin procedure");
+    analyze(&with_builtins(form)).and_then(|expr| execute(&expr, &env))
+        .and_then(|_| env_get(&env, name))
+        .and_then(|procedure| Ok(procedure.as_callable_result()?.clone()))
+        .expect("Invalid builtin procedure")
+}
+
+fn with_builtins(atom: Atom) -> Atom {
+    match atom {
+        Atom::Symbol(symbol) => match PROCEDURE_BUILTINS.iter().find(|&&(name, _)| symbol == name) {
+            Some(&(_, func)) => Atom::Callable(SchemeFnWrap::Fn(func)),
+            None => atom
+        },
+        Atom::Pair(_) => Atom::list(atom.to_vec().unwrap_or_default().into_iter().map(with_builtins).collect()),
+        _ => atom
+    }
+}
+
+// The procedure `atom` holds, for calling it
+#[inline]
+fn procedure(atom: &Atom) -> Result<SchemeFnWrap, SchemeError> {
+    match *atom {
+        Atom::Callable(ref func) => Ok(func.clone()),
+        ref callable => Err(SchemeError::type_error(&format!("Expected function, found {}", callable)))
+    }
+}
+
+// Call `lambda` with `args`, returning the value it returns
+pub fn run_lambda(lambda: &SchemeLambda, args: Vec<Atom>) -> Result<Atom, SchemeError> {
+    let mut stack = args;
+    let env = lambda_frame(&mut stack, lambda, 0)?;
+    let frame = Frame { chunk: lambda_chunk(&lambda.code), pc: 0, env, base: 0, lambda: None, handler: None };
+    run_vm(frame, stack, false)
+}
+
+// Run a top-level form. Continuations captured by earlier forms can be resumed here, and finish
+// by returning from this form instead
+pub fn run_top_level(chunk: Rc<Chunk>, env: Rc<RefCell<Environment>>) -> Result<Atom, SchemeError> {
+    run_vm(Frame { chunk, pc: 0, env, base: 0, lambda: None, handler: None }, vec![], true)
+}
+
+// The environment for a call to `lambda` with the arguments on `stack` from `base` up. A frame
+// kept on the stack takes the arguments there as its slots, and runs in the environment the
+// lambda was created in. Otherwise the arguments move to a new environment
+fn lambda_frame(stack: &mut Vec<Atom>, lambda: &SchemeLambda, base: usize) -> Result<Rc<RefCell<Environment>>, SchemeError> {
+    let code = &lambda.code;
+    if !code.on_stack {
+        return lambda.bind(stack.split_off(base))
+    }
+    lambda.check_arity(stack.len() - base)?;
+    if code.rest {
+        let rest = stack.split_off(base + code.required);
+        stack.push(Atom::list(rest));
+    }
+    if stack.len() != base + code.frame_size {
+        stack.resize(base + code.frame_size, Atom::Unspecified);
+    }
+    Ok(lambda.env.clone())
+}
+
+fn run_vm(frame: Frame, stack: Vec<Atom>, top_level: bool) -> Result<Atom, SchemeError> {
+    let outer_winders = winders();
+    let outer_handlers = handler_depth();
+    let mut vm = Vm {
+        extent: next_extent(),
+        top_level,
+        stack,
+        frame,
+        frames: vec![],
+    };
+    let mut result = vm.execute();
+    loop {
+        let mut err = match result {
+            Ok(result) => return Ok(result),
+            Err(err) => err
+        };
+        if let Some((k, values)) = escaped(&err) {
+            let ours = k.extent == vm.extent || (vm.top_level && k.top_level && k.is_resumable());
+            if !ours {
+                truncate_handlers(outer_handlers);
+                return Err(err)
+            }
+            vm.resume(k, values);
+            result = vm.execute();
+            continue
+        }
+        if vm.is_handled() {
+            result = vm.catch(err).and_then(|()| vm.execute());
+            continue
+        }
+        for frame in Some(&vm.frame).into_iter().chain(vm.frames.iter().rev()) {
+            if let Some(ref lambda) = frame.lambda {
+                err = err.push_trace(lambda.name());
+            }
+        }
+        // Leave any `dynamic-wind` extents the error passed through
+        truncate_handlers(outer_handlers);
+        rewind(&outer_winders, &vm.frame.env)?;
+        return Err(err)
+    }
+}
+
+impl Vm {
+    fn pop(&mut self) -> Atom {
+        self.stack.pop().expect("VM stack underflow")
+    }
+
+    // The current frame's program counter is kept in a local, and saved back whenever a call or
+    // return might switch to another frame
+    fn execute(&mut self) -> Result<Atom, SchemeError> {
+        loop {
+            let mut pc = self.frame.pc;
+            loop {
+                let op = self.frame.chunk.code[pc];
+                pc += 1;
+                match op {
+                    Op::Constant(index) => {
+                        let constant = self.frame.chunk.constants[index as usize].clone();
+                        self.stack.push(constant);
+                    },
+                    Op::Local(depth, index) => {
+                        let value = env_get_local(&self.frame.env, depth as usize, index as usize);
+                        self.stack.push(value);
+                    },
+                    Op::Slot(index) => {
+                        let value = self.stack[self.frame.base + index as usize].clone();
+                        self.stack.push(value);
+                    },
+                    Op::Global(index) => {
+                        let value = self.frame.chunk.globals[index as usize].get(&self.frame.env)?;
+                        self.stack.push(value);
+                    },
+                    Op::DefineLocal(depth, index) => {
+                        let value = self.pop();
+                        env_set_local(&self.frame.env, depth as usize, index as usize, value);
+                        self.stack.push(Atom::Unspecified);
+                    },
+                    Op::DefineGlobal(symbol) => {
+                        let value = self.pop();
+                        env_set(self.frame.env.clone(), symbol, value);
+                        self.stack.push(Atom::Unspecified);
+                    },
+                    Op::Jump(target) => pc = target as usize,
+                    Op::JumpIfFalse(target) => if let Atom::Bool(false) = self.pop() {
+                        pc = target as usize;
+                    },
+                    Op::Pop => {
+                        self.pop();
+                    },
+                    Op::Closure(index) => {
+                        let code = self.frame.chunk.lambdas[index as usize].clone();
+                        let lambda = SchemeLambda { code, env: self.frame.env.clone() };
+                        self.stack.push(Atom::Callable(SchemeFnWrap::lambda(lambda)));
+                    },
+                    Op::Call(argc) => {
+                        self.frame.pc = pc;
+                        let depth = self.frames.len();
+                        self.call(argc as usize, false)?;
+                        if self.frames.len() != depth {
+                            break
+                        }
+                    },
+                    Op::ApplyValues => {
+                        let values = self.pop().into_values();
+                        match self.pop() {
+                            Atom::Callable(consumer) => {
+                                self.frame.pc = pc;
+                                self.apply(consumer, values, true)?;
+                                break
+                            },
+                            consumer => return Err(SchemeError::type_error(&format!("Expected function, found {}", consumer)))
+                        }
+                    },
+                    Op::TailCall(argc) => {
+                        self.frame.pc = pc;
+                        self.call(argc as usize, true)?;
+                        break
+                    },
+                    Op::CallGlobal(index, argc) => {
+                        let func = self.frame.chunk.globals[index as usize].with(&self.frame.env, procedure)?;
+                        self.frame.pc = pc;
+                        let depth = self.frames.len();
+                        self.call_procedure(func, argc as usize, false)?;
+                        if self.frames.len() != depth {
+                            break
+                        }
+                    },
+                    Op::CallGlobal2(index, a, b) => {
+                        let func = self.frame.chunk.globals[index as usize].with(&self.frame.env, procedure)?;
+                        let (a, b) = (self.operand(a), self.operand(b));
+                        if let (&SchemeFnWrap::Fn(builtin), &Atom::Int(x), &Atom::Int(y)) = (&func, a, b) {
+                            if let Some(op) = integer_builtin(builtin) {
+                                // A comparison that `if` tests jumps without pushing the result
+                                match (op, self.frame.chunk.code[pc]) {
+                                    (IntegerOp::Compare(ordering, negate), Op::JumpIfFalse(target)) => {
+                                        pc = if IntegerOp::compare(ordering, negate, x, y) { pc + 1 } else { target as usize };
+                                    },
+                                    _ => self.push_integer_result(op, x, y)?,
+                                }
+                                continue
+                            }
+                        }
+                        let (a, b) = (a.clone(), b.clone());
+                        self.stack.push(a);
+                        self.stack.push(b);
+                        self.frame.pc = pc;
+                        let depth = self.frames.len();
+                        self.call_procedure(func, 2, false)?;
+                        if self.frames.len() != depth {
+                            break
+                        }
+                    },
+                    Op::TailCallGlobal(index, argc) => {
+                        let func = self.frame.chunk.globals[index as usize].with(&self.frame.env, procedure)?;
+                        self.frame.pc = pc;
+                        let depth = self.frames.len();
+                        self.call_procedure(func, argc as usize, true)?;
+                        // A builtin leaves the frame as it was, to go on to return its result
+                        if self.frames.len() != depth || self.frame.pc != pc {
+                            break
+                        }
+                    },
+                    Op::Return => {
+                        if let Some(ref installed) = self.frame.handler {
+                            truncate_handlers(installed.depth);
+                        }
+                        let result = self.pop();
+                        self.stack.truncate(self.frame.base);
+                        match self.frames.pop() {
+                            Some(caller) => {
+                                self.frame = caller;
+                                self.stack.push(result);
+                                break
+                            },
+                            None => return Ok(result)
+                        }
+                    },
+                    // The body runs in a frame of its own, which `catch` finds if it fails
+                    Op::Guard(index) => {
+                        let installed = Installed { catch: Catch::Guard(index as usize), depth: handler_depth(), winders: winders() };
+                        push_handler(Handler::Guard);
+                        let chunk = self.frame.chunk.guards[index as usize].1.clone();
+                        let body = Frame { chunk, pc: 0, env: self.frame.env.clone(), base: self.stack.len(), lambda: None, handler: Some(Box::new(installed)) };
+                        self.frame.pc = pc;
+                        self.enter(body, false);
+                        break
+                    },
+                    Op::Import(index) => {
+                        let result = import(&self.frame.chunk.forms[index as usize], self.frame.env.clone())?;
+                        self.stack.push(result);
+                    },
+                    Op::DefineLibrary(index) => {
+                        let form = &self.frame.chunk.forms[index as usize];
+                        let result = define_library(&form[0], &form[1..])?;
+                        self.stack.push(result);
+                    },
+                    Op::Wind => {
+                        let (before, after) = {
+                            let env = self.frame.env.borrow();
+                            (env.values[0].as_callable_result()?.clone(), env.values[2].as_callable_result()?.clone())
+                        };
+                        push_winder(before, after);
+                        self.stack.push(Atom::Unspecified);
+                    },
+                    Op::Unwind => {
+                        pop_winder();
+                        self.stack.push(Atom::Unspecified);
+                    },
+                    Op::Include(index, case_insensitive) => {
+                        let files = &self.frame.chunk.forms[index as usize];
+                        let result = include(files, self.frame.env.clone(), case_insensitive)?;
+                        self.stack.push(result);
+                    },
+                }
+            }
+        }
+    }
+
+    // Push what `op` gives for `a` and `b`. Each result is pushed where it's worked out, which is
+    // faster than passing an Atom back
+    #[inline]
+    fn push_integer_result(&mut self, op: IntegerOp, a: i32, b: i32) -> Result<(), SchemeError> {
+        let result = match op {
+            IntegerOp::Add => a.checked_add(b),
+            IntegerOp::Subtract => a.checked_sub(b),
+            IntegerOp::Multiply => a.checked_mul(b),
+            IntegerOp::Compare(ordering, negate) => {
+                self.stack.push(Atom::Bool(IntegerOp::compare(ordering, negate, a, b)));
+                return Ok(())
+            },
+        };
+        match result {
+            Some(result) => self.stack.push(Atom::Int(result)),
+            None => return Err(integer_overflow(&[Atom::Int(a), Atom::Int(b)]))
+        }
+        Ok(())
+    }
+
+    fn operand(&self, operand: Operand) -> &Atom {
+        match operand {
+            Operand::Constant(index) => &self.frame.chunk.constants[index as usize],
+            Operand::Slot(index) => &self.stack[self.frame.base + index as usize],
+        }
+    }
+
+    // Call the procedure below the top `argc` values
+    fn call(&mut self, argc: usize, tail: bool) -> Result<(), SchemeError> {
+        let func = self.stack.remove(self.stack.len() - argc - 1);
+        self.call_procedure(procedure(&func)?, argc, tail)
+    }
+
+    // Call `func` with the top `argc` values
+    fn call_procedure(&mut self, func: SchemeFnWrap, argc: usize, tail: bool) -> Result<(), SchemeError> {
+        let base = self.stack.len() - argc;
+        match func {
+            // Lambdas take their arguments where they are
+            SchemeFnWrap::Lambda(lambda) => self.enter_lambda(lambda, base, tail),
+            SchemeFnWrap::Fn(func) => {
+                if let [Atom::Int(a), Atom::Int(b)] = self.stack[base..] {
+                    if let Some(op) = integer_builtin(func) {
+                        self.stack.truncate(base);
+                        return self.push_integer_result(op, a, b)
+                    }
+                }
+                let args = self.stack.split_off(base);
+                self.apply(SchemeFnWrap::Fn(func), args, tail)
+            },
+            func => {
+                let args = self.stack.split_off(base);
+                self.apply(func, args, tail)
+            },
+        }
+    }
+
+    // Builtins run to completion, but lambdas only get a frame, which the main loop then runs
+    fn apply(&mut self, func: SchemeFnWrap, args: Vec<Atom>, tail: bool) -> Result<(), SchemeError> {
+        match func {
+            SchemeFnWrap::Fn(func) => match control(func) {
+                Some(Control::CallCc) => {
+                    if args.len() != 1 {
+                        return Err(SchemeError::arity(&format!("Invalid number of operands to call/cc {}", args.len())))
+                    }
+                    let receiver = args[0].as_callable_result()?.clone();
+                    let k = self.capture();
+                    self.apply(receiver, vec![k], tail)
+                },
+                Some(Control::DynamicWind) => {
+                    if args.len() != 3 {
+                        return Err(SchemeError::arity(&format!("Invalid number of operands to dynamic-wind {}", args.len())))
+                    }
+                    for arg in &args {
+                        arg.as_callable_result()?;
+                    }
+                    let mut values = args;
+                    values.push(Atom::Unspecified);
+                    let env = env_spawn_frame(self.frame.env.clone(), values);
+                    let chunk = DYNAMIC_WIND.with(Rc::clone);
+                    self.enter(Frame { chunk, pc: 0, env, base: self.stack.len(), lambda: None, handler: None }, tail);
+                    Ok(())
+                },
+                Some(Control::CallWithValues) => {
+                    if args.len() != 2 {
+                        return Err(SchemeError::arity(&format!("Invalid number of operands to call-with-values {}", args.len())))
+                    }
+                    for arg in &args {
+                        arg.as_callable_result()?;
+                    }
+                    let env = env_spawn_frame(self.frame.env.clone(), args);
+                    let chunk = CALL_WITH_VALUES.with(Rc::clone);
+                    self.enter(Frame { chunk, pc: 0, env, base: self.stack.len(), lambda: None, handler: None }, tail);
+                    Ok(())
+                },
+                Some(Control::Map) => {
+                    let (func, rows) = procedure_and_lists("map", &args)?;
+                    let rows = Atom::list(rows.into_iter().map(Atom::list).collect());
+                    self.apply(MAP.with(Clone::clone), vec![Atom::Callable(func.clone()), rows, Atom::Nil], tail)
+                },
+                Some(Control::ForEach) => {
+                    let (func, rows) = procedure_and_lists("for-each", &args)?;
+                    let rows = Atom::list(rows.into_iter().map(Atom::list).collect());
+                    self.apply(FOR_EACH.with(Clone::clone), vec![Atom::Callable(func.clone()), rows], tail)
+                },
+                Some(Control::WithExceptionHandler) => {
+                    if args.len() != 2 {
+                        return Err(SchemeError::arity(&format!("Invalid number of operands to with-exception-handler {}", args.len())))
+                    }
+                    let handler = args[0].as_callable_result()?.clone();
+                    args[1].as_callable_result()?;
+                    let installed = Installed { catch: Catch::Procedure(handler.clone()), depth: handler_depth(), winders: winders() };
+                    push_handler(Handler::Procedure(handler));
+                    let env = env_spawn_frame(self.frame.env.clone(), args[1..].to_vec());
+                    let chunk = CALL_THUNK.with(Rc::clone);
+                    self.enter(Frame { chunk, pc: 0, env, base: self.stack.len(), lambda: None, handler: Some(Box::new(installed)) }, tail);
+                    Ok(())
+                },
+                Some(Control::Apply) => {
+                    if args.len() < 2 {
+                        return Err(SchemeError::arity(&format!("Invalid number of operands to apply {}", args.len())))
+                    }
+                    let (func, args) = apply_args(&args)?;
+                    self.apply(func, args, tail)
+                },
+                None => {
+                    let result = func(self.frame.env.clone(), args)?;
+                    self.stack.push(result);
+                    Ok(())
+                }
+            },
+            SchemeFnWrap::Lambda(lambda) => {
+                let base = self.stack.len();
+                self.stack.extend(args);
+                self.enter_lambda(lambda, base, tail)
+            },
+            SchemeFnWrap::Continuation(k) => Err(Continuation::invoke(&k, args, &self.frame.env)),
+        }
+    }
+
+    // Call `lambda` with the arguments on the stack from `base` up
+    fn enter_lambda(&mut self, lambda: Rc<SchemeLambda>, base: usize, tail: bool) -> Result<(), SchemeError> {
+        let env = lambda_frame(&mut self.stack, &lambda, base).map_err(|err| err.push_trace(lambda.name()))?;
+        let frame = Frame { chunk: lambda_chunk(&lambda.code), pc: 0, env, base, lambda: Some(lambda), handler: None };
+        self.enter(frame, tail);
+        Ok(())
+    }
+
+    // Start running `callee`, replacing the current frame for a tail call unless the current frame
+    // has a handler installed, which has to stay until the call returns. The callee's slots on the
+    // stack move down to where the current frame's were
+    #[inline]
+    fn enter(&mut self, mut callee: Frame, tail: bool) {
+        if tail && self.frame.handler.is_none() {
+            self.stack.drain(self.frame.base..callee.base);
+            callee.base = self.frame.base;
+            self.frame = callee;
+        } else {
+            self.frames.push(mem::replace(&mut self.frame, callee));
+        }
+    }
+
+    // The continuation of the call being made, which carries on from the current instruction
+    fn capture(&self) -> Atom {
+        let mut frames = self.frames.clone();
+        frames.push(self.frame.clone());
+        Continuation {
+            extent: self.extent,
+            top_level: self.top_level,
+            stack: self.stack.clone(),
+            frames,
+            handlers: handlers(),
+            winders: winders(),
+        }.into_atom()
+    }
+
+    // Carry on from where `k` was captured, with `call/cc` returning `values`
+    fn resume(&mut self, k: &Continuation, values: &[Atom]) {
+        self.stack = k.stack.clone();
+        self.frames = k.frames.clone();
+        self.frame = self.frames.pop().expect("Resumed a continuation without frames");
+        self.stack.push(Continuation::result(values));
+        set_handlers(k.handlers.clone());
+    }
+
+    // Whether any frame has a handler installed
+    fn is_handled(&self) -> bool {
+        Some(&self.frame).into_iter().chain(&self.frames).any(|frame| frame.handler.is_some())
+    }
+
+    // Unwind to the innermost frame with a handler installed and let it handle `err`. A guard
+    // form that catches the error carries on with its value, and anything else is a new error
+    fn catch(&mut self, mut err: SchemeError) -> Result<(), SchemeError> {
+        while self.frame.handler.is_none() {
+            if let Some(ref lambda) = self.frame.lambda {
+                err = err.push_trace(lambda.name());
+            }
+            self.frame = self.frames.pop().expect("No frame with a handler to catch an error");
+        }
+        self.stack.truncate(self.frame.base);
+        let installed = self.frame.handler.take().expect("Frame has no handler to catch an error");
+        truncate_handlers(installed.depth);
+        rewind(&installed.winders, &self.frame.env)?;
+        match installed.catch {
+            Catch::Guard(index) => {
+                self.frame = self.frames.pop().expect("Guard body without a calling frame");
+                let guard = self.frame.chunk.guards[index].0.clone();
+                let result = guard_clauses(&self.frame.env, &guard, err)?;
+                self.stack.push(result);
+                Ok(())
+            },
+            Catch::Procedure(ref handler) => Err(deliver_to_handler(handler, installed.depth, err, &self.frame.env)),
+        }
+    }
+}
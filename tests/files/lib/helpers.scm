@@ -0,0 +1,3 @@
+; Relative includes resolve against this file's directory
+(include-ci "upper.scm")
+(define (twice x) (* 2 x))
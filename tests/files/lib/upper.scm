@@ -0,0 +1 @@
+(DEFINE (ADD-ONE X) (+ X 1))
@@ -0,0 +1,2 @@
+(include "lib/helpers.scm")
+(define total (twice 3))
@@ -1,5 +1,19 @@
 extern crate rust_scheme;
-use rust_scheme::interpreter::run_program;
+use rust_scheme::interpreter::{Engine, run_program, eval_program, set_command_line, set_engine};
+use rust_scheme::environment::Environment;
+use rust_scheme::parse::{is_complete, read_from_tokens, tokenize};
+use rust_scheme::load::load_file;
+use rust_scheme::library::add_library_path;
+use rust_scheme::error::{ErrorKind, Span};
+use rust_scheme::format::format_source;
+use rust_scheme::gc;
+use rust_scheme::port::{self, Current, Port};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::rc::Rc;
 
 pub fn test_program(program: &str, expected: &str) {
     match run_program(program) {
@@ -9,16 +23,13 @@ pub fn test_program(program: &str, expected: &str) {
 }
 
 pub fn test_error(program: &str) {
-    match run_program(program) {
-        Ok(_) => panic!(),
-        Err(_) => assert!(true)
-    }
+    assert!(run_program(program).is_err())
 }
 
 pub fn test_error_msg(program: &str, expected: &str) {
     match run_program(program) {
         Ok(_) => panic!(),
-        Err(msg) => assert_eq!(msg, expected.to_string())
+        Err(err) => assert_eq!(err.to_string(), expected.to_string())
     }
 }
 
@@ -44,37 +55,37 @@ fn test_divide() {
 
 #[test]
 fn test_gt() {
-    test_program("(> 10 5)", &format!("{}", true));
-    test_program("(> 5 10)", &format!("{}", false));
-    test_program("(> 5 5)", &format!("{}", false))
+    test_program("(> 10 5)", "#t");
+    test_program("(> 5 10)", "#f");
+    test_program("(> 5 5)", "#f")
 }
 
 #[test]
 fn test_lt() {
-    test_program("(< 10 5)", &format!("{}", false));
-    test_program("(< 5 10)", &format!("{}", true));
-    test_program("(< 5 5)", &format!("{}", false))
+    test_program("(< 10 5)", "#f");
+    test_program("(< 5 10)", "#t");
+    test_program("(< 5 5)", "#f")
 }
 
 #[test]
 fn test_ge() {
-    test_program("(>= 10 5)", &format!("{}", true));
-    test_program("(>= 5 10)", &format!("{}", false));
-    test_program("(>= 5 5)", &format!("{}", true))
+    test_program("(>= 10 5)", "#t");
+    test_program("(>= 5 10)", "#f");
+    test_program("(>= 5 5)", "#t")
 }
 
 #[test]
 fn test_le() {
-    test_program("(<= 10 5)", &format!("{}", false));
-    test_program("(<= 5 10)", &format!("{}", true));
-    test_program("(<= 5 5)", &format!("{}", true))
+    test_program("(<= 10 5)", "#f");
+    test_program("(<= 5 10)", "#t");
+    test_program("(<= 5 5)", "#t")
 }
 
 #[test]
 fn test_eq() {
-    test_program("(= 10 10)", &format!("{}", true));
-    test_program("(= 15 10)", &format!("{}", false));
-    test_program("(= 10 15)", &format!("{}", false))
+    test_program("(= 10 10)", "#t");
+    test_program("(= 15 10)", "#f");
+    test_program("(= 10 15)", "#f")
 }
 
 #[test]
@@ -95,7 +106,7 @@ fn test_list_and_append() {
 #[test]
 fn test_quote() {
     test_program("'(1 2 3)", "(1 2 3)");
-    test_program("(list? '(1 2 3))", "true");
+    test_program("(list? '(1 2 3))", "#t");
     test_program("(append (list 1 2 3) '(4 5 6))", "(1 2 3 4 5 6)")
 }
 
@@ -127,24 +138,94 @@ fn test_cdr() {
 
 #[test]
 fn test_is_eq() {
-    test_program("(eq? 1 1)", "true");
-    test_program("(eq? (list 1 2) (list 1 2))", "false");
-    test_program("(eq? (list 1 2) (list 1 1))", "false");
+    test_program("(eq? 1 1)", "#t");
+    test_program("(eq? (list 1 2) (list 1 2))", "#f");
+    test_program("(eq? (list 1 2) (list 1 1))", "#f");
+    test_program("(define x (list 1 2)) (eq? x x)", "#t");
+    test_program("(define x (list 1 2)) (eq? (cdr x) (cdr x))", "#t");
+    test_program("(eq? 'a 'a)", "#t");
+    test_program("(eq? '() '())", "#t");
+    test_program("(eq? car car)", "#t");
+    test_program("(eq? car cdr)", "#f");
+    test_program("(define (f) f) (eq? f (f))", "#t");
+    test_program("(eq? (lambda () 1) (lambda () 1))", "#f");
+    test_program("(guard (e (#t (eq? e e))) (error \"oops\"))", "#t");
+}
+
+#[test]
+fn test_is_eqv() {
+    test_program("(eqv? 2 2)", "#t");
+    test_program("(eqv? #f #f)", "#t");
+    test_program("(eqv? \"a\" \"a\")", "#f");
+    test_program("(define s \"a\") (eqv? s s)", "#t");
+    test_program("(eqv? (list 1) (list 1))", "#f");
+    test_program("(eqv? 1 #t)", "#f")
 }
 
 #[test]
 fn test_is_equal() {
-    test_program("(equal? 1 1)", "true");
-    test_program("(equal? (list 1 2) (list 1 2))", "true");
-    test_program("(equal? (list 1 2) (list 1 1))", "false");
-    test_program("(equal? 1 2)", "false");
+    test_program("(equal? 1 1)", "#t");
+    test_program("(equal? (list 1 2) (list 1 2))", "#t");
+    test_program("(equal? (list 1 2) (list 1 1))", "#f");
+    test_program("(equal? 1 2)", "#f");
+    test_program("(equal? \"abc\" \"abc\")", "#t");
+    test_program("(equal? '(1 (2 \"x\") . 3) '(1 (2 \"x\") . 3))", "#t");
+    test_program("(equal? '(1 2) '(1 2 3))", "#f");
+    test_program("(equal? car car)", "#t");
+    // Circular lists compare equal when they unfold to the same infinite structure
+    test_program("(define a (list 1 2)) (set-cdr! (cdr a) a)
+                  (define b (list 1 2 1 2)) (set-cdr! (cdr (cdr (cdr b))) b)
+                  (equal? a b)", "#t");
+    test_program("(define a (list 1 2)) (set-cdr! (cdr a) a)
+                  (define b (list 1 3)) (set-cdr! (cdr b) b)
+                  (equal? a b)", "#f");
 }
 
 #[test]
 fn test_is_list() {
-    test_program("(list? (list 1 2))", "true");
-    test_program("(list? (+ 1 2))", "false");
-    test_program("(list? 1)", "false");
+    test_program("(list? (list 1 2))", "#t");
+    test_program("(list? (+ 1 2))", "#f");
+    test_program("(list? 1)", "#f");
+}
+
+#[test]
+fn test_pairs() {
+    test_program("(cons 1 2)", "(1 . 2)");
+    test_program("(cons 1 (list 2 3))", "(1 2 3)");
+    test_program("(list? (cons 1 (list 2 3)))", "#t");
+    test_program("(list? (cons 1 2))", "#f");
+    test_program("(append (cons 1 (list 2)) (list 3))", "(1 2 3)");
+    test_program("(append (list 1) 2)", "(1 . 2)");
+    test_program("(cdr (cons 1 (cons 2 3)))", "(2 . 3)");
+    test_program("'(1 . (2 3))", "(1 2 3)");
+    test_program("'(1 2 . 3)", "(1 2 . 3)");
+    test_error("(append (cons 1 2) (list 3))")
+}
+
+#[test]
+fn test_set_car_cdr() {
+    test_program("(begin (define x (list 1 2)) (define y (cons 0 x)) (set-car! x 5) y)", "(0 5 2)");
+    test_program("(begin (define x (list 1 2 3)) (set-car! (cdr x) 9) x)", "(1 9 3)");
+    test_program("(begin (define t (list 3)) (define a (append (list 1) t)) (set-car! t 4) a)", "(1 4)");
+    test_program("(begin (define x (list 1 2)) (set-cdr! (cdr x) x) (list? x))", "#f");
+    test_error("(set-car! '() 1)")
+}
+
+#[test]
+fn test_quote_data() {
+    test_program("'a", "a");
+    test_program("'()", "()");
+    test_program("(quote (a (b c)))", "(a (b c))");
+    test_program("(car '((1 2) 3))", "(1 2)");
+    test_program("(begin '(1 2))", "(1 2)")
+}
+
+#[test]
+fn test_variadic_lambda() {
+    test_program("((lambda args args) 1 2 3)", "(1 2 3)");
+    test_program("((lambda (a . rest) rest) 1 2 3)", "(2 3)");
+    test_program("(begin (define (f a . rest) (cons a rest)) (f 1))", "(1)");
+    test_error("((lambda (a b . rest) a) 1)")
 }
 
 #[test]
@@ -158,6 +239,166 @@ fn test_recursion_simple() {
     test_program("(begin (define (fact x) (if (< x 2) x (* x (fact (- x 1))))) (fact 5))", "120")
 }
 
+#[test]
+fn test_lambda() {
+    test_program("((lambda (x y) (+ x y)) 1 2)", "3");
+    test_program("(begin (define (adder n) (lambda (x) (+ x n))) ((adder 3) 4))", "7")
+}
+
+#[test]
+fn test_guard() {
+    test_program("(guard (e (#t 42)) (raise 1))", "42");
+    test_program("(guard (e ((= e 1) (+ e 10))) (raise 1))", "11");
+    test_program("(guard (e ((= e 2) 0) (else (* e 3))) (+ 1 (raise 5)))", "15");
+    test_program("(guard (e ((= e 2) => (lambda (b) b))) (raise 2))", "#t");
+    test_program("(guard (e (#t 0)) (+ 1 2))", "3");
+    test_program("(guard (e ((error-object? e) (error-object-message e))) (car 1))", "\"CAR expects a pair\"");
+    test_program("(guard (outer (#t (+ outer 100))) (guard (inner ((= inner 2) 0)) (raise 1)))", "101");
+    test_error_msg("(guard (e ((= e 2) 0)) (raise 1))", "Uncaught raise 1")
+}
+
+#[test]
+fn test_error_object() {
+    test_program("(guard (e ((error-object? e) (error-object-message e))) (error \"bad thing\" 1 2))", "\"bad thing\"");
+    test_program("(guard (e ((error-object? e) (error-object-irritants e))) (error \"bad thing\" 1 2))", "(1 2)");
+    test_program("(guard (e (#t (error-object? e))) (raise 1))", "#f");
+    test_error_msg("(error \"bad thing\" 1 \"two\")", "bad thing 1 \"two\"")
+}
+
+#[test]
+fn test_with_exception_handler() {
+    test_program("(with-exception-handler (lambda (e) 10) (lambda () (+ 1 (raise-continuable 5))))", "11");
+    test_program("(with-exception-handler (lambda (e) (* e 2)) (lambda () (raise-continuable 21)))", "42");
+    test_program("(guard (e (#t (error-object-message e))) (with-exception-handler (lambda (e) 0) (lambda () (raise 1))))",
+                 "\"Exception handler returned from non-continuable raise\"");
+    test_program("(guard (e (#t e)) (with-exception-handler (lambda (e) (raise (+ e 1))) (lambda () (raise 1))))", "2");
+    test_program("(guard (e (#t e)) (with-exception-handler (lambda (e) (raise 7)) (lambda () (car 1))))", "7");
+    test_program("(with-exception-handler (lambda (e) 0) (lambda () (guard (e (#t (+ e 1))) (raise-continuable 1))))", "2")
+}
+
+#[test]
+fn test_error_details() {
+    let err = run_program("(begin (define (f x) (car x)) (f 1))").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Type);
+    assert_eq!(err.trace, vec!["f".to_string()]);
+    assert_eq!(err.span, Some(Span { line: 1, column: 1 }));
+
+    let err = run_program("(+ 1\n  (car 1)").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Read);
+    assert_eq!(err.span, Some(Span { line: 1, column: 1 }));
+
+    let err = run_program("(raise 3)").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Raise);
+    assert_eq!(err.condition().as_int(), Some(3))
+}
+
+#[test]
+fn test_persistent_env() {
+    let env = Environment::standard_env();
+    eval_program("(define (sq x)\n  (* x x))", env.clone()).unwrap();
+    assert!(eval_program("(car 1)", env.clone()).is_err());
+    let result = eval_program("(define y 3) (sq y)", env.clone()).unwrap();
+    assert_eq!(format!("{}", result), "9");
+    assert!(env.borrow().symbols().contains(&"sq".to_string()))
+}
+
+#[test]
+fn test_is_complete() {
+    assert!(is_complete("(+ 1 2)"));
+    assert!(is_complete("1 2"));
+    assert!(!is_complete("(define (f x)\n"));
+    assert!(!is_complete("(display \"a)"));
+    assert!(is_complete("(display \")\")"))
+}
+
+#[test]
+fn test_exit() {
+    assert_eq!(run_program("(exit 3)").unwrap_err().kind, ErrorKind::Exit(3));
+    assert_eq!(run_program("(exit)").unwrap_err().kind, ErrorKind::Exit(0));
+    assert_eq!(run_program("(exit #f)").unwrap_err().kind, ErrorKind::Exit(1));
+    assert_eq!(run_program("(guard (e (#t 0)) (exit 4))").unwrap_err().kind, ErrorKind::Exit(4));
+    assert_eq!(run_program("(with-exception-handler (lambda (e) 0) (lambda () (exit 5)))").unwrap_err().kind, ErrorKind::Exit(5))
+}
+
+#[test]
+fn test_command_line() {
+    set_command_line(vec!["script.scm".to_string(), "a".to_string()]);
+    test_program("(command-line)", "(\"script.scm\" \"a\")")
+}
+
+fn test_file(name: &str) -> String {
+    format!("{}/tests/files/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn test_load() {
+    let env = Environment::standard_env();
+    load_file(&test_file("main.scm"), env.clone(), false).unwrap();
+    assert_eq!(format!("{}", eval_program("(add-one total)", env.clone()).unwrap()), "7");
+
+    let program = format!("(begin (load \"{}\") (twice total))", test_file("main.scm"));
+    test_program(&program, "12");
+    test_program(&format!("(begin (include-ci \"{}\") (add-one 1))", test_file("lib/upper.scm")), "2");
+    test_error(&format!("(include \"{}\")", test_file("lib/upper.scm")));
+    // Loading into another environment leaves the caller's bindings alone
+    let program = format!("(define env (environment '(scheme base))) (load \"{}\" env) (list (eval 'total env) (eval '(twice 2) env))", test_file("main.scm"));
+    test_program(&program, "(6 4)");
+    test_error(&format!("(define env (environment '(scheme base))) (load \"{}\" env) total", test_file("main.scm")));
+    test_program(&format!("(load \"{}\" (interaction-environment)) total", test_file("main.scm")), "6");
+    test_error(&format!("(load \"{}\" 'not-an-environment)", test_file("main.scm")))
+}
+
+#[test]
+fn test_load_errors() {
+    let err = load_file(&test_file("cycle.scm"), Environment::standard_env(), false).unwrap_err();
+    assert_eq!(err.message, "Include cycle detected");
+    assert!(err.file.unwrap().ends_with("cycle.scm"));
+    assert_eq!(err.span, Some(Span { line: 1, column: 1 }));
+
+    let err = run_program("(load \"does-not-exist.scm\")").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::File);
+    test_error("(include 1)")
+}
+
+#[test]
+fn test_define_library() {
+    let env = Environment::standard_env();
+    let program = "(define-library (my lib)
+                     (export add1 (rename internal-double double))
+                     (import (scheme base))
+                     (begin
+                       (define (add1 x) (+ x 1))
+                       (define (internal-double x) (* 2 x))))
+                   (import (my lib))
+                   (double (add1 2))";
+    assert_eq!(format!("{}", eval_program(program, env.clone()).unwrap()), "6");
+    assert!(eval_program("(internal-double 1)", env.clone()).is_err());
+    assert!(eval_program("(define-library (bad lib) (export missing))", env.clone()).is_err())
+}
+
+#[test]
+fn test_import_sets() {
+    let env = Environment::new();
+    let env = std::rc::Rc::new(std::cell::RefCell::new(env));
+    eval_program("(import (only (scheme base) + car) (prefix (except (scheme base) car) b:))", env.clone()).unwrap();
+    assert_eq!(format!("{}", eval_program("(b:+ (car (b:list 1 2)) 2)", env.clone()).unwrap()), "3");
+    assert!(eval_program("(b:car (b:list 1 2))", env.clone()).is_err());
+    assert!(eval_program("(list 1 2)", env.clone()).is_err());
+
+    eval_program("(import (rename (scheme base) (list make-list) (cdr rest)))", env.clone()).unwrap();
+    assert_eq!(format!("{}", eval_program("(rest (make-list 1 2))", env.clone()).unwrap()), "(2)");
+
+    test_error("(import (only (scheme base) no-such-procedure))");
+    test_error("(import (no such library))")
+}
+
+#[test]
+fn test_library_search_path() {
+    add_library_path(std::path::PathBuf::from(test_file("libs")));
+    test_program("(begin (import (util math)) (+ (square 3) (cube 2)))", "17");
+    test_error("(begin (import (util math)) (hidden))")
+}
+
 #[test]
 fn test_misc() {
     test_program("( list (+ 34 6) ( * 2 1 )  )", "(40 2)")
@@ -168,4 +409,512 @@ fn test_misc() {
 #[test]
 fn test_mismatch_paren() {
     test_error_msg("(begin (define (fact x) (* x (fact (- x 1))) (fact 5))", "Missing right paren")
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_error_values_printed() {
+    for &engine in &[Engine::Tree, Engine::Vm] {
+        set_engine(engine);
+        test_error_msg("(5 1)", "Expected function, found 5");
+        test_error_msg("('(a \"b\" #\\c) 1)", "Expected function, found (a \"b\" #\\c)");
+        test_error_msg("undefined-variable", "Invalid definition undefined-variable")
+    }
+}
+
+#[test]
+fn test_gc_cycles() {
+    // Each program starts with (gc) to free cycles left over from earlier ones on this thread.
+    // A closure stored in the environment it closes over
+    test_program("(gc) (define (make) (define (self) self) self) (make) 0 (gc)", "2");
+    // A circular list, which the environment of `f` no longer references once it returns
+    test_program("(gc) (define (f) (define x (list 1 2)) (set-cdr! (cdr x) x) 0) (f) (gc)", "2");
+    // Reachable cycles are left alone
+    test_program("(define x (list 1 2)) (set-cdr! (cdr x) x) (gc) (car (cdr (cdr x)))", "1");
+    test_program("(define (f) 1) (gc) (f)", "1");
+    // An error object shared by two containers, and a cycle through an error's irritants
+    test_program("(define e (guard (x (#t x)) (error \"m\" (list 1)))) (define v (vector e e)) (gc) (error-object-irritants (vector-ref v 1))", "((1))");
+    test_program("(gc) (define (f) (define p (list 1)) (set-car! p (guard (x (#t x)) (error \"m\" p))) 0) (f) (gc)", "2");
+    // A procedure whose code remembers looking itself up, in an environment dropped after `eval`,
+    // is freed by the first collection
+    test_program("(gc) (eval '(begin (define (f n) (if (> n 0) (f (- n 1)) 0)) (f 2)) (environment '(scheme base))) (gc) (gc)", "0")
+}
+
+#[test]
+fn test_gc_bounded() {
+    let env = Environment::standard_env();
+    eval_program("(define (make-cycle) (define x (list 1 2)) (set-cdr! (cdr x) x) 0)", env.clone()).unwrap();
+    for _ in 0..20_000 {
+        eval_program("(make-cycle)", env.clone()).unwrap();
+    }
+    let stats = gc::stats();
+    assert!(stats.collections > 0);
+    assert!(stats.live < 25_000);
+    gc::collect();
+    assert!(gc::stats().live < 100)
+}
+
+#[test]
+fn test_symbols() {
+    test_program("(symbol? 'a)", "#t");
+    test_program("(symbol? \"a\")", "#f");
+    test_program("(symbol->string 'abc)", "\"abc\"");
+    test_program("(eq? (string->symbol \"abc\") 'abc)", "#t");
+    test_program("(symbol=? 'a 'a 'a)", "#t");
+    test_program("(symbol=? 'a 'a 'b)", "#f");
+    test_error("(symbol=? 'a \"a\")");
+    test_error("(symbol->string \"a\")")
+}
+
+#[test]
+fn test_gensym() {
+    test_program("(define g (gensym)) (eq? g g)", "#t");
+    test_program("(eq? (gensym) (gensym))", "#f");
+    test_program("(symbol? (generate-uninterned-symbol))", "#t");
+    // An uninterned symbol is distinct from the interned symbol with the same name
+    test_program("(define g (gensym 'tmp)) (eq? g (string->symbol (symbol->string g)))", "#f");
+    test_program("(define g (gensym \"x\")) (symbol=? g g)", "#t")
+}
+
+#[test]
+fn test_lexical_scope() {
+    // Closures see the frames they were created in, however deeply nested
+    test_program("(define (adder a) (lambda (b) (lambda (c) (+ a (+ b c))))) (((adder 1) 2) 3)", "6");
+    // Parameters shadow globals and outer parameters
+    test_program("(define x 10) (define (f x) ((lambda (x) x) (+ x 1))) (f 1)", "2");
+    test_program("(define x 10) (define (f) x) (define (g x) (f)) (g 1)", "10");
+    // Internal definitions can refer to each other in either order
+    test_program("(define (f n)
+                    (define (even? n) (if (= n 0) #t (odd? (- n 1))))
+                    (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+                    (even? n))
+                  (f 10)", "#t");
+    test_program("(define (f) (begin (define a 1) (define b 2)) (+ a b)) (f)", "3");
+    // Each call gets its own frame
+    test_program("(define (make n) (lambda () n)) (define a (make 1)) (define b (make 2)) (+ (a) (b))", "3");
+    // Globals defined after a procedure are still visible to it
+    test_program("(define (f) (g)) (define (g) 5) (f)", "5");
+    test_program("(define (f x) (guard (e (#t (+ x e))) (raise 2))) (f 1)", "3");
+    test_error("(define (f) y) (f)")
+}
+
+#[test]
+fn test_engines() {
+    let programs = [
+        ("(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))) (fib 15)", "610"),
+        ("(define (f x) (if (> x 0) 'positive)) (list (f 1) (eq? (f 0) (f 0)))", "(positive #t)"),
+        // Every value but #f counts as true
+        ("(list (if (memv 2 '(1 2)) 'y 'n) (if (memv 3 '(1 2)) 'y 'n) (if 0 'y 'n) (if '() 'y))", "(y n y y)"),
+        ("(define (sum . xs) (apply + xs)) (sum 1 2 3)", "6"),
+        ("(define (f x) (guard (e ((symbol? e) (list x e))) (raise 'oops))) (f 1)", "(1 oops)"),
+        ("(guard (e (#t (error-object-message e))) (error \"bad\" 1))", "\"bad\""),
+        ("(define (f a . rest) (list a rest)) (list (f 1) (f 1 2 3))", "((1 ()) (1 (2 3)))"),
+        ("(define (adder n) (lambda (x) (+ x n))) ((adder 1) 2)", "3"),
+        ("(define (g a b c) (list a b c)) (define (f x) (g x (+ x 1) (+ x 2))) (f 1)", "(1 2 3)"),
+        // Redefining a procedure is seen by code that has already called it
+        ("(define (g) 1) (define (f) (g)) (define a (f)) (define (g) 2) (list a (f))", "(1 2)"),
+    ];
+    for &engine in &[Engine::Tree, Engine::Vm] {
+        set_engine(engine);
+        for &(program, expected) in &programs {
+            test_program(program, expected);
+        }
+        let err = run_program("(define (f x) (car x)) (define (g x) (+ 1 (f x))) (g 1)").unwrap_err();
+        assert_eq!(err.trace, vec!["f".to_string(), "g".to_string()]);
+        test_error_msg("(define (f x) x) (f)", "f requires 1 arguments");
+        test_error_msg("(define (f x . xs) x) (f)", "f requires at least 1 arguments");
+        // Results that don't fit in an integer are errors, whether or not the VM works them out itself
+        test_error_msg("(+ 2147483647 1)", "Integer overflow 2147483647 1");
+        test_error_msg("(* 65536 65536)", "Integer overflow 65536 65536");
+        test_error_msg("(- -2147483648 1)", "Integer overflow -2147483648 1");
+        test_error_msg("(+ 1 2 2147483647)", "Integer overflow 1 2 2147483647");
+        test_error_msg("(abs -2147483648)", "Integer overflow -2147483648");
+        test_error_msg("(define (f x) (* x x)) (f 65536)", "Integer overflow 65536 65536");
+    }
+}
+
+#[test]
+fn test_vm_deep_recursion() {
+    // The VM keeps lambda frames on the heap, so recursion isn't limited by the Rust stack
+    set_engine(Engine::Vm);
+    test_program("(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1))))) (count 100000)", "100000");
+    test_program("(define (loop n) (if (= n 0) 'done (loop (- n 1)))) (loop 100000)", "done");
+}
+
+#[test]
+fn test_call_cc() {
+    for &engine in &[Engine::Tree, Engine::Vm] {
+        set_engine(engine);
+        test_program("(+ 1 (call/cc (lambda (k) (+ 10 (k 1)))))", "2");
+        test_program("(call-with-current-continuation (lambda (k) 3))", "3");
+        test_program("(call/cc (lambda (k) (apply k '(7))))", "7");
+        // Escapes pass through handlers and guards without being caught
+        test_program("(call/cc (lambda (k) (with-exception-handler (lambda (e) (k 42)) (lambda () (raise 'boom)))))", "42");
+        test_program("(call/cc (lambda (k) (guard (e (#t 0)) (k 5))))", "5");
+        test_program("(call-with-values (lambda () (call/cc (lambda (k) (k 1 2)))) list)", "(1 2)");
+    }
+}
+
+#[test]
+fn test_call_cc_reentry() {
+    set_engine(Engine::Vm);
+    // Resuming after call/cc has returned runs the rest of the procedure again
+    test_program("(define (f)
+                    (define box (list #f))
+                    (define n (list 0))
+                    (define v (call/cc (lambda (k) (set-car! box k) 0)))
+                    (set-car! n (+ (car n) 1))
+                    (if (< v 3) ((car box) (+ v 1)) (list v (car n))))
+                  (f)", "(3 4)");
+    // A continuation captured by an earlier top-level form returns from that form again
+    test_program("(define box (list #f)) (define r (+ 100 (call/cc (lambda (k) (set-car! box k) 1)))) ((car box) 5) r", "105");
+    test_program("(gc) (define (make) (define box (list #f)) (call/cc (lambda (k) (set-car! box k))) 0) (make) 0 (gc)", "3");
+    // Through map and for-each, which don't change the lists they've already returned
+    test_program("(define box (list #f))
+                  (define r (map (lambda (x) (call/cc (lambda (k) (if (= x 2) (set-car! box k)) x))) '(1 2 3)))
+                  (define first r)
+                  (if (= (car (cdr r)) 2) ((car box) 10))
+                  (list first r)", "((1 2 3) (1 10 3))");
+    test_program("(define box (list #f)) (define sum (list 0))
+                  (for-each (lambda (x) (call/cc (lambda (k) (if (= x 2) (set-car! box k)))) (set-car! sum (+ (car sum) x))) '(1 2 3))
+                  (if (< (car sum) 10) ((car box) 0))
+                  (car sum)", "11");
+    // Into a guard body or handler thunk, which catches errors again once resumed
+    test_program("(define box (list #f))
+                  (define r (guard (e (#t (list 'caught e)))
+                              ((lambda (v) (if (= v 2) (raise 'boom) v)) (call/cc (lambda (k) (set-car! box k) 1)))))
+                  (if (equal? r 1) ((car box) 2))
+                  r", "(caught boom)");
+    test_program("(define box (list #f))
+                  (define r (with-exception-handler (lambda (e) 40)
+                              (lambda () (+ (call/cc (lambda (k) (set-car! box k) 1)) (raise-continuable 'oops)))))
+                  (if (= r 41) ((car box) 2))
+                  r", "42");
+    // Builtins that call procedures from Rust, like vector-map, only let them escape
+    test_error("(define box (list #f)) (vector-map (lambda (x) (call/cc (lambda (k) (set-car! box k) x))) #(1)) ((car box) 2)");
+
+    set_engine(Engine::Tree);
+    // The tree walker's continuations only escape
+    test_error("(define box (list #f)) (call/cc (lambda (k) (set-car! box k))) ((car box) 1)");
+}
+
+#[test]
+fn test_dynamic_wind() {
+    let prelude = "(define log (list '())) (define (note x) (set-car! log (cons x (car log)))) ";
+    for &engine in &[Engine::Tree, Engine::Vm] {
+        set_engine(engine);
+        test_program(&format!("{}(list (dynamic-wind (lambda () (note 'before)) (lambda () (note 'during) 2) (lambda () (note 'after))) (car log))", prelude),
+                     "(2 (after during before))");
+        test_program(&format!("{}(call/cc (lambda (k) (dynamic-wind (lambda () (note 'in)) (lambda () (k 1) (note 'never)) (lambda () (note 'out))))) (car log)", prelude),
+                     "(out in)");
+        test_program(&format!("{}(guard (e (#t (cons e (car log)))) (dynamic-wind (lambda () (note 'in)) (lambda () (raise 'x)) (lambda () (note 'out))))", prelude),
+                     "(x out in)");
+    }
+    // Re-entering the extent runs `before` again
+    set_engine(Engine::Vm);
+    test_program(&format!("{}(define (g)
+                             (define box (list #f))
+                             (define v (dynamic-wind (lambda () (note 'in))
+                                                     (lambda () (call/cc (lambda (k) (set-car! box k) 0)))
+                                                     (lambda () (note 'out))))
+                             (if (< v 2) ((car box) (+ v 1)) v))
+                           (list (g) (car log))", prelude), "(2 (out in out in out in))");
+}
+
+#[test]
+fn test_values() {
+    for &engine in &[Engine::Tree, Engine::Vm] {
+        set_engine(engine);
+        test_program("(call-with-values (lambda () (values 1 2)) list)", "(1 2)");
+        test_program("(call-with-values (lambda () (values)) list)", "()");
+        test_program("(call-with-values (lambda () 5) list)", "(5)");
+        test_program("(values 1 2)", "1 2");
+        test_program("(receive (q r) (floor/ -7 2) (list q r))", "(-4 1)");
+        test_program("(receive (q r) (truncate/ -7 2) (list q r))", "(-3 -1)");
+        test_program("(list (floor-quotient 7 -2) (floor-remainder 7 -2))", "(-4 -1)");
+        test_program("(list (truncate-quotient 7 -2) (truncate-remainder 7 -2))", "(-3 1)");
+        // The quotient of the most negative integer by -1 doesn't fit in an integer
+        test_error("(floor/ -2147483648 -1)");
+        test_error("(truncate/ -2147483648 -1)");
+        test_error("(floor-quotient -2147483648 -1)");
+        test_error("(floor-remainder -2147483648 -1)");
+        test_error("(truncate-quotient -2147483648 -1)");
+        test_error("(truncate-remainder -2147483648 -1)");
+        test_error("(/ -2147483648 -1)");
+        test_program("(receive all (exact-integer-sqrt 17) all)", "(4 1)");
+        test_program("(let-values (((a b) (values 1 2)) ((c . d) (values 3 4 5))) (list a b c d))", "(1 2 3 (4 5))");
+        // let-values evaluates every expression outside the new bindings, let*-values doesn't
+        test_program("(define x 10) (let-values (((x) (values 1)) ((y) (values x))) (list x y))", "(1 10)");
+        test_program("(define x 10) (let*-values (((x) (values 1)) ((y) (values x))) (list x y))", "(1 1)");
+        test_program("(define-values (p q . r) (values 1 2 3 4)) (list p q r)", "(1 2 (3 4))");
+        test_program("(define (f) (define-values (a b) (values 1 2)) (define c 3) (+ a b c)) (f)", "6");
+        test_error("(define-values (a b) (values 1))");
+        test_error("(receive (a b) (values 1 2 3) a)");
+    }
+}
+
+#[test]
+fn test_vectors() {
+    test_program("'#(1 #(2) \"a\")", "#(1 #(2) \"a\")");
+    test_program("(define v (make-vector 3 0)) (vector-set! v 1 'x) v", "#(0 x 0)");
+    test_program("(vector-ref (vector 1 2 3) 2)", "3");
+    test_program("(vector-length #())", "0");
+    test_program("(define v (vector 1 2 3 4)) (vector-fill! v 0 1 3) v", "#(1 0 0 4)");
+    test_program("(vector->list #(1 2 3 4) 1 3)", "(2 3)");
+    test_program("(list->vector '(1 2))", "#(1 2)");
+    test_program("(vector-map + #(1 2 3) #(10 20))", "#(11 22)");
+    test_program("(list (equal? #(1 (2)) (vector 1 (list 2))) (eqv? #() #()))", "(#t #f)");
+    test_error("(vector-ref (vector 1 2) 2)");
+    test_error("(vector->list #(1 2) 2 1)");
+    test_program("'#u8(1 255)", "#u8(1 255)");
+    test_program("(bytevector-u8-ref (bytevector 7 8) 1)", "8");
+    test_program("(bytevector-copy #u8(1 2 3) 1)", "#u8(2 3)");
+    test_program("(utf8->string (string->utf8 \"h\u{e9}llo\") 0 3)", "\"h\u{e9}\"");
+    test_error("(bytevector 256)");
+    test_error("(utf8->string #u8(255))");
+    test_error("'#u8(1 x)");
+    // A vector holding itself is a cycle for the collector
+    test_program("(gc) (define (f) (define v (make-vector 1 0)) (vector-set! v 0 v) 0) (f) 0 (gc)", "1");
+}
+
+#[test]
+fn test_hash_tables() {
+    test_program("(define t (make-hash-table)) (hash-table-set! t '(1 2) 'a) (hash-table-ref t (list 1 2))", "a");
+    test_program("(define t (make-hash-table eq?)) (hash-table-set! t (list 1) 'a) (hash-table-ref/default t (list 1) 'none)", "none");
+    test_program("(define t (make-hash-table)) (hash-table-set! t 'b 2) (hash-table-set! t 'a 1) (hash-table-set! t 'b 3) (hash-table->alist t)", "((b . 3) (a . 1))");
+    test_program("(define t (make-hash-table)) (hash-table-set! t 1 1) (hash-table-set! t 2 2) (hash-table-delete! t 1) (list (hash-table-keys t) (hash-table-size t) (hash-table-exists? t 1))", "((2) 1 #f)");
+    test_program("(hash-table-ref (make-hash-table) 'x (lambda () 'missing))", "missing");
+    test_program("(define t (make-hash-table)) (hash-table-set! t 'x 1) (hash-table-ref t 'x (lambda () 0) (lambda (v) (+ v 10)))", "11");
+    test_program("(define t (make-hash-table)) (define (count w) (hash-table-update!/default t w (lambda (n) (+ n 1)) 0)) (count 'a) (count 'b) (count 'a) (hash-table->alist t)", "((a . 2) (b . 1))");
+    test_program("(define t (make-hash-table)) (define sums (make-hash-table)) (hash-table-set! t 1 10) (hash-table-set! t 2 20) (hash-table-walk t (lambda (k v) (hash-table-update!/default sums 'sum (lambda (sum) (+ sum k v)) 0))) (hash-table-ref sums 'sum)", "33");
+    // Keys compared modulo 10, with a hash consistent with that
+    test_program("(define (mod10 n) (receive (q r) (floor/ n 10) r)) (define t (make-hash-table (lambda (a b) (= (mod10 a) (mod10 b))) mod10)) (hash-table-set! t 3 'a) (hash-table-set! t 13 'b) (hash-table->alist t)", "((3 . b))");
+    test_program("(list (= (hash '(1 #(2))) (hash (list 1 (vector 2)))) (< (hash 'x 5) 5) (= (string-hash \"ab\") (string-hash \"ab\")))", "(#t #t #t)");
+    test_error("(hash-table-ref (make-hash-table) 'x)");
+    test_error("(make-hash-table (lambda (a b) #t))");
+    test_program("(import (srfi 69)) (hash-table? (make-hash-table))", "#t");
+    // Deleting most entries compacts the table without losing the rest
+    test_program("(define t (make-hash-table)) (define (fill n) (if (> n 0) (begin (hash-table-set! t n n) (fill (- n 1))))) (fill 20) (define (drain n) (if (> n 2) (begin (hash-table-delete! t n) (drain (- n 1))))) (drain 20) (list (hash-table->alist t) (hash-table-ref t 1))", "(((2 . 2) (1 . 1)) 1)");
+    // A table holding itself is a cycle for the collector
+    test_program("(gc) (define (f) (define t (make-hash-table)) (hash-table-set! t 'self t) 0) (f) 0 (gc)", "1");
+}
+
+#[test]
+fn test_list_procedures() {
+    test_program("(list (null? '()) (null? '(1)) (pair? '(1 . 2)) (pair? '()))", "(#t #f #t #f)");
+    test_program("(length '(1 2 3))", "3");
+    test_error("(length '(1 2 . 3))");
+    test_error("(define x (list 1 2)) (set-cdr! (cdr x) x) (length x)");
+    test_program("(reverse '(1 (2 3) 4))", "(4 (2 3) 1)");
+    test_program("(last-pair '(1 2 . 3))", "(2 . 3)");
+    test_program("(define x (list 1 2)) (define y (list-copy x)) (set-car! y 9) (list x y (list-copy '(1 . 2)) (list-copy 5))", "((1 2) (9 2) (1 . 2) 5)");
+    test_program("(list (list-tail '(1 2 3) 2) (list-ref '(a b c) 1))", "((3) b)");
+    test_error("(list-ref '(a b) 2)");
+    test_program("(list (memq 'c '(a b c d)) (memv 5 '(1 2)) (member (list 2) '(1 (2) 3)))", "((c d) #f ((2) 3))");
+    test_program("(member 'two '(1 2 3) (lambda (x y) (= y 2)))", "(2 3)");
+    test_program("(list (assq 'b '((a 1) (b 2))) (assv 3 '((1 . a))) (assoc '(k) '(((k) . v))))", "((b 2) #f ((k) . v))");
+    test_program("(assoc 5 '((1 . a) (4 . b)) (lambda (x key) (< key x)))", "(1 . a)");
+    test_error("(define x (list 1 2)) (set-cdr! (cdr x) x) (memq 3 x)");
+    test_error("(assq 'a '(1 2))");
+}
+
+#[test]
+fn test_srfi_1() {
+    test_program("(list (map + '(1 2 3) '(10 20)) (map car '((a) (b))))", "((11 22) (a b))");
+    test_program("(define t (make-hash-table)) (for-each (lambda (k v) (hash-table-set! t k v)) '(a b) '(1 2)) (hash-table->alist t)", "((a . 1) (b . 2))");
+    test_program("(list (filter (lambda (x) (> x 1)) '(1 2 3)) (remove (lambda (x) (> x 1)) '(1 2 3)))", "((2 3) (1))");
+    test_program("(partition (lambda (x) (> x 1)) '(1 2 3))", "(2 3) (1)");
+    test_program("(list (fold cons '() '(1 2 3)) (fold-right cons '() '(1 2 3)) (fold (lambda (a b acc) (+ acc (* a b))) 0 '(1 2) '(3 4)))", "((3 2 1) (1 2 3) 11)");
+    test_program("(list (reduce + 0 '(1 2 3)) (reduce + 0 '()) (reduce list 0 '(1 2 3)))", "(6 0 (3 (2 1)))");
+    test_program("(list (append-map (lambda (x) (list x x)) '(1 2)) (filter-map (lambda (x) (if (> x 1) (* x 10) #f)) '(1 2 3)))", "((1 1 2 2) (20 30))");
+    test_program("(list (find (lambda (x) (> x 1)) '(1 2 3)) (find-tail (lambda (x) (> x 1)) '(1 2 3)) (find null? '(1)))", "(2 (2 3) #f)");
+    test_program("(list (any (lambda (x) (if (> x 1) (* x 10) #f)) '(1 2 3)) (every (lambda (x) (* x 10)) '(1 2)) (every car '()) (any < '(3 1) '(2 2)))", "(20 20 #t #t)");
+    test_program("(list (list-index (lambda (x) (> x 1)) '(1 2 3)) (list-index null? '(1)))", "(1 #f)");
+    test_program("(list (iota 3) (iota 3 1) (iota 3 0 -2))", "((0 1 2) (1 2 3) (0 -2 -4))");
+    test_program("(list (iota 2 2147483646) (iota 2 -2147483648 2147483647))", "((2147483646 2147483647) (-2147483648 -1))");
+    test_error_msg("(iota 3 2147483647)", "Integer overflow 3 2147483647");
+    test_error_msg("(iota 3 0 2147483647)", "Integer overflow 3 0 2147483647");
+    test_program("(list (delete '(1) '((1) 2 (1))) (delete 2 '(1 2 3) (lambda (x y) (< x y))))", "((2) (1 2))");
+    test_program("(delete-duplicates '(a b a (c) (c) b))", "(a b (c))");
+    test_error("(map car '(1 . 2))");
+    test_error("(fold + 0 '(1 2) 3)");
+    test_error("(filter (lambda (x) (car x)) '(1))");
+}
+
+#[test]
+fn test_sort() {
+    test_program("(sort '(3 1 2) <)", "(1 2 3)");
+    test_program("(sort #(3 1 2) >)", "#(3 2 1)");
+    // Equal elements keep their order
+    test_program("(sort '((1 a) (0 b) (1 c) (0 d)) (lambda (x y) (< (car x) (car y))))", "((0 b) (0 d) (1 a) (1 c))");
+    test_program("(define l (list 2 1 3)) (sort! l <) l", "(1 2 3)");
+    test_program("(define v (vector 2 1 3)) (sort! v <) v", "#(1 2 3)");
+    test_program("(list (list-sort < '(5 4 3 2 1 0 9 8 7 6)) (vector-sort < #()))", "((0 1 2 3 4 5 6 7 8 9) #())");
+    test_program("(merge '((1 a) (2 a)) '((1 b) (3 b)) (lambda (x y) (< (car x) (car y))))", "((1 a) (1 b) (2 a) (3 b))");
+    // An inconsistent comparison still gives back every element
+    test_program("(length (sort (iota 50) (lambda (a b) #t)))", "50");
+    test_error_msg("(sort '(1 a) <)", "Not an int");
+    test_error("(sort '(2 1) (lambda (a b) (raise 'oops)))");
+}
+
+#[test]
+fn test_chars() {
+    test_program("(list #\\a #\\space #\\x41 #\\( (char->integer #\\A) (integer->char 955))", "(#\\a #\\space #\\A #\\( 65 #\\\u{3bb})");
+    test_program("(list (char? #\\a) (char? \"a\") (eqv? #\\a #\\a))", "(#t #f #t)");
+    test_error("#\\nonsense");
+}
+
+#[test]
+fn test_string_ports() {
+    test_program("(with-output-to-string (lambda () (display \"a\") (write \"b\") (write-char #\\c) (newline) (display '(1 \"d\"))))", "\"a\\\"b\\\"c\\n(1 d)\"");
+    test_program("(define p (open-output-string)) (write-string \"hello\" p 1 3) (display 5 p) (get-output-string p)", "\"el5\"");
+    test_program("(call-with-output-string (lambda (p) (write 'x p)))", "\"x\"");
+    test_program("(define p (open-input-string \"(a b) 42\\nnext line\\n\")) (list (read p) (read p) (read-char p) (peek-char p) (read-line p) (read-line p))", "((a b) 42 #\\newline #\\n \"next line\" #<eof>)");
+    test_program("(define p (open-input-string \"x\")) (list (char-ready? p) (read p) (read p) (eof-object? (read-char p)))", "(#t x #<eof> #t)");
+    test_program("(define p (open-input-string \"\")) (close-port p) (list (input-port? p) (input-port-open? p) (output-port? p))", "(#t #f #f)");
+    test_error("(define p (open-input-string \"x\")) (close-input-port p) (read-char p)");
+    test_error("(read-char (open-output-string))");
+    // The current output port is restored when the thunk fails
+    test_program("(guard (e (#t (output-port? (current-output-port)))) (with-output-to-string (lambda () (raise 'oops))))", "#t");
+}
+
+#[test]
+fn test_printer() {
+    test_program("(list #t #f '() '(a b . c) \"q\\\"uote\" #\\space)", "(#t #f () (a b . c) \"q\\\"uote\" #\\space)");
+    test_program("(with-output-to-string (lambda () (write '(\"a\" #\\b)) (display '(\"a\" #\\b #(\"c\")))))", "\"(\\\"a\\\" #\\\\b)(a b #(c))\"");
+    // Symbols that aren't identifiers are written between bars, and control characters as hex
+    test_program("(list (string->symbol \"a b\") (string->symbol \"\") (string->symbol \"12\") (string->symbol \"x|y\") 'a.b '...)", "(|a b| || |12| |x\\|y| a.b ...)");
+    test_program("(with-output-to-string (lambda () (display (list (string->symbol \"a b\") (string->symbol \"\")))))", "\"(a b )\"");
+    test_program("(list \"bell\\a\\x1b;\" \"\\x0;\")", "(\"bell\\x7;\\x1b;\" \"\\x0;\")");
+    test_program("(define (fact n) n) (list fact car (lambda (x) x))", "(#<procedure fact> #<procedure car> #<procedure>)");
+    test_program("(define x (list 1 2 3)) (set-cdr! (cdr (cdr x)) x) x", "#0=(1 2 3 . #0#)");
+    test_program("(define p (list 1)) (set-car! p p) p", "#0=(#0#)");
+    test_program("(define v (vector 1 2)) (vector-set! v 1 v) v", "#0=#(1 #0#)");
+    test_program("(define x (list \"a\")) (set-cdr! x x) (with-output-to-string (lambda () (display x)))", "\"#0=(a . #0#)\"");
+    // Shared structure that isn't part of a cycle is only labeled by `write-shared`
+    test_program("(define t (list 2 3)) (with-output-to-string (lambda () (write (cons t (cons 1 t))) (write-shared (cons t (cons 1 t)))))", "\"((2 3) 1 2 3)(#0=(2 3) 1 . #0#)\"");
+}
+
+fn pretty(source: &str, width: usize) -> String {
+    let mut tokens = tokenize(source).unwrap();
+    read_from_tokens(&mut tokens).unwrap().pretty(width)
+}
+
+#[test]
+fn test_pretty() {
+    assert_eq!(pretty("(define (f x) (+ x 1))", 40), "(define (f x) (+ x 1))");
+    assert_eq!(pretty("(define (f x) (cond ((< x 0) 'negative) ((= x 0) 'zero) (else 'positive)))", 40),
+               "(define (f x)\n  (cond ((< x 0) 'negative)\n        ((= x 0) 'zero)\n        (else 'positive)))");
+    assert_eq!(pretty("(let loop ((i 0)) (if (< i 10) (loop (+ i 1)) i))", 30),
+               "(let loop ((i 0))\n  (if (< i 10)\n      (loop (+ i 1))\n      i))");
+    assert_eq!(pretty("(lambda (a b) (display a) (display b))", 20), "(lambda (a b)\n  (display a)\n  (display b))");
+    assert_eq!(pretty("((1 2) (3 4) . 5)", 8), "((1 2)\n (3 4)\n . 5)");
+    assert_eq!(pretty("#(1 2 3 4 5 6 7 8)", 10), "#(1 2 3 4\n  5 6 7 8)");
+    test_program("(with-output-to-string (lambda () (pretty-print '(a \"b\"))))", "\"(a \\\"b\\\")\\n\"");
+    // Cyclic data stays on one line
+    test_program("(define x (iota 40)) (set-cdr! (last-pair x) x) (with-output-to-string (lambda () (pretty-print x)))",
+                 "\"#0=(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32 33 34 35 36 37 38 39 . #0#)\\n\"");
+}
+
+#[test]
+fn test_format_source() {
+    let source = ";;; Header\n#| block\n   comment |#\n(define (f x)   (+ x 1)) ; trailing\n\n\n\n(define y 'a) (display y)\n";
+    assert_eq!(format_source(source).unwrap(),
+               ";;; Header\n#| block\n   comment |#\n(define (f x) (+ x 1)) ; trailing\n\n(define y 'a)\n(display y)\n");
+    let long = "(define (classify x) (cond ((< x 0) 'negative-number) ((= x 0) 'zero) (else 'large-positive-number)))";
+    assert_eq!(format_source(long).unwrap(),
+               "(define (classify x)\n  (cond ((< x 0) 'negative-number)\n        ((= x 0) 'zero)\n        (else 'large-positive-number)))\n");
+    // Comments inside a form stay on their lines while the code around them is laid out
+    let commented = "(define (g y)\n  ; explain\n  (+ y    1)) \n(list 1 ; one\n 2   #| two |# 3)";
+    assert_eq!(format_source(commented).unwrap(),
+               "(define (g y)\n  ; explain\n  (+ y 1))\n(list 1 ; one\n      2 #| two |# 3)\n");
+    let last = "(f a ; last\n)";
+    assert_eq!(format_source(last).unwrap(), "(f a ; last\n )\n");
+    // Literals keep the spelling they were written with
+    let literals = "(list   \"tab\\rhere\\x41;\\\"\"  #\\x41 #\\space #true #f)";
+    assert_eq!(format_source(literals).unwrap(), "(list \"tab\\rhere\\x41;\\\"\" #\\x41 #\\space #true #f)\n");
+    let formatted = format_source(source).unwrap();
+    assert_eq!(format_source(&formatted).unwrap(), formatted);
+    assert_eq!(format_source("").unwrap(), "");
+    assert!(format_source("(define x").is_err());
+    // Datum comments stay attached to the datum they comment out
+    let datum_comments = "(list 1 #;(ignored   datum) 3)\n#;  (define x\n  1)\n";
+    assert_eq!(format_source(datum_comments).unwrap(), "(list 1 #;(ignored datum) 3)\n#;(define x 1)\n");
+    assert!(format_source("(list 1 #;)").is_err());
+    test_program("(list 1 #;(2) 3 #; #;4 5 6)", "(1 3 6)");
+    test_program("'(a #;b . #;c d)", "(a . d)");
+    test_error("(list 1 #;)");
+    test_program("(list 1 #| two #| nested |# |# 3)", "(1 3)");
+    test_program("(list \"a\\x41;\\x3bb;\" \"\\a\\b\" \"one\\r\" \"two\\\n    lines\")", "(\"aAλ\" \"\\x7;\\x8;\" \"one\\r\" \"twolines\")");
+    test_error("\"\\xZZ;\"");
+}
+
+#[test]
+fn test_records() {
+    let point = "(define-record-type <point> (make-point x y) point? (x point-x set-point-x!) (y point-y) (label point-label set-point-label!)) ";
+    test_program(&format!("{}(define p (make-point 1 2)) (set-point-x! p 10) (list p (point-x p) (point-y p) (point? p) (point? 'p))", point), "(#<record point> 10 2 #t #f)");
+    test_program(&format!("{}(define p (make-point 1 2)) (list (vector? p) (pair? p) (hash-table? p) (eqv? p p) (equal? p (make-point 1 2)))", point), "(#f #f #f #t #f)");
+    test_program(&format!("{}(list <point> point-x (point-label (make-point 1 2)))", point), "(#<record-type point> #<procedure point-x> #<unspecified>)");
+    // Internal definitions, and types with the same field names that don't mix
+    test_program("(define (f) (define-record-type node (make-node next) node? (next node-next)) (node-next (make-node 5))) (f)", "5");
+    test_error_msg(&format!("{}(define-record-type node (make-node x) node? (x node-x)) (point-x (make-node 1))", point), "Not a point record");
+    test_error_msg("(define-record-type p (make-p z) p? (x p-x))", "Record constructor takes unknown field z");
+    test_error_msg("(define-record-type p (make-p) p? (x p-x) (x p-y))", "Duplicate record field x");
+    // A record that refers to itself is freed once it's unreachable
+    test_program(&format!("(gc) {}(define (f) (define p (make-point 1 2)) (set-point-label! p p) 0) (f) 0 (gc)", point), "1");
+}
+
+#[test]
+fn test_binary_ports() {
+    test_program("(define p (open-output-bytevector)) (write-u8 1 p) (write-bytevector #u8(2 3 4 5) p 1 3) (get-output-bytevector p)", "#u8(1 3 4)");
+    test_program("(define p (open-input-bytevector #u8(7 8 9))) (list (peek-u8 p) (read-u8 p) (u8-ready? p) (read-bytevector 5 p) (read-bytevector 5 p) (read-u8 p))", "(7 7 #t #u8(8 9) #<eof> #<eof>)");
+    test_program("(list (binary-port? (open-input-bytevector #u8())) (textual-port? (open-input-bytevector #u8())) (textual-port? (open-output-string)))", "(#t #f #t)");
+    test_error_msg("(read-char (open-input-bytevector #u8(65)))", "Not a textual port");
+    test_error_msg("(write-u8 65 (open-output-string))", "Not a binary port");
+}
+
+#[test]
+fn test_file_ports() {
+    let dir = env::temp_dir().join(format!("rust-scheme-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).display().to_string();
+    let text = path("text.scm");
+    let data = path("data.bin");
+
+    test_program(&format!("(with-output-to-file {:?} (lambda () (write '(a \"b\")) (newline) (display \"last\"))) (call-with-input-file {:?} read)", text, text), "(a \"b\")");
+    test_program(&format!("(with-input-from-file {:?} (lambda () (read-line) (read-line)))", text), "\"last\"");
+    test_program(&format!("(call-with-output-file {:?} (lambda (p) (write-string \"more\" p))) (call-with-input-file {:?} read-line)", text, text), "\"more\"");
+    test_program(&format!("(define p (open-binary-output-file {:?})) (write-bytevector #u8(0 255 16) p) (close-port p) (define q (open-binary-input-file {:?})) (list (read-u8 q) (read-bytevector 8 q))", data, data), "(0 #u8(255 16))");
+    test_program(&format!("(directory-files {:?})", dir.display().to_string()), "(\"data.bin\" \"text.scm\")");
+    test_program(&format!("(delete-file {:?}) (list (file-exists? {:?}) (file-exists? {:?}))", data, data, text), "(#f #t)");
+    test_program(&format!("(guard (e ((file-error? e) (error-object-irritants e))) (open-input-file {:?}))", data), &format!("({:?})", data));
+    test_error_msg(&format!("(delete-file {:?})", data), &format!("Unable to delete file {:?}", data));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// A writer the test keeps a handle on, standing in for a host application's own buffer
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_redirect_ports() {
+    let buffer = SharedBuffer::default();
+    let previous_output = port::set_current(Current::Output, Rc::new(Port::writer(Box::new(buffer.clone()), false)));
+    let previous_input = port::set_current(Current::Input, Rc::new(Port::input_string("(+ 1 2)")));
+    let result = run_program("(display \"args: \") (write (cdr (read)))");
+    port::set_current(Current::Output, previous_output);
+    port::set_current(Current::Input, previous_input);
+    assert!(result.is_ok());
+    assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), "args: (1 2)");
+}